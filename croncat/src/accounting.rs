@@ -0,0 +1,81 @@
+//!
+//! Rewards and fees for each chain, so operators can tell whether an agent
+//! is paying for itself. Kept by day in the chain's [`LocalEarningsStorage`].
+//!
+
+use std::collections::HashMap;
+
+use chrono::Utc;
+use cosm_orc::orchestrator::ChainTxResponse;
+use lazy_static::lazy_static;
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use crate::{errors::Report, store::earnings::LocalEarningsStorage};
+
+lazy_static! {
+    /// Earnings ledgers, by chain ID.
+    static ref LEDGERS: Mutex<HashMap<String, LocalEarningsStorage>> = Mutex::new(HashMap::new());
+}
+
+/// The UTC day entries are recorded under.
+fn today() -> String {
+    Utc::now().format("%Y-%m-%d").to_string()
+}
+
+/// Run an update against the chain's ledger, if it's registered.
+async fn update<F>(chain_id: &str, f: F)
+where
+    F: FnOnce(&mut LocalEarningsStorage, &str) -> Result<(), Report>,
+{
+    let mut ledgers = LEDGERS.lock().await;
+    if let Some(ledger) = ledgers.get_mut(chain_id) {
+        if let Err(err) = f(ledger, &today()) {
+            warn!("[{}] Failed to write earnings: {}", chain_id, err);
+        }
+    }
+}
+
+/// Start keeping earnings for a chain, failing if its ledger can't be read.
+pub async fn register(chain_id: &str) -> Result<(), Report> {
+    let mut ledgers = LEDGERS.lock().await;
+    if !ledgers.contains_key(chain_id) {
+        ledgers.insert(chain_id.to_string(), LocalEarningsStorage::new(chain_id)?);
+    }
+
+    Ok(())
+}
+
+/// Record the agent's reward balance in the manager contract, from `AgentResponse`.
+pub async fn record_balance(chain_id: &str, balance: u128) {
+    update(chain_id, |ledger, day| ledger.record_balance(day, balance)).await
+}
+
+/// Record rewards paid out of the manager contract.
+pub async fn record_withdrawal(chain_id: &str, amount: u128) {
+    update(chain_id, |ledger, day| {
+        ledger.record_withdrawal(day, amount)
+    })
+    .await
+}
+
+///
+/// Record the rewards a withdraw or unregister paid out, taken from
+/// the `rewards` attributes of the manager's wasm event.
+///
+pub async fn record_payout(chain_id: &str, res: &ChainTxResponse) {
+    let amount = res
+        .find_event_tags("wasm".to_string(), "rewards".to_string())
+        .iter()
+        .filter_map(|tag| tag.value.parse::<u128>().ok())
+        .sum();
+    record_withdrawal(chain_id, amount).await
+}
+
+/// Record the fee paid for a transaction.
+pub async fn record_fee(chain_id: &str, fee: u128, proxy_call: bool) {
+    update(chain_id, |ledger, day| {
+        ledger.record_fee(day, fee, proxy_call)
+    })
+    .await
+}
@@ -0,0 +1,401 @@
+//!
+//! Alerts for agent events operators should act on, sent to webhooks,
+//! Slack, Discord or a local command, with per-event rate limiting.
+//!
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use lazy_static::lazy_static;
+use reqwest::header::CONTENT_TYPE;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+use url::Url;
+
+use crate::errors::{eyre, Report};
+
+/// How long to wait on a sink before giving up on an alert.
+const SINK_TIMEOUT_SECONDS: u64 = 10;
+
+/// The same event isn't sent again within this window, unless configured.
+const DEFAULT_RATE_LIMIT_SECONDS: u64 = 300;
+
+/// Consecutive failed batches before alerting, unless configured.
+const DEFAULT_BATCH_FAILURE_THRESHOLD: u32 = 3;
+
+lazy_static! {
+    /// Alert config and state, by chain ID.
+    static ref ALERTERS: Mutex<HashMap<String, Alerter>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    #[default]
+    Info,
+    Warning,
+    Critical,
+}
+
+///
+/// Where alerts get sent.
+///
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum AlertSink {
+    /// POST the alert as JSON.
+    Webhook { url: Url },
+    /// POST a Slack incoming webhook message.
+    Slack { url: Url },
+    /// POST a Discord webhook message.
+    Discord { url: Url },
+    /// Run a command with the alert in `CRONCAT_ALERT_*` environment variables.
+    Command {
+        command: String,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlertsConfig {
+    pub sinks: Vec<AlertSink>,
+    /// Alerts below this severity are dropped.
+    #[serde(default)]
+    pub min_severity: Severity,
+    /// Don't repeat the same event within this many seconds.
+    pub rate_limit_seconds: Option<u64>,
+    /// Consecutive failed proxy call batches before alerting.
+    pub batch_failure_threshold: Option<u32>,
+}
+
+///
+/// Agent events worth alerting on.
+///
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AlertEvent {
+    LowBalance {
+        balance: String,
+        denom: String,
+        threshold: u64,
+    },
+    AgentNominated,
+    AgentStopped {
+        reason: String,
+    },
+    AgentPaused {
+        reason: String,
+    },
+    AgentUnregistered {
+        reason: String,
+    },
+    AgentResumed,
+    RegisterFailed {
+        reason: String,
+    },
+    TreasuryTopUp {
+        amount: String,
+        denom: String,
+    },
+    TreasuryCapReached {
+        spent: u64,
+        cap: u64,
+        denom: String,
+    },
+    TaskJailed {
+        task_hash: String,
+    },
+    BatchFailures {
+        kind: String,
+        consecutive: u32,
+    },
+    NoRpcSources,
+}
+
+impl AlertEvent {
+    pub fn severity(&self) -> Severity {
+        match self {
+            Self::AgentNominated | Self::AgentResumed | Self::TreasuryTopUp { .. } => {
+                Severity::Info
+            }
+            Self::LowBalance { .. }
+            | Self::AgentPaused { .. }
+            | Self::TaskJailed { .. }
+            | Self::BatchFailures { .. } => Severity::Warning,
+            Self::AgentStopped { .. }
+            | Self::AgentUnregistered { .. }
+            | Self::RegisterFailed { .. }
+            | Self::TreasuryCapReached { .. }
+            | Self::NoRpcSources => Severity::Critical,
+        }
+    }
+
+    /// Events with the same key are rate limited together.
+    fn key(&self) -> String {
+        match self {
+            Self::LowBalance { .. } => "low_balance".to_string(),
+            Self::AgentNominated => "agent_nominated".to_string(),
+            Self::AgentStopped { .. } => "agent_stopped".to_string(),
+            Self::AgentPaused { .. } => "agent_paused".to_string(),
+            Self::AgentUnregistered { .. } => "agent_unregistered".to_string(),
+            Self::AgentResumed => "agent_resumed".to_string(),
+            Self::RegisterFailed { .. } => "register_failed".to_string(),
+            Self::TreasuryTopUp { .. } => "treasury_top_up".to_string(),
+            Self::TreasuryCapReached { .. } => "treasury_cap_reached".to_string(),
+            Self::TaskJailed { task_hash } => format!("task_jailed:{task_hash}"),
+            Self::BatchFailures { kind, .. } => format!("batch_failures:{kind}"),
+            Self::NoRpcSources => "no_rpc_sources".to_string(),
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            Self::LowBalance {
+                balance,
+                denom,
+                threshold,
+            } => format!(
+                "Agent balance {balance} {denom} is below the threshold of {threshold} {denom}"
+            ),
+            Self::AgentNominated => "Agent was nominated and is checking in".to_string(),
+            Self::AgentStopped { reason } => format!("Agent stopped: {reason}"),
+            Self::AgentPaused { reason } => format!("Agent paused: {reason}"),
+            Self::AgentUnregistered { reason } => format!("Agent unregistered: {reason}"),
+            Self::AgentResumed => "Agent funded again and resumed".to_string(),
+            Self::RegisterFailed { reason } => {
+                format!("Failed to register the agent again: {reason}")
+            }
+            Self::TreasuryTopUp { amount, denom } => {
+                format!("Topped up agent with {amount} {denom} from the treasury")
+            }
+            Self::TreasuryCapReached { spent, cap, denom } => format!(
+                "Treasury sent {spent} {denom} today, another top-up would pass the daily cap of {cap} {denom}"
+            ),
+            Self::TaskJailed { task_hash } => {
+                format!("Task jailed after repeated failures: {task_hash}")
+            }
+            Self::BatchFailures { kind, consecutive } => {
+                format!("{consecutive} {kind} proxy call batches failed in a row")
+            }
+            Self::NoRpcSources => "Every RPC source is marked bad".to_string(),
+        }
+    }
+}
+
+///
+/// The alert sent to sinks.
+///
+#[derive(Debug, Clone, Serialize)]
+pub struct Alert {
+    pub chain_id: String,
+    pub severity: Severity,
+    pub message: String,
+    pub timestamp: String,
+    #[serde(flatten)]
+    pub event: AlertEvent,
+}
+
+impl Alert {
+    fn summary(&self) -> String {
+        format!("[{}] {:?}: {}", self.chain_id, self.severity, self.message)
+    }
+}
+
+/// Tracks when each event was last sent.
+#[derive(Debug, Default)]
+struct RateLimiter {
+    window: Duration,
+    sent: HashMap<String, Instant>,
+}
+
+impl RateLimiter {
+    fn new(window: Duration) -> Self {
+        Self {
+            window,
+            sent: HashMap::new(),
+        }
+    }
+
+    /// Whether an event can be sent now, recording it if so.
+    fn allow(&mut self, key: &str, now: Instant) -> bool {
+        match self.sent.get(key) {
+            Some(last) if now.duration_since(*last) < self.window => false,
+            _ => {
+                self.sent.insert(key.to_string(), now);
+                true
+            }
+        }
+    }
+}
+
+struct Alerter {
+    config: AlertsConfig,
+    limiter: RateLimiter,
+    /// Consecutive failed batches, by batch kind.
+    batch_failures: HashMap<String, u32>,
+}
+
+/// Set up alerts for a chain, state is kept when a chain restarts.
+pub async fn register(chain_id: &str, config: Option<AlertsConfig>) {
+    let Some(config) = config else {
+        return;
+    };
+    let window = Duration::from_secs(
+        config
+            .rate_limit_seconds
+            .unwrap_or(DEFAULT_RATE_LIMIT_SECONDS),
+    );
+    ALERTERS
+        .lock()
+        .await
+        .entry(chain_id.to_string())
+        .or_insert_with(|| Alerter {
+            config,
+            limiter: RateLimiter::new(window),
+            batch_failures: HashMap::new(),
+        });
+}
+
+/// Build the alert, if it passes the chain's severity filter and rate limit.
+async fn prepare(chain_id: &str, event: AlertEvent) -> Option<(Alert, Vec<AlertSink>)> {
+    let mut alerters = ALERTERS.lock().await;
+    let alerter = alerters.get_mut(chain_id)?;
+    let severity = event.severity();
+    if severity < alerter.config.min_severity
+        || !alerter.limiter.allow(&event.key(), Instant::now())
+    {
+        return None;
+    }
+
+    let alert = Alert {
+        chain_id: chain_id.to_string(),
+        severity,
+        message: event.message(),
+        timestamp: Utc::now().to_rfc3339(),
+        event,
+    };
+    Some((alert, alerter.config.sinks.clone()))
+}
+
+///
+/// Send an alert in the background, so the caller isn't held up by slow sinks.
+///
+pub async fn emit(chain_id: &str, event: AlertEvent) {
+    if let Some((alert, sinks)) = prepare(chain_id, event).await {
+        tokio::spawn(async move { deliver(&alert, &sinks).await });
+    }
+}
+
+///
+/// Send an alert and wait for it to be delivered, for when the process is about to exit.
+///
+pub async fn emit_and_wait(chain_id: &str, event: AlertEvent) {
+    if let Some((alert, sinks)) = prepare(chain_id, event).await {
+        deliver(&alert, &sinks).await;
+    }
+}
+
+///
+/// Track the result of a proxy call batch, alerting once failures
+/// reach the chain's `batch_failure_threshold` in a row.
+///
+pub async fn record_batch(chain_id: &str, kind: &str, failed: bool) {
+    let consecutive = {
+        let mut alerters = ALERTERS.lock().await;
+        let Some(alerter) = alerters.get_mut(chain_id) else {
+            return;
+        };
+        let threshold = alerter
+            .config
+            .batch_failure_threshold
+            .unwrap_or(DEFAULT_BATCH_FAILURE_THRESHOLD);
+        let count = alerter.batch_failures.entry(kind.to_string()).or_default();
+        *count = if failed { count.saturating_add(1) } else { 0 };
+        if *count < threshold {
+            return;
+        }
+        *count
+    };
+
+    emit(
+        chain_id,
+        AlertEvent::BatchFailures {
+            kind: kind.to_string(),
+            consecutive,
+        },
+    )
+    .await;
+}
+
+async fn deliver(alert: &Alert, sinks: &[AlertSink]) {
+    debug!("Sending alert: {}", alert.summary());
+    for sink in sinks {
+        if let Err(err) = send(alert, sink).await {
+            warn!("Failed to send alert: {}", err);
+        }
+    }
+}
+
+async fn send(alert: &Alert, sink: &AlertSink) -> Result<(), Report> {
+    let timeout = Duration::from_secs(SINK_TIMEOUT_SECONDS);
+    let (url, body) = match sink {
+        AlertSink::Webhook { url } => (url, serde_json::to_value(alert)?),
+        AlertSink::Slack { url } => (url, serde_json::json!({ "text": alert.summary() })),
+        AlertSink::Discord { url } => (url, serde_json::json!({ "content": alert.summary() })),
+        AlertSink::Command { command, args } => {
+            let status = tokio::process::Command::new(command)
+                .args(args)
+                .env("CRONCAT_ALERT_CHAIN_ID", &alert.chain_id)
+                .env(
+                    "CRONCAT_ALERT_SEVERITY",
+                    format!("{:?}", alert.severity).to_lowercase(),
+                )
+                .env("CRONCAT_ALERT_MESSAGE", &alert.message)
+                .env("CRONCAT_ALERT_JSON", serde_json::to_string(alert)?)
+                .kill_on_drop(true)
+                .status();
+            let status = tokio::time::timeout(timeout, status)
+                .await
+                .map_err(|_| eyre!("Alert command timed out: {}", command))??;
+            if !status.success() {
+                return Err(eyre!("Alert command {} exited with {}", command, status));
+            }
+            return Ok(());
+        }
+    };
+
+    reqwest::Client::new()
+        .post(url.clone())
+        .header(CONTENT_TYPE, "application/json")
+        .body(body.to_string())
+        .timeout(timeout)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_limits_by_event() {
+        let mut limiter = RateLimiter::new(Duration::from_secs(60));
+        let now = Instant::now();
+        let jailed = AlertEvent::TaskJailed {
+            task_hash: "abc".to_string(),
+        };
+
+        assert!(limiter.allow(&jailed.key(), now));
+        assert!(!limiter.allow(&jailed.key(), now + Duration::from_secs(30)));
+        assert!(limiter.allow(&AlertEvent::NoRpcSources.key(), now));
+        assert!(limiter.allow(&jailed.key(), now + Duration::from_secs(61)));
+    }
+}
@@ -30,3 +30,18 @@ pub type StatusStreamRx = broadcast::Receiver<utils::Status>;
 pub fn create_shutdown_channel() -> (ShutdownTx, ShutdownRx) {
     broadcast::channel(1)
 }
+
+///
+/// Wait for the next block, or `None` once shutdown is signalled, so a loop
+/// finishes the block it's working on before stopping.
+///
+pub async fn next_block(
+    block_stream_rx: &mut StatusStreamRx,
+    shutdown_rx: &mut ShutdownRx,
+) -> Option<utils::Status> {
+    tokio::select! {
+        biased;
+        _ = shutdown_rx.recv() => None,
+        block = block_stream_rx.recv() => block.ok(),
+    }
+}
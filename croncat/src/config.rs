@@ -1,25 +1,211 @@
 //! Agent configuration.
 
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
 
 use color_eyre::Result;
-use cosmos_chain_registry::{chain::Rpc, ChainInfo, ChainRegistry};
 use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
 use url::Url;
 
+use crate::{
+    alerts::AlertsConfig,
+    errors::CroncatError,
+    registry::{self, ChainInfo, RegistrySnapshot, Rpc},
+};
+
 #[derive(Debug, Clone, Serialize)]
 pub struct Config {
     pub chains: HashMap<String, ChainConfig>,
 }
 
+/// The config file read from the working directory when no path is given.
+const DEFAULT_CONFIG_FILENAME: &str = "config.yaml";
+
+/// Prefix of the environment variables overriding chain config fields.
+const ENV_OVERRIDE_PREFIX: &str = "CRONCAT_";
+
 impl Config {
     pub fn from_pwd() -> Result<Self> {
-        let pwd = std::env::current_dir()?;
-        let config_path = pwd.join("config.yaml");
-        let config = std::fs::read_to_string(config_path)?;
-        let config = serde_yaml::from_str(&config)?;
+        Self::from_path(None)
+    }
+
+    ///
+    /// Load the config from a file, or a directory of fragments, falling back
+    /// to `./config.yaml`. Chain fields can be overridden from the environment.
+    ///
+    pub fn from_path(path: Option<&Path>) -> Result<Self> {
+        let config = Self::read_yaml(path)?;
+        let config = serde_yaml::from_value(config)?;
         Ok(config)
     }
+
+    ///
+    /// Refresh the local registry snapshot for every chain in the config
+    /// that doesn't define its chain info inline.
+    ///
+    pub fn sync_registry(path: Option<&Path>) -> Result<(Vec<String>, PathBuf)> {
+        let config: RawConfig = serde_yaml::from_value(Self::read_yaml(path)?)?;
+        let registry_path = config.registry_path();
+        let chain_ids: Vec<String> = config
+            .chains
+            .into_iter()
+            .filter(|(_, entry)| entry.chain_info.is_none())
+            .map(|(chain_id, _)| chain_id)
+            .collect();
+
+        registry::sync(&chain_ids, &registry_path)?;
+
+        Ok((chain_ids, registry_path))
+    }
+
+    /// The absolute path of the config that would be loaded, handy for service units.
+    pub fn resolve_path(path: Option<&Path>) -> Result<PathBuf> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => std::env::current_dir()?.join(DEFAULT_CONFIG_FILENAME),
+        };
+        path.canonicalize().map_err(|err| {
+            CroncatError::Config(format!("Config not found at {path:?}: {err}")).into()
+        })
+    }
+
+    /// Read the raw config and layer the environment overrides on top.
+    fn read_yaml(path: Option<&Path>) -> Result<Value> {
+        let path = Self::resolve_path(path)?;
+        let mut config = if path.is_dir() {
+            read_fragments(&path)?
+        } else {
+            read_file(&path)?
+        };
+        apply_env_overrides(&mut config, std::env::vars());
+
+        Ok(Value::Mapping(config))
+    }
+}
+
+fn read_file(path: &Path) -> Result<Mapping> {
+    let contents = fs::read_to_string(path)?;
+    let value: Value = serde_yaml::from_str(&contents)
+        .map_err(|err| CroncatError::Config(format!("Failed to parse {path:?}: {err}")))?;
+    match value {
+        Value::Mapping(mapping) => Ok(mapping),
+        Value::Null => Ok(Mapping::new()),
+        _ => Err(CroncatError::Config(format!("Expected a mapping in {path:?}")).into()),
+    }
+}
+
+///
+/// Merge every `*.yaml` file in the directory in name order. `config.yaml`, and any
+/// fragment with a `chains` key, is a top level config. Other fragments hold a
+/// single chain entry named after the file, e.g. `juno-1.yaml`.
+///
+fn read_fragments(dir: &Path) -> Result<Mapping> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<_>>()?;
+    paths.retain(|path| {
+        path.is_file()
+            && matches!(
+                path.extension().and_then(|ext| ext.to_str()),
+                Some("yaml" | "yml")
+            )
+    });
+    paths.sort();
+
+    let mut config = Mapping::new();
+    for path in paths {
+        let fragment = read_file(&path)?;
+        let is_top_level = path.file_name() == Some(OsStr::new(DEFAULT_CONFIG_FILENAME))
+            || fragment.contains_key("chains");
+        if is_top_level {
+            merge_yaml(&mut config, fragment);
+        } else {
+            let chain_id = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .ok_or_else(|| CroncatError::Config(format!("Invalid fragment name {path:?}")))?;
+            let mut chains = Mapping::new();
+            chains.insert(chain_id.into(), Value::Mapping(fragment));
+            let mut wrapped = Mapping::new();
+            wrapped.insert("chains".into(), Value::Mapping(chains));
+            merge_yaml(&mut config, wrapped);
+        }
+    }
+
+    Ok(config)
+}
+
+/// Deep merge `other` into `base`, values in `other` win.
+fn merge_yaml(base: &mut Mapping, other: Mapping) {
+    for (key, value) in other {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Mapping(base_map)), Value::Mapping(other_map)) => {
+                merge_yaml(base_map, other_map)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+///
+/// Override chain entry fields with `CRONCAT_<CHAIN_ID>_<FIELD>` variables, the chain id
+/// upper cased with dashes as underscores. Values are parsed as YAML, so numbers,
+/// booleans and inline maps all work.
+///
+fn apply_env_overrides(config: &mut Mapping, vars: impl IntoIterator<Item = (String, String)>) {
+    let chains = match config.get_mut("chains") {
+        Some(Value::Mapping(chains)) => chains,
+        _ => return,
+    };
+    let prefixes: Vec<(Value, String)> = chains
+        .keys()
+        .filter_map(|chain_id| {
+            chain_id
+                .as_str()
+                .map(|id| (chain_id.clone(), env_prefix(id)))
+        })
+        .collect();
+
+    for (key, value) in vars {
+        // The longest prefix wins, so `juno-1` isn't mistaken for `juno`.
+        let matched = prefixes
+            .iter()
+            .filter(|(_, prefix)| key.len() > prefix.len() && key.starts_with(prefix.as_str()))
+            .max_by_key(|(_, prefix)| prefix.len());
+        let (chain_id, prefix) = match matched {
+            Some(matched) => matched,
+            None => continue,
+        };
+
+        let field = key[prefix.len()..].to_lowercase();
+        let value = serde_yaml::from_str(&value).unwrap_or(Value::String(value));
+        if let Some(Value::Mapping(entry)) = chains.get_mut(chain_id) {
+            entry.insert(field.into(), value);
+        }
+    }
+}
+
+/// The environment variable prefix for a chain, `juno-1` becomes `CRONCAT_JUNO_1_`.
+fn env_prefix(chain_id: &str) -> String {
+    let chain_id: String = chain_id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{ENV_OVERRIDE_PREFIX}{chain_id}_")
 }
 
 impl<'de> Deserialize<'de> for Config {
@@ -28,24 +214,47 @@ impl<'de> Deserialize<'de> for Config {
         D: serde::Deserializer<'de>,
     {
         // Deserialize the raw config entry so we can get info from the chain registry.
-        let config_yaml =
-            HashMap::<String, HashMap<String, RawChainConfigEntry>>::deserialize(deserializer)?;
-        let chains = config_yaml
-            .get("chains")
-            .ok_or_else(|| serde::de::Error::custom("missing 'chains' key"))?;
-        let registry =
-            ChainRegistry::from_remote().map_err(|e| serde::de::Error::custom(e.to_string()))?;
+        let config_yaml = RawConfig::deserialize(deserializer)?;
+        let snapshot = RegistrySnapshot::load(&config_yaml.registry_path())
+            .map_err(|e| serde::de::Error::custom(e.to_string()))?;
+
+        // Chain info comes from the config first, then the local snapshot, then the remote registry if allowed.
+        let mut missing: Vec<String> = config_yaml
+            .chains
+            .iter()
+            .filter(|(chain_id, entry)| {
+                entry.chain_info.is_none() && snapshot.get(chain_id).is_none()
+            })
+            .map(|(chain_id, _)| chain_id.clone())
+            .collect();
+        missing.sort();
+        let remote = if missing.is_empty() {
+            HashMap::new()
+        } else if config_yaml.remote_registry {
+            registry::fetch_remote(&missing).map_err(|e| serde::de::Error::custom(e.to_string()))?
+        } else {
+            return Err(serde::de::Error::custom(format!(
+                "No chain info for {}, add `chain_info` to the config, run `croncatd registry sync` or set `remote_registry: true`",
+                missing.join(", ")
+            )));
+        };
 
         // Collect the chain configs from the registry.
         let mut chain_configs = HashMap::new();
 
-        #[allow(clippy::unnecessary_to_owned)]
-        for (chain_id, entry) in chains.to_owned() {
-            let chain_info = registry.get_by_chain_id(&chain_id).map_err(|e| {
-                serde::de::Error::custom(format!("Registry get_by_chain_id error: {e}"))
-            })?;
-            let chain_config = ChainConfig::from_entry(chain_info, entry);
-            chain_configs.insert(chain_id.to_owned(), chain_config);
+        for (chain_id, entry) in config_yaml.chains {
+            let mut chain_info = entry
+                .chain_info
+                .clone()
+                .or_else(|| snapshot.get(&chain_id))
+                .or_else(|| remote.get(&chain_id).cloned())
+                .ok_or_else(|| serde::de::Error::custom(format!("No chain info for {chain_id}")))?;
+            if chain_info.chain_id.is_empty() {
+                chain_info.chain_id = chain_id.clone();
+            }
+            let chain_config = ChainConfig::from_entry(chain_info, entry)
+                .map_err(|e| serde::de::Error::custom(e.to_string()))?;
+            chain_configs.insert(chain_id, chain_config);
         }
 
         // Return the config.
@@ -55,6 +264,25 @@ impl<'de> Deserialize<'de> for Config {
     }
 }
 
+/// The top level of `config.yaml`.
+#[derive(Debug, Clone, Deserialize)]
+struct RawConfig {
+    pub chains: HashMap<String, RawChainConfigEntry>,
+    /// Look up chains missing from the local snapshot in the remote registry.
+    #[serde(default)]
+    pub remote_registry: bool,
+    /// Registry snapshot to use instead of the default one.
+    pub registry_path: Option<PathBuf>,
+}
+
+impl RawConfig {
+    fn registry_path(&self) -> PathBuf {
+        self.registry_path
+            .clone()
+            .unwrap_or_else(RegistrySnapshot::default_path)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct RawChainConfigEntry {
     pub factory: String,
@@ -62,19 +290,146 @@ struct RawChainConfigEntry {
     pub block_polling_seconds: Option<f64>,
     pub block_polling_timeout_seconds: Option<f64>,
     pub websocket_timeout_seconds: Option<f64>,
+    pub drain_timeout_seconds: Option<f64>,
     pub uptime_ping_url: Option<Url>,
+    pub uptime_ping_fail_url: Option<Url>,
+    pub uptime_ping_interval_seconds: Option<f64>,
+    pub uptime_ping_method: Option<HeartbeatMethod>,
     pub gas_prices: Option<f32>,
     pub gas_adjustment: Option<f32>,
     pub threshold: Option<u64>,
+    pub low_balance_policy: Option<LowBalancePolicy>,
+    pub treasury: Option<TreasuryConfig>,
     pub include_evented_tasks: Option<bool>,
     pub custom_sources: Option<HashMap<String, ChainDataSource>>,
     pub rpc_timeout_seconds: Option<f64>,
     pub denom: Option<String>,
+    pub fee_granter: Option<String>,
+    pub authz_granter: Option<String>,
+    pub metrics_address: Option<SocketAddr>,
+    pub max_batch_gas: Option<u64>,
+    pub max_batch_size: Option<usize>,
+    pub query_concurrency: Option<usize>,
+    pub query_batch_size: Option<usize>,
+    pub simulation: Option<SimulationPolicy>,
+    pub alerts: Option<AlertsConfig>,
+    pub chain_info: Option<ChainInfo>,
+}
+
+///
+/// When to simulate a transaction before broadcasting it.
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SimulationPolicy {
+    /// Simulate every contract execution.
+    Always,
+    /// Only simulate evented task batches.
+    Evented,
+    /// Never simulate, broadcast straight away through cosm-orc.
+    #[default]
+    Never,
+}
+
+impl SimulationPolicy {
+    /// Whether an execution should be simulated under this policy.
+    pub fn applies(&self, evented: bool) -> bool {
+        match self {
+            Self::Always => true,
+            Self::Evented => evented,
+            Self::Never => false,
+        }
+    }
+}
+
+///
+/// What to do when the agent balance stays below `threshold`.
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LowBalancePolicy {
+    /// Stop sending proxy calls until the agent is funded again.
+    #[default]
+    Pause,
+    /// Unregister the agent, registering again once it's funded.
+    Unregister,
+    /// Stop the chain with an error.
+    Exit,
+}
+
+///
+/// Top up the agent from a treasury account in the keystore when it runs low.
+///
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TreasuryConfig {
+    /// Name of the keystore account to send funds from.
+    pub account: String,
+    /// Sent to the agent each time its balance drops below `threshold`.
+    pub top_up_amount: u64,
+    /// Most the treasury will send in a UTC day.
+    pub daily_cap: u64,
+}
+
+///
+/// How the heartbeat status is sent to the uptime monitor.
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HeartbeatMethod {
+    /// GET the ping URL with the status as query params.
+    #[default]
+    Get,
+    /// POST the status as a JSON body.
+    Post,
+}
+
+///
+/// How blocks are read from a data source.
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    /// Poll the RPC status endpoint over HTTP.
+    #[default]
+    Http,
+    /// Subscribe to new blocks over the RPC websocket.
+    Websocket,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChainDataSource {
     pub rpc: String,
+    pub transport: Option<Transport>,
+}
+
+impl ChainDataSource {
+    /// Whether blocks come from a websocket subscription, set by `transport` or a `ws:` address.
+    pub fn is_websocket(&self) -> bool {
+        self.transport == Some(Transport::Websocket)
+            || self.rpc.starts_with("ws://")
+            || self.rpc.starts_with("wss://")
+    }
+
+    ///
+    /// The websocket address for this source, an http address gets
+    /// switched over to the tendermint `/websocket` endpoint.
+    ///
+    pub fn websocket_url(&self) -> String {
+        let rpc = self.rpc.trim_end_matches('/');
+        if rpc.starts_with("ws://") || rpc.starts_with("wss://") {
+            return rpc.to_string();
+        }
+
+        let host = rpc
+            .strip_prefix("https://")
+            .map(|host| format!("wss://{host}"))
+            .or_else(|| {
+                rpc.strip_prefix("http://")
+                    .map(|host| format!("ws://{host}"))
+            })
+            .unwrap_or_else(|| format!("wss://{rpc}"));
+        format!("{host}/websocket")
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -85,51 +440,119 @@ pub struct ChainConfig {
     pub block_polling_seconds: f64,
     pub block_polling_timeout_seconds: f64,
     pub websocket_timeout_seconds: f64,
+    /// How long loops get to finish their current block on shutdown.
+    pub drain_timeout_seconds: f64,
     pub uptime_ping_url: Option<Url>,
+    /// Pinged when the chain shuts down with an error, defaults to `<uptime_ping_url>/fail`.
+    pub uptime_ping_fail_url: Option<Url>,
+    /// How often to ping `uptime_ping_url`.
+    pub uptime_ping_interval_seconds: f64,
+    /// How the heartbeat status is sent along with the ping.
+    pub uptime_ping_method: HeartbeatMethod,
     pub gas_prices: f32,
     pub gas_adjustment: f32,
     pub threshold: Option<u64>,
+    /// What to do when the balance stays below `threshold` after withdrawing rewards.
+    pub low_balance_policy: LowBalancePolicy,
+    /// Tops up the agent when it's low and has no rewards to withdraw.
+    pub treasury: Option<TreasuryConfig>,
     pub include_evented_tasks: Option<bool>,
     pub rpc_timeout_seconds: Option<f64>,
     pub denom: Option<String>,
+    /// The denom fees are paid in, the chain's first fee token.
+    pub fee_denom: String,
+    /// Account paying the agent's fees through a fee grant, instead of the agent itself.
+    pub fee_granter: Option<String>,
+    /// Cold account the agent acts for through an authz grant, the stored key only signs.
+    pub authz_granter: Option<String>,
+    /// Serve prometheus metrics at this address, if set.
+    pub metrics_address: Option<SocketAddr>,
+    /// Proxy calls get split into several transactions to stay under this much gas.
+    pub max_batch_gas: u64,
+    /// Cap on proxy calls in a single transaction, if set.
+    pub max_batch_size: Option<usize>,
+    /// How many evented task query calls can be in flight at once.
+    pub query_concurrency: usize,
+    /// How many tasks' queries to combine into a single `mod_generic` call.
+    pub query_batch_size: usize,
+    /// When to simulate transactions before broadcasting them.
+    pub simulation: SimulationPolicy,
+    /// Where to send alerts for agent events, if anywhere.
+    pub alerts: Option<AlertsConfig>,
 }
 
 impl ChainConfig {
-    fn from_entry(mut info: ChainInfo, entry: RawChainConfigEntry) -> Self {
-        let gas_prices = entry
-            .gas_prices
-            .unwrap_or(info.fees.fee_tokens[0].fixed_min_gas_price);
+    fn from_entry(mut info: ChainInfo, entry: RawChainConfigEntry) -> Result<Self, CroncatError> {
+        let fee_token = info.fees.fee_tokens.first().cloned().ok_or_else(|| {
+            CroncatError::Config(format!(
+                "No fee tokens for {}, add one to fees.fee_tokens in its chain_info",
+                info.chain_id
+            ))
+        })?;
+        let gas_prices = entry.gas_prices.unwrap_or(fee_token.fixed_min_gas_price);
         let gas_adjustment = entry.gas_adjustment.unwrap_or(1.5);
         let block_polling_seconds = entry.block_polling_seconds.unwrap_or(5.0);
         let block_polling_timeout_seconds = entry.block_polling_timeout_seconds.unwrap_or(30.0);
         let websocket_timeout_seconds = entry.websocket_timeout_seconds.unwrap_or(30.0);
+        let drain_timeout_seconds = entry.drain_timeout_seconds.unwrap_or(30.0);
+        let max_batch_gas = entry.max_batch_gas.unwrap_or(3_000_000);
+        let query_concurrency = entry.query_concurrency.unwrap_or(8).max(1);
+        let query_batch_size = entry.query_batch_size.unwrap_or(1).max(1);
+        let uptime_ping_interval_seconds = entry.uptime_ping_interval_seconds.unwrap_or(60.0);
+        if !uptime_ping_interval_seconds.is_finite() || uptime_ping_interval_seconds <= 0.0 {
+            return Err(CroncatError::Config(format!(
+                "uptime_ping_interval_seconds must be a positive number of seconds for {}, got {uptime_ping_interval_seconds}",
+                info.chain_id
+            )));
+        }
 
         // Add optional custom sources to the chain info.
         if let Some(custom_sources) = entry.custom_sources {
             for (provider, source) in custom_sources {
-                // Add the custom RPC source.
+                // Add the custom RPC source, websocket sources are kept by their ws address.
+                let address = if source.is_websocket() {
+                    source.websocket_url()
+                } else {
+                    source.rpc.clone()
+                };
                 info.apis.rpc.push(Rpc {
                     provider: Some(provider.clone()),
-                    address: source.rpc.clone(),
+                    address,
                 });
             }
         }
 
-        Self {
+        Ok(Self {
             info,
             factory: entry.factory,
             registry: entry.registry,
             block_polling_seconds,
             block_polling_timeout_seconds,
             websocket_timeout_seconds,
+            drain_timeout_seconds,
             uptime_ping_url: entry.uptime_ping_url,
+            uptime_ping_fail_url: entry.uptime_ping_fail_url,
+            uptime_ping_interval_seconds,
+            uptime_ping_method: entry.uptime_ping_method.unwrap_or_default(),
             gas_prices,
             gas_adjustment,
             threshold: entry.threshold,
+            low_balance_policy: entry.low_balance_policy.unwrap_or_default(),
+            treasury: entry.treasury,
             include_evented_tasks: entry.include_evented_tasks,
             rpc_timeout_seconds: entry.rpc_timeout_seconds,
             denom: entry.denom,
-        }
+            fee_denom: fee_token.denom,
+            fee_granter: entry.fee_granter,
+            authz_granter: entry.authz_granter,
+            metrics_address: entry.metrics_address,
+            max_batch_gas,
+            max_batch_size: entry.max_batch_size,
+            query_concurrency,
+            query_batch_size,
+            simulation: entry.simulation.unwrap_or_default(),
+            alerts: entry.alerts,
+        })
     }
 
     pub fn data_sources(&self) -> HashMap<String, ChainDataSource> {
@@ -137,15 +560,64 @@ impl ChainConfig {
 
         for rpc_endpoint in self.info.apis.rpc.iter() {
             if rpc_endpoint.provider.is_some() {
-                data_sources.insert(
-                    rpc_endpoint.provider.clone().unwrap(),
-                    ChainDataSource {
-                        rpc: rpc_endpoint.address.clone(),
-                    },
-                );
+                let mut source = ChainDataSource {
+                    rpc: rpc_endpoint.address.clone(),
+                    transport: None,
+                };
+                if source.is_websocket() {
+                    source.transport = Some(Transport::Websocket);
+                }
+                data_sources.insert(rpc_endpoint.provider.clone().unwrap(), source);
             }
         }
 
         data_sources
     }
+
+    /// Data sources we can send queries and transactions to, websocket sources only stream blocks.
+    pub fn rpc_sources(&self) -> HashMap<String, ChainDataSource> {
+        let mut data_sources = self.data_sources();
+        data_sources.retain(|_, source| !source.is_websocket());
+        data_sources
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn env_overrides_chain_fields() {
+        let mut config: Mapping = serde_yaml::from_str(
+            "chains:\n  juno:\n    factory: juno1a\n  juno-1:\n    factory: juno1b\n    gas_prices: 0.04\n",
+        )
+        .unwrap();
+        let vars = [
+            ("CRONCAT_JUNO_1_GAS_PRICES", "0.075"),
+            ("CRONCAT_JUNO_1_INCLUDE_EVENTED_TASKS", "true"),
+            ("CRONCAT_JUNO_DENOM", "ujuno"),
+            ("CRONCAT_CONFIG", "/etc/croncat"),
+        ]
+        .map(|(key, value)| (key.to_string(), value.to_string()));
+        apply_env_overrides(&mut config, vars);
+
+        let raw: RawConfig = serde_yaml::from_value(Value::Mapping(config)).unwrap();
+        let juno_1 = &raw.chains["juno-1"];
+        assert_eq!(juno_1.gas_prices, Some(0.075));
+        assert_eq!(juno_1.include_evented_tasks, Some(true));
+        assert_eq!(juno_1.denom, None);
+        assert_eq!(raw.chains["juno"].denom.as_deref(), Some("ujuno"));
+    }
+
+    #[test]
+    fn inline_chain_info_needs_a_fee_token() {
+        let entry: RawChainConfigEntry = serde_yaml::from_str(
+            "factory: juno1a\nchain_info:\n  chain_id: juno-1\n  bech32_prefix: juno\n  fees:\n    fee_tokens: []\n",
+        )
+        .unwrap();
+        let info = entry.chain_info.clone().unwrap();
+
+        let err = ChainConfig::from_entry(info, entry).unwrap_err();
+        assert!(err.to_string().contains("No fee tokens for juno-1"));
+    }
 }
@@ -5,3 +5,436 @@
 
 pub use color_eyre::eyre::eyre;
 pub use color_eyre::Report;
+use thiserror::Error;
+
+///
+/// Errors returned by the croncat contracts, parsed from the raw chain response.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContractError {
+    /// The agent isn't registered with the agents contract.
+    AgentNotRegistered,
+    /// The agent is already registered with the agents contract.
+    AgentAlreadyRegistered,
+    /// Registration is whitelisted and this agent isn't approved yet.
+    WhitelistRequired,
+    /// The manager has no tasks for this agent in the current slot.
+    NoTasksInSlot,
+    /// The agent has no rewards to withdraw.
+    NoRewards,
+    /// A task action or query underflowed or overflowed while executing.
+    Arithmetic,
+    /// The contract doesn't exist on this node.
+    ContractNotFound,
+    /// Any contract error we don't handle specifically.
+    Other,
+}
+
+impl ContractError {
+    /// Parse the contract error from a lowercased error message.
+    fn parse(msg: &str) -> Option<Self> {
+        if msg.contains("agent not registered") || msg.contains("agent not found") {
+            Some(Self::AgentNotRegistered)
+        } else if msg.contains("already registered") {
+            Some(Self::AgentAlreadyRegistered)
+        } else if msg.contains("whitelist") {
+            Some(Self::WhitelistRequired)
+        } else if msg.contains("no tasks to be done in this slot") {
+            Some(Self::NoTasksInSlot)
+        } else if msg.contains("no rewards available for withdraw") {
+            Some(Self::NoRewards)
+        } else if msg.contains("underflow") || msg.contains("overflow") {
+            Some(Self::Arithmetic)
+        } else if msg.contains("contract: not found") {
+            Some(Self::ContractNotFound)
+        } else if msg.contains("execute wasm contract failed")
+            || msg.contains("query wasm contract failed")
+        {
+            Some(Self::Other)
+        } else {
+            None
+        }
+    }
+}
+
+///
+/// Where a transaction that failed after it was broadcast ended up.
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TxReceipt {
+    pub hash: String,
+    pub height: u64,
+    pub gas_wanted: u64,
+    pub gas_used: u64,
+    /// Fee amount paid in the chain's fee denom.
+    pub fee: u128,
+}
+
+/// Codespace of errors raised by the Cosmos SDK itself.
+pub const SDK_CODESPACE: &str = "sdk";
+
+/// Codespace of errors raised by the wasm module.
+pub const WASM_CODESPACE: &str = "wasm";
+
+///
+/// Every error the agent knows how to act on, the message is kept as-is for logging.
+///
+#[derive(Debug, Clone, Error)]
+pub enum CroncatError {
+    /// The RPC node couldn't be reached or returned garbage.
+    #[error("{0}")]
+    Transport(String),
+    /// The call didn't finish in time.
+    #[error("{0}")]
+    Timeout(String),
+    /// The contract rejected the call.
+    #[error("{message}")]
+    Contract {
+        kind: ContractError,
+        message: String,
+    },
+    /// The agent account doesn't exist on chain.
+    #[error("{0}")]
+    AccountNotFound(String),
+    /// The transaction was signed with a stale account sequence, nothing was broadcast.
+    #[error("{0}")]
+    Sequence(String),
+    /// Not enough funds to pay for the transaction.
+    #[error("{0}")]
+    InsufficientFunds(String),
+    /// Local configuration or key storage is invalid.
+    #[error("{0}")]
+    Config(String),
+    /// The agent stopped for good, restarting it won't help.
+    #[error("{0}")]
+    Stopped(String),
+    /// The transaction may have reached the mempool but we didn't see it in a block,
+    /// sending it again could execute it twice.
+    #[error("{message}")]
+    TxPending {
+        hash: Option<String>,
+        message: String,
+    },
+    /// The transaction was included in a block but failed, the fee was still paid.
+    #[error("{cause}")]
+    TxFailed {
+        cause: Box<CroncatError>,
+        receipt: TxReceipt,
+    },
+    /// Anything we couldn't classify.
+    #[error("{0}")]
+    Unknown(String),
+}
+
+impl CroncatError {
+    /// Create a contract error with a custom message.
+    pub fn contract(kind: ContractError, message: impl Into<String>) -> Self {
+        Self::Contract {
+            kind,
+            message: message.into(),
+        }
+    }
+
+    ///
+    /// Classify a report, using the typed error if one was already attached,
+    /// otherwise parsing the message returned by the chain.
+    ///
+    pub fn classify(err: &Report) -> Self {
+        if let Some(err) = err.downcast_ref::<CroncatError>() {
+            return err.clone();
+        }
+        if let Some(err) = err.downcast_ref::<tendermint_rpc::Error>() {
+            if let Some(error) = Self::from_rpc(err) {
+                return error;
+            }
+        }
+        if let Some(err) = err.downcast_ref::<reqwest::Error>() {
+            return Self::from_http(err);
+        }
+
+        Self::from_message(err.to_string())
+    }
+
+    ///
+    /// Classify an error code returned by the chain, for a transaction or an ABCI query.
+    /// Codes we don't know fall back to the message.
+    ///
+    pub fn from_abci(codespace: &str, code: u32, log: &str) -> Self {
+        let message = log.to_string();
+        match (codespace, code) {
+            // ErrInvalidSequence, ErrWrongSequence
+            (SDK_CODESPACE, 3 | 32) => Self::Sequence(message),
+            // ErrInsufficientFunds, ErrInsufficientFee
+            (SDK_CODESPACE, 5 | 13) => Self::InsufficientFunds(message),
+            // ErrUnknownAddress
+            (SDK_CODESPACE, 9) => Self::AccountNotFound(message),
+            // ErrNotFound
+            (WASM_CODESPACE, 8) => Self::Contract {
+                kind: ContractError::ContractNotFound,
+                message,
+            },
+            // ErrExecuteFailed, ErrQueryFailed, the contract's own error is in the log
+            (WASM_CODESPACE, 5 | 9) => Self::Contract {
+                kind: ContractError::parse(&message.to_lowercase()).unwrap_or(ContractError::Other),
+                message,
+            },
+            _ => Self::from_message(message),
+        }
+    }
+
+    /// Errors from the tendermint RPC client that say the node couldn't be reached.
+    fn from_rpc(err: &tendermint_rpc::Error) -> Option<Self> {
+        use tendermint_rpc::error::ErrorDetail;
+
+        match err.detail() {
+            ErrorDetail::Io(_)
+            | ErrorDetail::Http(_)
+            | ErrorDetail::Hyper(_)
+            | ErrorDetail::WebSocket(_) => Some(Self::Transport(err.to_string())),
+            ErrorDetail::WebSocketTimeout(_) => Some(Self::Timeout(err.to_string())),
+            _ => None,
+        }
+    }
+
+    /// Errors from HTTP requests, which never reached a node that could answer.
+    fn from_http(err: &reqwest::Error) -> Self {
+        if err.is_timeout() {
+            Self::Timeout(err.to_string())
+        } else {
+            Self::Transport(err.to_string())
+        }
+    }
+
+    ///
+    /// Parse the message of an error that came back untyped, like the ones
+    /// cosm-orc returns with the chain's log inlined.
+    ///
+    fn from_message(message: String) -> Self {
+        let msg = message.to_lowercase();
+
+        if msg.contains("timeout") || msg.contains("timed out") {
+            Self::Timeout(message)
+        } else if msg.contains("insufficient funds") || msg.contains("insufficient fee") {
+            Self::InsufficientFunds(message)
+        } else if msg.contains("account sequence mismatch") {
+            Self::Sequence(message)
+        } else if msg.contains("account") && msg.contains("not found") {
+            Self::AccountNotFound(message)
+        } else if let Some(kind) = ContractError::parse(&msg) {
+            Self::Contract { kind, message }
+        } else if msg.contains("error sending request") || msg.contains("failed to connect") {
+            Self::Transport(message)
+        } else {
+            Self::Unknown(message)
+        }
+    }
+
+    /// Wrap the error of a transaction that was included in a block but failed.
+    pub fn tx_failed(cause: Self, receipt: TxReceipt) -> Self {
+        Self::TxFailed {
+            cause: Box::new(cause),
+            receipt,
+        }
+    }
+
+    /// The underlying error, without the receipt of a failed transaction.
+    pub fn cause(&self) -> &Self {
+        match self {
+            Self::TxFailed { cause, .. } => cause.cause(),
+            _ => self,
+        }
+    }
+
+    /// Prefix the message with what we were doing when it happened.
+    pub fn context(mut self, context: impl std::fmt::Display) -> Self {
+        let message = match &mut self {
+            Self::TxFailed { cause, .. } => {
+                **cause = cause.as_ref().clone().context(context);
+                return self;
+            }
+            Self::Transport(message)
+            | Self::Timeout(message)
+            | Self::Contract { message, .. }
+            | Self::AccountNotFound(message)
+            | Self::Sequence(message)
+            | Self::InsufficientFunds(message)
+            | Self::Config(message)
+            | Self::Stopped(message)
+            | Self::TxPending { message, .. }
+            | Self::Unknown(message) => message,
+        };
+        *message = format!("{context}: {message}");
+        self
+    }
+
+    /// The parsed contract error, if the contract rejected the call.
+    pub fn contract_error(&self) -> Option<&ContractError> {
+        match self.cause() {
+            Self::Contract { kind, .. } => Some(kind),
+            _ => None,
+        }
+    }
+
+    ///
+    /// Errors that retrying won't fix, the operator has to step in.
+    ///
+    pub fn is_fatal(&self) -> bool {
+        match self {
+            Self::Contract { kind, .. } => matches!(
+                kind,
+                ContractError::AgentNotRegistered
+                    | ContractError::AgentAlreadyRegistered
+                    | ContractError::WhitelistRequired
+            ),
+            Self::AccountNotFound(_) | Self::Config(_) | Self::Stopped(_) => true,
+            Self::TxFailed { cause, .. } => cause.is_fatal(),
+            // A low balance is left to the low balance policy
+            Self::Transport(_)
+            | Self::Timeout(_)
+            | Self::Sequence(_)
+            | Self::InsufficientFunds(_)
+            | Self::TxPending { .. }
+            | Self::Unknown(_) => false,
+        }
+    }
+
+    ///
+    /// Errors caused by the RPC source itself, so another source should be tried.
+    /// Nothing that happened after a broadcast is, so a transaction is never sent twice.
+    ///
+    pub fn is_source_error(&self) -> bool {
+        match self {
+            Self::Transport(_) | Self::Timeout(_) | Self::Unknown(_) => true,
+            Self::Contract { .. }
+            | Self::AccountNotFound(_)
+            | Self::Sequence(_)
+            | Self::InsufficientFunds(_)
+            | Self::Config(_)
+            | Self::Stopped(_)
+            | Self::TxPending { .. }
+            | Self::TxFailed { .. } => false,
+        }
+    }
+
+    ///
+    /// Errors caused by a single task, which should be cooled down and eventually jailed.
+    ///
+    pub fn is_task_error(&self) -> bool {
+        matches!(self.contract_error(), Some(ContractError::Arithmetic))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_classify_contract_errors() {
+        let err = eyre!("execute wasm contract failed: Generic error: Agent not registered");
+        let classified = CroncatError::classify(&err);
+        assert_eq!(
+            classified.contract_error(),
+            Some(&ContractError::AgentNotRegistered)
+        );
+        assert!(classified.is_fatal());
+        assert!(!classified.is_source_error());
+
+        let err = eyre!("execute wasm contract failed: Overflow: Cannot Sub with 1 and 2");
+        let classified = CroncatError::classify(&err);
+        assert!(classified.is_task_error());
+        assert!(!classified.is_fatal());
+
+        let err = eyre!("execute wasm contract failed: No tasks to be done in this slot");
+        assert_eq!(
+            CroncatError::classify(&err).contract_error(),
+            Some(&ContractError::NoTasksInSlot)
+        );
+    }
+
+    #[test]
+    fn can_classify_chain_errors() {
+        let err = eyre!("Timeout (20s) while querying contract: deadline has elapsed");
+        assert!(matches!(
+            CroncatError::classify(&err),
+            CroncatError::Timeout(_)
+        ));
+
+        let err = eyre!("account juno1abc not found");
+        assert!(CroncatError::classify(&err).is_fatal());
+
+        let err = eyre!("No valid rpc sources available");
+        assert!(CroncatError::classify(&err).is_source_error());
+
+        // A URL in the message doesn't make it a transport error
+        let err = eyre!("execute wasm contract failed: Agent not registered, see https://cron.cat");
+        assert!(!CroncatError::classify(&err).is_source_error());
+    }
+
+    #[test]
+    fn can_classify_abci_errors() {
+        let err = CroncatError::from_abci(
+            "sdk",
+            32,
+            "account sequence mismatch, expected 42, got 41: incorrect account sequence",
+        );
+        assert!(matches!(err, CroncatError::Sequence(_)));
+        assert!(!err.is_fatal());
+        assert!(!err.is_source_error());
+
+        let err = CroncatError::from_abci("sdk", 5, "0ujunox is smaller than 100ujunox");
+        assert!(matches!(err, CroncatError::InsufficientFunds(_)));
+        assert!(!err.is_fatal());
+
+        let err = CroncatError::from_abci(
+            "wasm",
+            5,
+            "execute wasm contract failed: Overflow: Cannot Sub with 1 and 2",
+        );
+        assert!(err.is_task_error());
+
+        let err = CroncatError::from_abci("sdk", 9, "account juno1abc does not exist")
+            .context("Failed to send funds");
+        assert!(matches!(err, CroncatError::AccountNotFound(_)));
+        assert_eq!(
+            err.to_string(),
+            "Failed to send funds: account juno1abc does not exist"
+        );
+    }
+
+    #[test]
+    fn failed_transactions_keep_their_cause() {
+        let cause = CroncatError::from_abci(
+            "wasm",
+            5,
+            "execute wasm contract failed: Overflow: Cannot Sub with 1 and 2",
+        );
+        let err = CroncatError::tx_failed(
+            cause,
+            TxReceipt {
+                hash: "ABC".to_string(),
+                height: 42,
+                ..TxReceipt::default()
+            },
+        )
+        .context("Proxy call failed");
+        assert!(err.is_task_error());
+        assert!(!err.is_source_error());
+        assert!(matches!(err.cause(), CroncatError::Contract { .. }));
+        assert!(err.to_string().starts_with("Proxy call failed: "));
+
+        let err = CroncatError::TxPending {
+            hash: Some("ABC".to_string()),
+            message: "Transaction ABC wasn't included after 20s".to_string(),
+        };
+        assert!(!err.is_source_error());
+        assert!(!err.is_fatal());
+    }
+
+    #[test]
+    fn keeps_typed_errors() {
+        let err: Report = CroncatError::Config("Agent not found: agent".to_string()).into();
+        let classified = CroncatError::classify(&err);
+        assert!(matches!(classified, CroncatError::Config(_)));
+        assert_eq!(classified.to_string(), "Agent not found: agent");
+    }
+}
@@ -9,15 +9,20 @@
 pub use tokio;
 
 // Our modules
+pub mod accounting;
+pub mod alerts;
 pub mod channels;
 pub mod config;
 pub mod errors;
 pub mod logging;
+pub mod metrics;
 pub mod modules;
 pub mod monitor;
+pub mod registry;
 pub mod rpc;
 pub mod store;
 pub mod system;
+pub mod tracker;
 pub mod utils;
 
 // pub use cw_croncat_core::msg::GetConfigResponse;
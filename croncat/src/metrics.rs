@@ -0,0 +1,225 @@
+//!
+//! Prometheus metrics for the agent daemon, served over HTTP at `/metrics`.
+//!
+
+use std::{collections::HashSet, convert::Infallible, net::SocketAddr};
+
+use croncat_sdk_agents::types::AgentStatus;
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server, StatusCode,
+};
+use lazy_static::lazy_static;
+use prometheus::{
+    core::Collector, Encoder, GaugeVec, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use tokio::sync::Mutex;
+use tracing::{error, info};
+
+use crate::{errors::Report, modules::tasks::EvaluationSummary};
+
+/// The path metrics are served at.
+const METRICS_PATH: &str = "/metrics";
+
+/// Proxy call batches from [`crate::modules::tasks::scheduled_tasks_loop`].
+pub const BATCH_KIND_SCHEDULED: &str = "scheduled";
+
+/// Proxy call batches from [`crate::modules::tasks::evented_tasks_loop`].
+pub const BATCH_KIND_EVENTED: &str = "evented";
+
+lazy_static! {
+    static ref REGISTRY: Registry = Registry::new();
+
+    /// Addresses we already serve metrics on, chains can share an exporter.
+    static ref EXPORTERS: Mutex<HashSet<SocketAddr>> = Mutex::new(HashSet::new());
+
+    static ref BLOCKS_SEEN: IntCounterVec = register(
+        IntCounterVec::new(
+            Opts::new("croncat_blocks_seen_total", "Blocks received from the dispatcher"),
+            &["chain_id"],
+        )
+        .unwrap()
+    );
+    static ref PROXY_BATCHES: IntCounterVec = register(
+        IntCounterVec::new(
+            Opts::new("croncat_proxy_batches_total", "Proxy call batches by kind and result"),
+            &["chain_id", "kind", "result"],
+        )
+        .unwrap()
+    );
+    static ref COOLDOWN_TASKS: IntGaugeVec = register(
+        IntGaugeVec::new(
+            Opts::new("croncat_cooldown_tasks", "Evented tasks currently cooling down"),
+            &["chain_id"],
+        )
+        .unwrap()
+    );
+    static ref JAILED_TASKS: IntGaugeVec = register(
+        IntGaugeVec::new(
+            Opts::new("croncat_jailed_tasks", "Evented tasks jailed by this agent"),
+            &["chain_id"],
+        )
+        .unwrap()
+    );
+    static ref AGENT_STATUS: IntGaugeVec = register(
+        IntGaugeVec::new(
+            Opts::new("croncat_agent_status", "Current agent status, 1 for the active status"),
+            &["chain_id", "status"],
+        )
+        .unwrap()
+    );
+    static ref NATIVE_BALANCE: GaugeVec = register(
+        GaugeVec::new(
+            Opts::new("croncat_native_balance", "Native balance of the agent account"),
+            &["chain_id", "denom"],
+        )
+        .unwrap()
+    );
+    static ref BALANCE_THRESHOLD: GaugeVec = register(
+        GaugeVec::new(
+            Opts::new("croncat_balance_threshold", "Configured minimum native balance"),
+            &["chain_id", "denom"],
+        )
+        .unwrap()
+    );
+    static ref QUERY_EVALUATIONS: IntCounterVec = register(
+        IntCounterVec::new(
+            Opts::new("croncat_query_evaluations_total", "Evented task query evaluations by result"),
+            &["chain_id", "result"],
+        )
+        .unwrap()
+    );
+    static ref RPC_SOURCE_HEALTHY: IntGaugeVec = register(
+        IntGaugeVec::new(
+            Opts::new("croncat_rpc_source_healthy", "RPC source health, 0 when marked bad"),
+            &["chain_id", "provider"],
+        )
+        .unwrap()
+    );
+}
+
+/// Register a collector with our registry, and hand it back.
+fn register<C: Collector + Clone + 'static>(collector: C) -> C {
+    REGISTRY
+        .register(Box::new(collector.clone()))
+        .expect("Failed to register metric");
+    collector
+}
+
+/// Count a block seen by the dispatcher.
+pub fn inc_blocks_seen(chain_id: &str) {
+    BLOCKS_SEEN.with_label_values(&[chain_id]).inc();
+}
+
+/// Count proxy call batch transactions, result is one of `sent`, `succeeded` or `failed`.
+pub fn inc_proxy_batch(chain_id: &str, kind: &str, result: &str, count: usize) {
+    PROXY_BATCHES
+        .with_label_values(&[chain_id, kind, result])
+        .inc_by(count as u64);
+}
+
+/// Set the cooldown and jailed task counts.
+pub fn set_task_health(chain_id: &str, cooldown: u64, jailed: u64) {
+    COOLDOWN_TASKS
+        .with_label_values(&[chain_id])
+        .set(cooldown as i64);
+    JAILED_TASKS
+        .with_label_values(&[chain_id])
+        .set(jailed as i64);
+}
+
+/// Count a block's evented task query evaluations by result.
+pub fn inc_query_evaluations(chain_id: &str, summary: &EvaluationSummary) {
+    for (result, count) in [
+        ("ready", summary.ready),
+        ("not_ready", summary.not_ready),
+        ("errored", summary.errored),
+    ] {
+        QUERY_EVALUATIONS
+            .with_label_values(&[chain_id, result])
+            .inc_by(count as u64);
+    }
+}
+
+/// Set the current agent status.
+pub fn set_agent_status(chain_id: &str, status: &AgentStatus) {
+    for other in [
+        AgentStatus::Active,
+        AgentStatus::Pending,
+        AgentStatus::Nominated,
+    ] {
+        AGENT_STATUS
+            .with_label_values(&[chain_id, format!("{other:?}").as_str()])
+            .set((&other == status) as i64);
+    }
+}
+
+/// Set the agent native balance, along with the threshold it's checked against.
+pub fn set_native_balance(chain_id: &str, denom: &str, balance: u128, threshold: u64) {
+    NATIVE_BALANCE
+        .with_label_values(&[chain_id, denom])
+        .set(balance as f64);
+    BALANCE_THRESHOLD
+        .with_label_values(&[chain_id, denom])
+        .set(threshold as f64);
+}
+
+/// Set the health of an RPC source.
+pub fn set_rpc_source_health(chain_id: &str, provider: &str, healthy: bool) {
+    RPC_SOURCE_HEALTHY
+        .with_label_values(&[chain_id, provider])
+        .set(healthy as i64);
+}
+
+///
+/// Serve metrics at the given address, unless another chain already does.
+///
+pub async fn spawn_exporter(chain_id: &str, address: SocketAddr) {
+    if !EXPORTERS.lock().await.insert(address) {
+        return;
+    }
+
+    info!(
+        "[{}] Serving metrics at http://{}{}",
+        chain_id, address, METRICS_PATH
+    );
+
+    tokio::task::spawn(async move {
+        if let Err(err) = serve(address).await {
+            error!("Metrics exporter at {} stopped: {}", address, err);
+            EXPORTERS.lock().await.remove(&address);
+        }
+    });
+}
+
+async fn serve(address: SocketAddr) -> Result<(), Report> {
+    let make_service =
+        make_service_fn(|_conn| async { Ok::<_, Infallible>(service_fn(handle_request)) });
+
+    Server::try_bind(&address)?.serve(make_service).await?;
+
+    Ok(())
+}
+
+async fn handle_request(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    if req.uri().path() != METRICS_PATH {
+        return Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())
+            .unwrap());
+    }
+
+    let encoder = TextEncoder::new();
+    let mut buffer = vec![];
+
+    match encoder.encode(&REGISTRY.gather(), &mut buffer) {
+        Ok(_) => Ok(Response::builder()
+            .header(hyper::header::CONTENT_TYPE, encoder.format_type())
+            .body(Body::from(buffer))
+            .unwrap()),
+        Err(err) => Ok(Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::from(err.to_string()))
+            .unwrap()),
+    }
+}
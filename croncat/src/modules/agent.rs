@@ -1,15 +1,21 @@
 use color_eyre::{eyre::eyre, Report};
 use croncat_sdk_agents::types::AgentStatus;
-use std::process::exit;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::Mutex;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
-use crate::config::ChainConfig;
+use crate::config::{ChainConfig, LowBalancePolicy};
+use crate::errors::{ContractError, CroncatError};
 use crate::{
-    channels::{ShutdownRx, StatusStreamRx},
+    accounting,
+    alerts::{self, AlertEvent},
+    channels::{next_block, ShutdownRx, StatusStreamRx},
+    metrics, monitor,
     rpc::RpcClientService,
-    utils::AtomicIntervalCounter,
+    store::{txs::TxKind, unregistered::LocalUnregisteredStorage},
+    tracker,
+    utils::{drain_join, AtomicIntervalCounter},
 };
 use cosm_orc::orchestrator::{Address, ChainTxResponse, Coin};
 use cosmrs::bip32;
@@ -18,12 +24,72 @@ use croncat_sdk_agents::msg::{
     AgentResponse, AgentTaskResponse, ExecuteMsg as AgentExecuteMsg, QueryMsg as AgentQueryMsg,
 };
 
-use super::manager::Manager;
+use super::{manager::Manager, treasury::Treasury};
+
+/// Where the gas for our transactions comes from.
+#[derive(Debug, PartialEq, Eq)]
+enum GasSource<'a> {
+    /// The account's own balance.
+    Balance(&'a str),
+    /// A fee grant to the account that signs, which is never the authz granter.
+    Grant { granter: &'a str, grantee: &'a str },
+}
+
+/// Where `gas_payer`, the signing key's account, gets its gas from.
+fn gas_source<'a>(gas_payer: &'a str, fee_granter: Option<&'a str>) -> GasSource<'a> {
+    match fee_granter {
+        Some(granter) => GasSource::Grant {
+            granter,
+            grantee: gas_payer,
+        },
+        None => GasSource::Balance(gas_payer),
+    }
+}
+
+/// Unregistrations to try for a low balance, before staying paused instead.
+const MAX_UNREGISTER_ATTEMPTS: u32 = 3;
+
+///
+/// Failed unregistrations for a low balance. Each attempt pays a fee from a balance
+/// that's already low, so they're spaced out and given up on after a few.
+///
+#[derive(Debug, Default)]
+struct UnregisterRetry {
+    failures: u32,
+    // status checks to skip before the next attempt
+    skip: u32,
+}
+
+impl UnregisterRetry {
+    /// Whether to try unregistering on this status check.
+    fn ready(&mut self) -> bool {
+        if self.gave_up() {
+            return false;
+        }
+        if self.skip > 0 {
+            self.skip -= 1;
+            return false;
+        }
+        true
+    }
+
+    /// Count a failed attempt, doubling the wait for the next. True for the first one.
+    fn failed(&mut self) -> bool {
+        self.failures += 1;
+        self.skip = 1 << self.failures;
+        self.failures == 1
+    }
+
+    fn gave_up(&self) -> bool {
+        self.failures >= MAX_UNREGISTER_ATTEMPTS
+    }
+}
 
 pub struct Agent {
     pub client: RpcClientService,
     pub contract_addr: Address,
     pub account_id: String,
+    pub treasury: Option<Treasury>,
 }
 
 impl Agent {
@@ -32,16 +98,25 @@ impl Agent {
         contract_addr: Address,
         key: bip32::XPrv,
         client: RpcClientService,
+        treasury: Option<Treasury>,
     ) -> Result<Self, Report> {
-        let signing_key: SigningKey = key.into();
-        let account_id = signing_key
-            .public_key()
-            .account_id(&cfg.info.bech32_prefix)?;
+        // With authz the agent on chain is the granter, our key only signs for it
+        let account_id = match cfg.authz_granter {
+            Some(granter) => granter,
+            None => {
+                let signing_key: SigningKey = key.into();
+                signing_key
+                    .public_key()
+                    .account_id(&cfg.info.bech32_prefix)?
+                    .to_string()
+            }
+        };
 
         Ok(Self {
             client,
             contract_addr,
-            account_id: account_id.to_string(),
+            account_id,
+            treasury,
         })
     }
 
@@ -53,7 +128,8 @@ impl Agent {
         &self,
         payable_account_id: &Option<String>,
     ) -> Result<ChainTxResponse, Report> {
-        self.client
+        let res = self
+            .client
             .execute(|signer| {
                 let payable_account_id = payable_account_id.clone();
                 let contract_addr = self.contract_addr.clone();
@@ -69,11 +145,13 @@ impl Agent {
                         .await
                 }
             })
-            .await
+            .await;
+        tracker::track(self.client.chain_id(), TxKind::Register, vec![], res).await
     }
 
     pub async fn check_in(&self) -> Result<ChainTxResponse, Report> {
-        self.client
+        let res = self
+            .client
             .execute(|signer| {
                 let contract_addr = self.contract_addr.clone();
                 async move {
@@ -82,11 +160,13 @@ impl Agent {
                         .await
                 }
             })
-            .await
+            .await;
+        tracker::track(self.client.chain_id(), TxKind::CheckIn, vec![], res).await
     }
 
     pub async fn unregister(&self) -> Result<ChainTxResponse, Report> {
-        self.client
+        let res = self
+            .client
             .execute(|signer| {
                 let contract_addr = self.contract_addr.clone();
                 async move {
@@ -98,11 +178,16 @@ impl Agent {
                         .await
                 }
             })
-            .await
+            .await;
+        let res = tracker::track(self.client.chain_id(), TxKind::Unregister, vec![], res).await?;
+        // Unregistering pays out any rewards left in the manager
+        accounting::record_payout(self.client.chain_id(), &res).await;
+        Ok(res)
     }
 
     pub async fn update(&self, payable_account_id: String) -> Result<ChainTxResponse, Report> {
-        self.client
+        let res = self
+            .client
             .execute(|signer| {
                 let payable_account_id = payable_account_id.clone();
                 let contract_addr = self.contract_addr.clone();
@@ -118,7 +203,8 @@ impl Agent {
                         .await
                 }
             })
-            .await
+            .await;
+        tracker::track(self.client.chain_id(), TxKind::Update, vec![], res).await
     }
 
     pub async fn get(&self, account_id: &str) -> Result<Option<AgentResponse>, Report> {
@@ -163,12 +249,13 @@ impl Agent {
             })
             .await?;
 
-        if agent_info.is_none() {
-            Err(eyre!("Agent not registered"))
-        } else if let Some(agent) = agent_info.unwrap().agent {
-            Ok(agent.status)
-        } else {
-            Err(eyre!("Agent not registered"))
+        match agent_info.and_then(|info| info.agent) {
+            Some(agent) => Ok(agent.status),
+            None => Err(CroncatError::contract(
+                ContractError::AgentNotRegistered,
+                "Agent not registered",
+            )
+            .into()),
         }
     }
 
@@ -201,6 +288,39 @@ impl Agent {
         self.client.query_balance(account_id.as_str()).await
     }
 
+    /// The account paying gas, our signing key rather than the authz granter.
+    pub fn gas_payer(&self) -> String {
+        self.client.account_id()
+    }
+
+    ///
+    /// What the agent has to pay gas with. That's the signing key's balance, or with
+    /// a fee granter what's left of the grant, capped by the granter's balance.
+    ///
+    pub async fn query_gas_balance(&self, fee_granter: Option<&String>) -> Result<Coin, Report> {
+        let gas_payer = self.gas_payer();
+        let (granter, grantee) = match gas_source(&gas_payer, fee_granter.map(String::as_str)) {
+            GasSource::Balance(account) => {
+                return self.query_native_balance(Some(account.to_string())).await
+            }
+            GasSource::Grant { granter, grantee } => (granter, grantee),
+        };
+
+        let granter_balance = self.query_native_balance(Some(granter.to_string())).await?;
+        let allowance = self
+            .client
+            .query_fee_allowance(granter, grantee, &granter_balance.denom.to_string())
+            .await?;
+
+        Ok(Coin {
+            amount: match allowance {
+                Some(allowance) => allowance.min(granter_balance.amount),
+                None => granter_balance.amount,
+            },
+            denom: granter_balance.denom,
+        })
+    }
+
     pub async fn send_funds(
         &self,
         account_id: &str,
@@ -208,16 +328,13 @@ impl Agent {
         amount: u128,
         denom: &str,
     ) -> Result<ChainTxResponse, Report> {
-        self.client
-            .send_funds(account_id, to, denom, amount)
+        let res = self.client.send_funds(account_id, to, denom, amount).await;
+        tracker::track(self.client.chain_id(), TxKind::SendFunds, vec![], res)
             .await
             .map_err(|err| {
-                eyre!(
-                    "Failed to send funds from {} to {}: {}",
-                    account_id,
-                    to,
-                    err
-                )
+                CroncatError::classify(&err)
+                    .context(format!("Failed to send funds from {account_id} to {to}"))
+                    .into()
             })
     }
 }
@@ -228,7 +345,7 @@ impl Agent {
 ///
 pub async fn check_status_loop(
     mut block_stream_rx: StatusStreamRx,
-    mut shutdown_rx: ShutdownRx,
+    shutdown_rx: ShutdownRx,
     block_status: Arc<Mutex<AgentStatus>>,
     chain_id: Arc<String>,
     chain_config: ChainConfig,
@@ -236,8 +353,16 @@ pub async fn check_status_loop(
     manager_client: Arc<Manager>,
 ) -> Result<(), Report> {
     let block_counter = AtomicIntervalCounter::new(10);
+    let drain_timeout = Duration::from_secs_f64(chain_config.drain_timeout_seconds);
+    let mut loop_shutdown_rx = shutdown_rx.resubscribe();
     let task_handle: tokio::task::JoinHandle<Result<(), Report>> = tokio::task::spawn(async move {
-        while let Ok(block) = block_stream_rx.recv().await {
+        // Payable account to register again with, once the low balance policy unregistered us.
+        // It's kept on disk, so a restart keeps waiting for funds.
+        let mut unregistered_store = LocalUnregisteredStorage::new(&chain_id)?;
+        let mut unregistered = unregistered_store.get(agent_client.account_id()).cloned();
+        let mut unregister_retry = UnregisterRetry::default();
+
+        while let Some(block) = next_block(&mut block_stream_rx, &mut loop_shutdown_rx).await {
             block_counter.tick();
             if block_counter.is_at_interval() {
                 info!(
@@ -246,17 +371,68 @@ pub async fn check_status_loop(
                 );
 
                 let account_id = agent_client.account_id();
-                let agent = agent_client.get(account_id.as_str()).await?;
 
-                let mut locked_status = agent
+                // Unregistered for a low balance, only watch for funds until we can register again
+                if let Some(payable_account_id) = &unregistered {
+                    let threshold = chain_config.threshold.unwrap_or_default();
+                    let balance = agent_client
+                        .query_gas_balance(chain_config.fee_granter.as_ref())
+                        .await?
+                        .amount;
+                    if balance < threshold as u128 {
+                        continue;
+                    }
+
+                    info!("[{}] Agent funded again, registering", chain_id);
+                    match agent_client
+                        .register(&Some(payable_account_id.clone()))
+                        .await
+                    {
+                        Ok(_) => {}
+                        // Registered by hand in the meantime
+                        Err(err)
+                            if CroncatError::classify(&err).contract_error()
+                                == Some(&ContractError::AgentAlreadyRegistered) => {}
+                        Err(err) => {
+                            // Try again on the next interval
+                            error!("[{}] Failed to register agent again: {}", chain_id, err);
+                            alerts::emit(
+                                &chain_id,
+                                AlertEvent::RegisterFailed {
+                                    reason: err.to_string(),
+                                },
+                            )
+                            .await;
+                            continue;
+                        }
+                    }
+
+                    unregistered = None;
+                    if let Err(err) = unregistered_store.remove(account_id) {
+                        error!(
+                            "[{}] Failed to save the agent registration: {}",
+                            chain_id, err
+                        );
+                    }
+                    manager_client.resume();
+                    alerts::emit(&chain_id, AlertEvent::AgentResumed).await;
+                    continue;
+                }
+
+                let agent = agent_client
+                    .get(account_id.as_str())
+                    .await?
                     .ok_or(eyre!("Agent unregistered during the loop"))?
                     .agent
-                    .ok_or(eyre!("Agent unregistered during the loop"))?
-                    .status;
+                    .ok_or(eyre!("Agent unregistered during the loop"))?;
+                accounting::record_balance(&chain_id, u128::from(agent.balance)).await;
+
+                let mut locked_status = agent.status;
 
                 info!("[{}] Agent status: {:?}", chain_id, locked_status);
 
                 if locked_status == AgentStatus::Nominated {
+                    alerts::emit(&chain_id, AlertEvent::AgentNominated).await;
                     info!(
                         "Checking in agent: {}",
                         agent_client.check_in().await.map(|result| result.res.log)?
@@ -273,44 +449,173 @@ pub async fn check_status_loop(
                     info!("Agent status: {:?}", locked_status);
                 }
 
+                metrics::set_agent_status(&chain_id, &locked_status);
+                monitor::record_agent_status(&chain_id, &locked_status).await;
                 *block_status.lock().await = locked_status;
 
                 if let Some(threshold) = chain_config.threshold {
                     // Check the agent's balance to make sure it's not falling below a threshold
                     let account_id = agent_client.account_id();
                     let agent_balance = agent_client
-                        .query_native_balance(Some(account_id.clone()))
+                        .query_gas_balance(chain_config.fee_granter.as_ref())
                         .await?;
-                    let agent_native_balance = agent_balance.amount;
+                    let mut agent_native_balance = agent_balance.amount;
                     let denom = agent_balance.denom;
+                    metrics::set_native_balance(
+                        &chain_id,
+                        &denom.to_string(),
+                        agent_native_balance,
+                        threshold,
+                    );
+                    monitor::record_balance(&chain_id, agent_native_balance, &denom.to_string())
+                        .await;
 
                     // If agent balance is too low and the agent has some native coins in the manager contract
-                    // call withdraw_reward
-                    // If manager balance is zero, exit
+                    // call withdraw_reward, otherwise top up from the treasury if there is one
+                    // If it's still too low, apply the low balance policy
                     if agent_native_balance < threshold as u128 {
-                        let agent = agent_client.get(account_id.as_str()).await?;
-                        let reward_balance = agent
+                        alerts::emit(
+                            &chain_id,
+                            AlertEvent::LowBalance {
+                                balance: agent_native_balance.to_string(),
+                                denom: denom.to_string(),
+                                threshold,
+                            },
+                        )
+                        .await;
+                        let agent = agent_client
+                            .get(account_id.as_str())
+                            .await?
                             .ok_or(eyre!("Agent unregistered during the loop"))?
                             .agent
-                            .unwrap()
-                            .balance;
-
-                        if !reward_balance.is_zero() {
-                            info!("Automatically withdrawing agent reward");
-                            let result = manager_client.withdraw_reward().await?;
-                            let log = result.res.log;
-                            info!("Log: {log}");
-
-                            let native_balance_after_withdraw = agent_client
-                                .query_native_balance(Some(account_id.clone()))
-                                .await?
-                                .amount;
-                            if native_balance_after_withdraw < threshold as u128 {
-                                error!("Not enough balance to continue, the agent in required to have {} {}, current balance: {} {}", threshold, denom, native_balance_after_withdraw, denom);
-                                error!("Stopping the agent");
-                                exit(1);
+                            .ok_or(eyre!("Agent unregistered during the loop"))?;
+
+                        // Rewards and top-ups land in the agent's own balance, which doesn't pay gas
+                        // under a fee grant, and rewards go to the granter's payable account under authz
+                        if chain_config.fee_granter.is_none()
+                            && chain_config.authz_granter.is_none()
+                        {
+                            if !agent.balance.is_zero() {
+                                info!("Automatically withdrawing agent reward");
+                                let result = manager_client.withdraw_reward().await?;
+                                let log = result.res.log;
+                                info!("Log: {log}");
+
+                                agent_native_balance =
+                                    agent_client.query_gas_balance(None).await?.amount;
+                            } else if let Some(treasury) = &agent_client.treasury {
+                                // No rewards to withdraw, pull a top-up from the treasury instead
+                                match treasury
+                                    .top_up(&agent_client.gas_payer(), &denom.to_string())
+                                    .await
+                                {
+                                    Ok(Some(_)) => {
+                                        agent_native_balance =
+                                            agent_client.query_gas_balance(None).await?.amount;
+                                    }
+                                    Ok(None) => {
+                                        warn!(
+                                            "[{}] Treasury daily cap reached, not topping up",
+                                            chain_id
+                                        )
+                                    }
+                                    Err(err) => {
+                                        error!(
+                                            "[{}] Failed to top up from the treasury: {}",
+                                            chain_id, err
+                                        )
+                                    }
+                                }
+                            }
+                        }
+
+                        if agent_native_balance < threshold as u128 {
+                            let reason = format!(
+                                "balance {agent_native_balance} {denom} is below the threshold of {threshold} {denom}"
+                            );
+                            error!("Not enough balance to continue, the agent in required to have {} {}, current balance: {} {}", threshold, denom, agent_native_balance, denom);
+
+                            match chain_config.low_balance_policy {
+                                LowBalancePolicy::Pause => {
+                                    if !manager_client.is_paused() {
+                                        warn!("[{}] Pausing proxy calls until funded", chain_id);
+                                        manager_client.pause();
+                                        alerts::emit(&chain_id, AlertEvent::AgentPaused { reason })
+                                            .await;
+                                    }
+                                }
+                                LowBalancePolicy::Unregister => {
+                                    manager_client.pause();
+                                    if !unregister_retry.ready() {
+                                        continue;
+                                    }
+                                    match agent_client.unregister().await {
+                                        Ok(_) => {
+                                            warn!("[{}] Unregistered agent until funded", chain_id);
+                                            unregister_retry = UnregisterRetry::default();
+                                            let payable_account_id =
+                                                agent.payable_account_id.to_string();
+                                            if let Err(err) = unregistered_store
+                                                .insert(account_id, &payable_account_id)
+                                            {
+                                                error!(
+                                                    "[{}] Failed to save the unregistered agent: {}",
+                                                    chain_id, err
+                                                );
+                                            }
+                                            unregistered = Some(payable_account_id);
+                                            alerts::emit(
+                                                &chain_id,
+                                                AlertEvent::AgentUnregistered { reason },
+                                            )
+                                            .await;
+                                        }
+                                        Err(err) => {
+                                            // Every attempt pays a fee, so back off and alert once
+                                            let first = unregister_retry.failed();
+                                            if unregister_retry.gave_up() {
+                                                error!(
+                                                    "[{}] Failed to unregister, staying paused until funded: {}",
+                                                    chain_id, err
+                                                );
+                                            } else {
+                                                error!(
+                                                    "[{}] Failed to unregister, pausing and trying again later: {}",
+                                                    chain_id, err
+                                                );
+                                            }
+                                            if first {
+                                                alerts::emit(
+                                                    &chain_id,
+                                                    AlertEvent::AgentPaused {
+                                                        reason: format!(
+                                                            "{reason}, and unregistering failed: {err}"
+                                                        ),
+                                                    },
+                                                )
+                                                .await;
+                                            }
+                                        }
+                                    }
+                                }
+                                LowBalancePolicy::Exit => {
+                                    error!("Stopping the agent");
+                                    alerts::emit_and_wait(
+                                        &chain_id,
+                                        AlertEvent::AgentStopped {
+                                            reason: reason.clone(),
+                                        },
+                                    )
+                                    .await;
+                                    return Err(CroncatError::Stopped(reason).into());
+                                }
                             }
                         }
+                    } else if manager_client.is_paused() {
+                        info!("[{}] Agent funded again, resuming proxy calls", chain_id);
+                        unregister_retry = UnregisterRetry::default();
+                        manager_client.resume();
+                        alerts::emit(&chain_id, AlertEvent::AgentResumed).await;
                     }
                 }
             }
@@ -318,10 +623,50 @@ pub async fn check_status_loop(
         Ok(())
     });
 
-    tokio::select! {
-        Ok(task) = task_handle => {task?}
-        _ = shutdown_rx.recv() => {}
+    drain_join(task_handle, shutdown_rx, drain_timeout).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fee_grants_are_to_the_signing_key() {
+        // With authz the agent's account is the granter's, not the key's that signs
+        let account_id = "juno1authzgranter";
+        let gas_payer = "juno1signer";
+        let fee_granter = "juno1feegranter";
+
+        let source = gas_source(gas_payer, Some(fee_granter));
+        assert_eq!(
+            source,
+            GasSource::Grant {
+                granter: fee_granter,
+                grantee: gas_payer,
+            }
+        );
+        assert_ne!(
+            source,
+            GasSource::Grant {
+                granter: fee_granter,
+                grantee: account_id,
+            }
+        );
+        assert_eq!(gas_source(gas_payer, None), GasSource::Balance(gas_payer));
     }
 
-    Ok(())
+    #[test]
+    fn unregister_retries_back_off_then_give_up() {
+        let mut retry = UnregisterRetry::default();
+        let mut attempts = vec![];
+        for check in 0..20 {
+            if retry.ready() {
+                attempts.push(check);
+                retry.failed();
+            }
+        }
+
+        assert_eq!(attempts, vec![0, 3, 8]);
+        assert!(retry.gave_up());
+    }
 }
@@ -1,6 +1,6 @@
-use crate::channels::{ShutdownRx, StatusStreamRx};
+use crate::channels::{next_block, ShutdownRx, StatusStreamRx};
 use crate::config::ChainConfig;
-use crate::utils::AtomicIntervalCounter;
+use crate::utils::{drain_join, AtomicIntervalCounter};
 use crate::{rpc::RpcClientService, store::factory::LocalCacheStorage};
 use color_eyre::{eyre::eyre, Report};
 use cosm_orc::orchestrator::Address;
@@ -10,6 +10,7 @@ use croncat_sdk_factory::msg::{
 use std::collections::HashMap;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::Mutex;
 use tracing::info;
 
@@ -59,7 +60,7 @@ impl Factory {
             client,
             contract_addr: Address::from_str(&cfg.factory)?,
             chain_id: chain_id.clone(),
-            store: LocalCacheStorage::new(Some(chain_id)),
+            store: LocalCacheStorage::new(Some(chain_id))?,
         })
     }
 
@@ -237,14 +238,16 @@ impl Factory {
 ///
 pub async fn refresh_factory_loop(
     mut block_stream_rx: StatusStreamRx,
-    mut shutdown_rx: ShutdownRx,
+    shutdown_rx: ShutdownRx,
     chain_id: Arc<String>,
     factory_client: Arc<Mutex<Factory>>,
+    drain_timeout: Duration,
 ) -> Result<(), Report> {
     // TODO: Figure out best interval here!
     let block_counter = AtomicIntervalCounter::new(200);
+    let mut loop_shutdown_rx = shutdown_rx.resubscribe();
     let task_handle: tokio::task::JoinHandle<Result<(), Report>> = tokio::task::spawn(async move {
-        while let Ok(_block) = block_stream_rx.recv().await {
+        while let Some(_block) = next_block(&mut block_stream_rx, &mut loop_shutdown_rx).await {
             block_counter.tick();
             if block_counter.is_at_interval() && factory_client.lock().await.load().await? {
                 info!("[{}] Factory Cache Reloaded", chain_id);
@@ -253,10 +256,5 @@ pub async fn refresh_factory_loop(
         Ok(())
     });
 
-    tokio::select! {
-        Ok(task) = task_handle => {task?}
-        _ = shutdown_rx.recv() => {}
-    }
-
-    Ok(())
+    drain_join(task_handle, shutdown_rx, drain_timeout).await
 }
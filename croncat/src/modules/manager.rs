@@ -1,23 +1,221 @@
-use crate::{errors::Report, rpc::RpcClientService};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering::SeqCst};
+
+use crate::{
+    accounting,
+    config::ChainConfig,
+    errors::{CroncatError, Report},
+    rpc::RpcClientService,
+    store::txs::TxKind,
+    tracker,
+};
 use cosm_orc::orchestrator::{Address, ChainTxResponse};
 use cosm_tome::modules::cosmwasm::model::ExecRequest;
 use croncat_sdk_manager::msg::ManagerExecuteMsg;
+use futures_util::Future;
+
+///
+/// Outcome of a proxy batch, bisected down to the failing calls when the whole batch fails.
+///
+pub struct BatchOutcome<T, R = ChainTxResponse> {
+    /// Transactions broadcast, counting every part of a bisected batch.
+    pub sent: usize,
+    /// Responses of every batch, or part of it, that went through.
+    pub responses: Vec<R>,
+    /// Calls that failed with a task error on their own.
+    pub failed: Vec<T>,
+    /// The first error that wasn't caused by a task, bisection stops there.
+    pub error: Option<Report>,
+}
+
+impl<T, R> Default for BatchOutcome<T, R> {
+    fn default() -> Self {
+        Self {
+            sent: 0,
+            responses: vec![],
+            failed: vec![],
+            error: None,
+        }
+    }
+}
+
+impl<T, R> BatchOutcome<T, R> {
+    /// Fold another outcome into this one.
+    fn extend(&mut self, other: Self) {
+        self.sent += other.sent;
+        self.responses.extend(other.responses);
+        self.failed.extend(other.failed);
+        if self.error.is_none() {
+            self.error = other.error;
+        }
+    }
+}
+
+///
+/// Split `count` proxy calls into batch sizes that fit within `max_batch_gas`,
+/// and `max_batch_size` if set. Every batch holds at least one call.
+///
+pub fn plan_batches(
+    count: usize,
+    gas_per_task: u64,
+    max_batch_gas: u64,
+    max_batch_size: Option<usize>,
+) -> Vec<usize> {
+    let by_gas = (max_batch_gas / gas_per_task.max(1)).max(1) as usize;
+    let size = max_batch_size.map_or(by_gas, |max| by_gas.min(max.max(1)));
+
+    (0..count)
+        .step_by(size)
+        .map(|start| size.min(count - start))
+        .collect()
+}
+
+///
+/// Send a batch, and when it fails with a task error, simulate each half until
+/// the failing calls are isolated. Only halves that pass simulation are broadcast,
+/// so healthy calls still go through without paying for the failing ones.
+///
+pub async fn bisect_batch<T, R, S, SFut, F, Fut>(
+    items: Vec<T>,
+    simulate: S,
+    send: F,
+) -> BatchOutcome<T, R>
+where
+    T: Clone,
+    S: Fn(Vec<T>) -> SFut,
+    SFut: Future<Output = Result<u64, Report>>,
+    F: Fn(Vec<T>) -> Fut,
+    Fut: Future<Output = Result<R, Report>>,
+{
+    let mut outcome = BatchOutcome::default();
+    if items.is_empty() {
+        return outcome;
+    }
+
+    let mut pending = vec![];
+
+    outcome.sent += 1;
+    match send(items.clone()).await {
+        Ok(res) => {
+            outcome.responses.push(res);
+            return outcome;
+        }
+        Err(err) if CroncatError::classify(&err).is_task_error() => {
+            split_batch(items, &mut pending, &mut outcome.failed)
+        }
+        Err(err) => {
+            outcome.error = Some(err);
+            return outcome;
+        }
+    }
+
+    while let Some(batch) = pending.pop() {
+        let res = match simulate(batch.clone()).await {
+            Ok(_) => {
+                outcome.sent += 1;
+                send(batch.clone()).await
+            }
+            Err(err) => Err(err),
+        };
+        match res {
+            Ok(res) => outcome.responses.push(res),
+            // A half that passed simulation can still fail in the block, keep splitting it
+            Err(err) if CroncatError::classify(&err).is_task_error() => {
+                split_batch(batch, &mut pending, &mut outcome.failed)
+            }
+            Err(err) => {
+                outcome.error = Some(err);
+                break;
+            }
+        }
+    }
+
+    outcome
+}
+
+/// Queue both halves of a failing batch, or fail the call if it's on its own.
+fn split_batch<T>(mut batch: Vec<T>, pending: &mut Vec<Vec<T>>, failed: &mut Vec<T>) {
+    if batch.len() == 1 {
+        failed.extend(batch);
+        return;
+    }
+    // Push the second half first, so calls keep their order
+    let second = batch.split_off(batch.len() / 2);
+    pending.push(second);
+    pending.push(batch);
+}
+
+///
+/// Send a batch once. A task error fails every call in it, for batches
+/// where the contract picks the tasks and there's nothing to isolate.
+///
+pub async fn send_batch<T, R, F, Fut>(items: Vec<T>, send: F) -> BatchOutcome<T, R>
+where
+    T: Clone,
+    F: Fn(Vec<T>) -> Fut,
+    Fut: Future<Output = Result<R, Report>>,
+{
+    let mut outcome = BatchOutcome::default();
+    if items.is_empty() {
+        return outcome;
+    }
+
+    outcome.sent += 1;
+    match send(items.clone()).await {
+        Ok(res) => outcome.responses.push(res),
+        Err(err) if CroncatError::classify(&err).is_task_error() => outcome.failed = items,
+        Err(err) => outcome.error = Some(err),
+    }
+
+    outcome
+}
+
+/// Gas we assume a single proxy call takes, until we've seen a batch go through.
+const DEFAULT_GAS_PER_TASK: u64 = 300_000;
 
 pub struct Manager {
     pub client: RpcClientService,
     pub contract_addr: Address,
+    max_batch_gas: u64,
+    max_batch_size: Option<usize>,
+    // running estimate, from the gas used by the batches we sent
+    gas_per_task: AtomicU64,
+    // set by the low balance policy, no proxy calls are sent while paused
+    paused: AtomicBool,
 }
 
 impl Manager {
-    pub async fn new(contract_addr: Address, client: RpcClientService) -> Result<Self, Report> {
+    pub async fn new(
+        cfg: ChainConfig,
+        contract_addr: Address,
+        client: RpcClientService,
+    ) -> Result<Self, Report> {
         Ok(Self {
             client,
             contract_addr,
+            max_batch_gas: cfg.max_batch_gas,
+            max_batch_size: cfg.max_batch_size,
+            gas_per_task: AtomicU64::new(DEFAULT_GAS_PER_TASK),
+            paused: AtomicBool::new(false),
         })
     }
 
+    /// Stop sending proxy calls until [`Manager::resume`] is called.
+    pub fn pause(&self) {
+        self.paused.store(true, SeqCst);
+    }
+
+    /// Start sending proxy calls again.
+    pub fn resume(&self) {
+        self.paused.store(false, SeqCst);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(SeqCst)
+    }
+
     pub async fn proxy_call(&self, task_hash: Option<String>) -> Result<ChainTxResponse, Report> {
-        self.client
+        let res = self
+            .client
             .execute(|signer| {
                 let task_hash = task_hash.clone();
                 let contract_addr = self.contract_addr.clone();
@@ -30,50 +228,133 @@ impl Manager {
                         .await
                 }
             })
-            .await
+            .await;
+        tracker::track(
+            self.client.chain_id(),
+            TxKind::ProxyCall,
+            task_hash.into_iter().collect(),
+            res,
+        )
+        .await
     }
 
-    // Generates batch of proxy_calls for executing a known batch without evented tasks
-    pub async fn proxy_call_batch(&self, count: usize) -> Result<ChainTxResponse, Report> {
-        self.client
-            .execute(|signer| {
-                let mut proxy_calls: Vec<Option<String>> = Vec::with_capacity(count);
-                for _ in 0..count {
-                    proxy_calls.push(None);
-                }
-                let reqs = vec![ExecRequest {
-                    address: self.contract_addr.clone(),
-                    msg: ManagerExecuteMsg::ProxyBatch(proxy_calls),
-                    funds: vec![],
-                }];
-                async move { signer.execute_batch(reqs).await }
-            })
-            .await
+    // Generates batch of proxy_calls for executing a known batch without evented tasks,
+    // the contract picks which tasks run so failing batches aren't bisected
+    pub async fn proxy_call_batch(&self, count: usize) -> BatchOutcome<Option<String>> {
+        let mut outcome = BatchOutcome::default();
+        for size in self.plan(count) {
+            outcome.extend(
+                send_batch(vec![None; size], |proxy_calls| {
+                    self.proxy_batch(proxy_calls, false)
+                })
+                .await,
+            );
+            if outcome.error.is_some() {
+                break;
+            }
+        }
+
+        outcome
     }
 
-    // Generates batch of proxy_calls for executing a known batch without evented tasks
+    ///
+    /// Generates batch of proxy_calls for executing a known batch of evented tasks,
+    /// a failing batch is bisected by simulation down to the failing tasks.
+    /// Stops at the first non-task error.
+    ///
     pub async fn proxy_call_evented_batch(
         &self,
-        tash_hashes: Vec<String>,
+        mut task_hashes: Vec<String>,
+    ) -> BatchOutcome<String> {
+        let mut outcome = BatchOutcome::default();
+        for size in self.plan(task_hashes.len()) {
+            let rest = task_hashes.split_off(size);
+            outcome.extend(
+                bisect_batch(
+                    task_hashes,
+                    |task_hashes| {
+                        self.simulate_proxy_batch(task_hashes.into_iter().map(Some).collect())
+                    },
+                    |task_hashes| {
+                        self.proxy_batch(task_hashes.into_iter().map(Some).collect(), true)
+                    },
+                )
+                .await,
+            );
+            if outcome.error.is_some() {
+                break;
+            }
+            task_hashes = rest;
+        }
+
+        outcome
+    }
+
+    /// Batch sizes for `count` proxy calls, within the gas limits.
+    fn plan(&self, count: usize) -> Vec<usize> {
+        plan_batches(
+            count,
+            self.gas_per_task.load(SeqCst),
+            self.max_batch_gas,
+            self.max_batch_size,
+        )
+    }
+
+    async fn proxy_batch(
+        &self,
+        proxy_calls: Vec<Option<String>>,
+        evented: bool,
     ) -> Result<ChainTxResponse, Report> {
+        let count = proxy_calls.len() as u64;
+        let res = self
+            .client
+            .execute(|signer| {
+                let reqs = self.proxy_batch_request(&proxy_calls);
+                async move { signer.execute_batch(reqs, evented).await }
+            })
+            .await;
+        let kind = if evented {
+            TxKind::EventedBatch
+        } else {
+            TxKind::ScheduledBatch
+        };
+        let task_hashes = proxy_calls.into_iter().flatten().collect();
+        let res = tracker::track(self.client.chain_id(), kind, task_hashes, res).await?;
+
+        // Smooth the estimate, so a single cheap or expensive batch doesn't swing it
+        if count > 0 && res.gas_used > 0 {
+            let observed = res.gas_used / count;
+            let estimate = self.gas_per_task.load(SeqCst);
+            self.gas_per_task.store(estimate / 2 + observed / 2, SeqCst);
+        }
+
+        Ok(res)
+    }
+
+    /// Simulate a proxy batch, to find out whether it would fail without paying for it.
+    async fn simulate_proxy_batch(&self, proxy_calls: Vec<Option<String>>) -> Result<u64, Report> {
         self.client
             .execute(|signer| {
-                let mut proxy_calls: Vec<Option<String>> = Vec::with_capacity(tash_hashes.len());
-                for task_hash in tash_hashes.iter() {
-                    proxy_calls.push(Some(task_hash.to_owned()));
-                }
-                let reqs = vec![ExecRequest {
-                    address: self.contract_addr.clone(),
-                    msg: ManagerExecuteMsg::ProxyBatch(proxy_calls),
-                    funds: vec![],
-                }];
-                async move { signer.execute_batch(reqs).await }
+                let reqs = self.proxy_batch_request(&proxy_calls);
+                async move { signer.simulate_batch(&reqs).await }
             })
             .await
     }
 
+    fn proxy_batch_request(
+        &self,
+        proxy_calls: &[Option<String>],
+    ) -> Vec<ExecRequest<ManagerExecuteMsg>> {
+        vec![ExecRequest {
+            address: self.contract_addr.clone(),
+            msg: ManagerExecuteMsg::ProxyBatch(proxy_calls.to_vec()),
+            funds: vec![],
+        }]
+    }
+
     pub async fn withdraw_reward(&self) -> Result<ChainTxResponse, Report> {
-        self.client
+        let res = self
+            .client
             .execute(|signer| {
                 let contract_addr = self.contract_addr.clone();
                 async move {
@@ -85,6 +366,97 @@ impl Manager {
                         .await
                 }
             })
-            .await
+            .await;
+        let res =
+            tracker::track(self.client.chain_id(), TxKind::WithdrawReward, vec![], res).await?;
+        accounting::record_payout(self.client.chain_id(), &res).await;
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::eyre;
+    use std::sync::Mutex;
+
+    #[tokio::test]
+    async fn bisect_isolates_failing_calls() {
+        let fails = |batch: &Vec<u32>| batch.contains(&3) || batch.contains(&6);
+        let simulated = Mutex::new(vec![]);
+        let sent = Mutex::new(vec![]);
+        let outcome = bisect_batch(
+            (1..=8).collect(),
+            |batch: Vec<u32>| {
+                simulated.lock().unwrap().push(batch.clone());
+                async move {
+                    if fails(&batch) {
+                        Err(eyre!("execute wasm contract failed: Overflow"))
+                    } else {
+                        Ok(100)
+                    }
+                }
+            },
+            |batch: Vec<u32>| {
+                sent.lock().unwrap().push(batch.clone());
+                async move {
+                    if fails(&batch) {
+                        Err(eyre!("execute wasm contract failed: Overflow"))
+                    } else {
+                        Ok(batch)
+                    }
+                }
+            },
+        )
+        .await;
+
+        assert_eq!(outcome.failed, vec![3, 6]);
+        assert_eq!(
+            outcome.responses,
+            vec![vec![1, 2], vec![4], vec![5], vec![7, 8]]
+        );
+        assert!(outcome.error.is_none());
+        // Only the whole batch and the halves that passed simulation paid a fee
+        assert_eq!(simulated.lock().unwrap().len(), 10);
+        assert_eq!(sent.lock().unwrap().len(), 5);
+        assert_eq!(outcome.sent, 5);
+    }
+
+    #[tokio::test]
+    async fn send_batch_fails_the_whole_batch() {
+        let outcome = send_batch(vec![None::<String>; 4], |_| async {
+            Err::<(), _>(eyre!("execute wasm contract failed: Overflow"))
+        })
+        .await;
+
+        assert_eq!(outcome.sent, 1);
+        assert_eq!(outcome.failed.len(), 4);
+        assert!(outcome.error.is_none());
+    }
+
+    #[test]
+    fn plans_batches_within_limits() {
+        assert_eq!(
+            plan_batches(10, 1_000_000, 3_000_000, None),
+            vec![3, 3, 3, 1]
+        );
+        assert_eq!(plan_batches(10, 1_000_000, 3_000_000, Some(2)), vec![2; 5]);
+        assert_eq!(plan_batches(2, 5_000_000, 3_000_000, None), vec![1, 1]);
+        assert_eq!(plan_batches(4, 0, 3_000_000, Some(0)), vec![1; 4]);
+        assert!(plan_batches(0, 1_000_000, 3_000_000, None).is_empty());
+    }
+
+    #[tokio::test]
+    async fn bisect_stops_on_other_errors() {
+        let outcome = bisect_batch(
+            vec![1, 2, 3],
+            |_: Vec<u32>| async { Ok(100) },
+            |_: Vec<u32>| async { Err::<(), _>(eyre!("No valid rpc sources available")) },
+        )
+        .await;
+
+        assert!(outcome.responses.is_empty());
+        assert!(outcome.failed.is_empty());
+        assert!(outcome.error.is_some());
     }
 }
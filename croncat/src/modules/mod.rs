@@ -8,3 +8,4 @@ pub mod factory;
 pub mod manager;
 pub mod polling;
 pub mod tasks;
+pub mod treasury;
@@ -6,13 +6,13 @@ use crate::utils::Block;
 use crate::utils::Status;
 use async_stream::try_stream;
 use color_eyre::{eyre::eyre, Report};
-use futures_util::TryStream;
+use futures_util::{StreamExt, TryStream};
 use std::{
     pin::Pin,
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 use tendermint::Time;
-use tendermint_rpc::{Client, HttpClient};
+use tendermint_rpc::{query::EventType, Client, HttpClient, SubscriptionClient, WebSocketClient};
 use tokio::time::{sleep, timeout};
 use tracing::debug;
 
@@ -97,6 +97,69 @@ pub fn poll_stream_blocks(http_rpc_host: String, poll_duration_secs: f64) -> Sta
     })
 }
 
+///
+/// Stream sync info from the given websocket endpoint, as each new block is committed.
+/// Reconnects when no block arrives within `timeout_secs`.
+///
+pub fn ws_stream_blocks(ws_rpc_host: String, timeout_secs: f64) -> StatusStream {
+    Box::pin(try_stream! {
+        let ws_timeout_duration = Duration::from_secs_f64(timeout_secs);
+        loop {
+            let (client, driver) = match timeout(ws_timeout_duration, WebSocketClient::new(ws_rpc_host.as_str())).await {
+                Ok(Ok(connection)) => connection,
+                Ok(Err(err)) => {
+                    debug!("Failed to connect to websocket {}: {}", ws_rpc_host, err);
+                    sleep(ws_timeout_duration).await;
+                    continue;
+                }
+                Err(err) => {
+                    debug!("Timed out connecting to websocket {}: {}", ws_rpc_host, err);
+                    continue;
+                }
+            };
+            let driver_handle = tokio::spawn(async move { driver.run().await });
+
+            match client.subscribe(EventType::NewBlock.into()).await {
+                Ok(mut subscription) => loop {
+                    match timeout(ws_timeout_duration, subscription.next()).await {
+                        Ok(Some(Ok(_event))) => {
+                            // The event only has the block, grab the status so we stream the same data as polling
+                            match timeout(ws_timeout_duration, client.status()).await {
+                                Ok(Ok(status)) => {
+                                    debug!("Websocket block {} {}", status.sync_info.latest_block_height, status.sync_info.latest_block_time);
+                                    yield Status { inner: status };
+                                }
+                                Ok(Err(err)) => debug!("Failed to get status over websocket: {}", err),
+                                Err(err) => debug!("Timed out getting status over websocket: {}", err),
+                            }
+                        }
+                        Ok(Some(Err(err))) => {
+                            debug!("Websocket subscription error: {}", err);
+                            break;
+                        }
+                        Ok(None) => {
+                            debug!("Websocket subscription closed");
+                            break;
+                        }
+                        Err(err) => {
+                            debug!("Timed out waiting for a block over websocket: {}", err);
+                            break;
+                        }
+                    }
+                },
+                Err(err) => {
+                    debug!("Failed to subscribe to new blocks: {}", err);
+                    sleep(ws_timeout_duration).await;
+                }
+            }
+
+            // Reconnect
+            let _ = client.close();
+            driver_handle.abort();
+        }
+    })
+}
+
 ///
 /// Stream polled blocks from the given rpc endpoint.
 /// Streams the entire data of a given block
@@ -1,26 +1,30 @@
 use crate::config::ChainConfig;
 use crate::store::tasks::EventType;
-use crate::utils::AtomicIntervalCounter;
+use crate::utils::{drain_join, AtomicIntervalCounter};
 use cosm_orc::orchestrator::{Address, ChainTxResponse};
 use cosmwasm_schema::cw_serde;
 use cosmwasm_std::Timestamp;
 use croncat_sdk_agents::types::AgentStatus;
 use croncat_sdk_tasks::msg::TasksQueryMsg;
 use croncat_sdk_tasks::types::{Boundary, CosmosQuery, TaskInfo};
+use futures_util::{stream, Future, FutureExt, StreamExt};
 use mod_sdk::types::QueryResponse;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
 use std::sync::{
     atomic::{AtomicBool, Ordering::SeqCst},
     Arc,
 };
+use std::time::Duration;
 use tendermint::Time;
 // use croncat_sdk_tasks::types::Boundary;
 use crate::{
-    channels::{ShutdownRx, StatusStreamRx},
-    errors::{eyre, Report},
+    alerts::{self, AlertEvent},
+    channels::{next_block, ShutdownRx, StatusStreamRx},
+    errors::{eyre, ContractError, CroncatError, Report},
     logging::{debug, info},
-    monitor::ping_uptime_monitor,
+    metrics::{self, BATCH_KIND_EVENTED, BATCH_KIND_SCHEDULED},
+    monitor,
     rpc::RpcClientService,
     store::tasks::LocalEventStorage,
 };
@@ -28,7 +32,10 @@ use tokio::{sync::Mutex, task::JoinHandle};
 use tracing::error;
 
 use super::factory::Factory;
-use super::{agent::Agent, manager::Manager};
+use super::{
+    agent::Agent,
+    manager::{BatchOutcome, Manager},
+};
 
 pub struct Tasks {
     pub client: RpcClientService,
@@ -37,6 +44,33 @@ pub struct Tasks {
     pub store: LocalEventStorage,
     // for helping with batch query validation
     pub generic_querier_addr: Address,
+    query_concurrency: usize,
+    query_batch_size: usize,
+    // consecutive query errors, by task hash
+    query_errors: HashMap<String, u32>,
+}
+
+/// Consecutive errored query evaluations before a task goes to cooldown.
+const QUERY_ERROR_THRESHOLD: u32 = 3;
+
+/// How a task's queries evaluated in a block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryEvaluation {
+    Ready,
+    NotReady,
+    /// The queries failed to evaluate, transport errors are the RPC source's fault and not the task's.
+    Errored {
+        reason: String,
+        transport: bool,
+    },
+}
+
+/// How the evaluations went for a block.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EvaluationSummary {
+    pub ready: usize,
+    pub not_ready: usize,
+    pub errored: usize,
 }
 
 #[cw_serde]
@@ -86,8 +120,11 @@ impl Tasks {
             client,
             contract_addr,
             chain_id: chain_id.clone(),
-            store: LocalEventStorage::new(Some(chain_id)),
+            store: LocalEventStorage::new(Some(chain_id))?,
             generic_querier_addr,
+            query_concurrency: cfg.query_concurrency,
+            query_batch_size: cfg.query_batch_size,
+            query_errors: HashMap::new(),
         })
     }
 
@@ -111,8 +148,10 @@ impl Tasks {
         Ok(self.store.get_stats())
     }
 
-    pub fn set_cooldown_task(&mut self, task_hash: String) {
-        self.store.set_cooldown_task(task_hash);
+    pub async fn set_cooldown_task(&mut self, task_hash: String) {
+        if self.store.set_cooldown_task(task_hash.clone()) {
+            alerts::emit(&self.chain_id, AlertEvent::TaskJailed { task_hash }).await;
+        }
     }
 
     pub fn get_cooldown_task(&mut self) -> Option<String> {
@@ -365,66 +404,197 @@ impl Tasks {
 
     // submit the same queries that will re-evaluate on-chain
     // Just need to get all to eval "true" to submit to the chain
-    // Return task hash of validated task
+    // Return how every task evaluated, in priority order
     pub async fn validate_queries(
         &self,
         tasks_with_queries: Vec<&TaskInfo>,
         mod_generic_addr: &str,
-    ) -> Result<Vec<String>, Report> {
-        let mut ready_hashes: HashSet<String> = HashSet::new();
-
-        // Process all the queries
-        // TODO: This needs to change to be BATCH RPC query! Too much latency here...
-        for task in tasks_with_queries {
-            let queries = if let Some(q) = task.queries.to_owned() {
-                q
-            } else {
-                continue;
-            };
-
-            let res: Result<QueryResponse, Report> = self
-                .client
-                .query(move |querier| {
-                    let batch_query = BatchQueryMsg {
-                        batch_query: BatchQuery {
-                            queries: queries.to_owned(),
-                        },
-                    };
-                    async move {
-                        querier
-                            .rpc_client
-                            .wasm_query::<BatchQueryMsg, QueryResponse>(
-                                batch_query,
-                                Some(Address::from_str(mod_generic_addr)?),
-                            )
-                            .await
-                    }
-                })
-                .await;
+    ) -> Result<Vec<(String, QueryEvaluation)>, Report> {
+        let mod_generic_addr = Address::from_str(mod_generic_addr)?;
+        let tasks: Vec<(String, Vec<CosmosQuery>)> = tasks_with_queries
+            .into_iter()
+            .filter_map(|task| Some((task.task_hash.clone(), task.queries.clone()?)))
+            .collect();
+
+        // Several tasks' queries go in one call, mod_generic only says true if all of them are
+        let evaluations = evaluate_grouped(
+            tasks,
+            self.query_batch_size,
+            self.query_concurrency,
+            |group: Vec<(String, Vec<CosmosQuery>)>| {
+                let queries = group.into_iter().flat_map(|(_, q)| q).collect();
+                self.batch_query(queries, mod_generic_addr.clone())
+            },
+        )
+        .await;
+
+        // Dedupe, if theres any remote chance it could  happen
+        let mut seen = HashSet::new();
+        Ok(evaluations
+            .into_iter()
+            .map(|((task_hash, _), evaluation)| (task_hash, evaluation))
+            .filter(|(task_hash, _)| seen.insert(task_hash.clone()))
+            .collect())
+    }
 
-            // Eval if result false, if so break!
-            match res {
-                // likely this was because the response payload didnt match
-                Err(err) if err.to_string().contains("No valid data sources available") => {
-                    break;
+    ///
+    /// Tally a block's evaluations, sending tasks whose queries keep
+    /// erroring to cooldown, where they get jailed if it goes on.
+    ///
+    pub async fn record_evaluations(
+        &mut self,
+        evaluations: &[(String, QueryEvaluation)],
+    ) -> EvaluationSummary {
+        let mut summary = EvaluationSummary::default();
+        for (task_hash, evaluation) in evaluations {
+            let reason = match evaluation {
+                QueryEvaluation::Ready => {
+                    summary.ready += 1;
+                    self.query_errors.remove(task_hash);
+                    continue;
                 }
-                Err(_) => (),
-                Ok(data) => {
-                    if !data.result {
-                        break;
-                    } else {
-                        let h = task.task_hash.clone();
-                        // Dedupe, if theres any remote chance it could  happen
-                        if !ready_hashes.contains(&h) {
-                            ready_hashes.insert(h);
-                        }
+                QueryEvaluation::NotReady => {
+                    summary.not_ready += 1;
+                    self.query_errors.remove(task_hash);
+                    continue;
+                }
+                QueryEvaluation::Errored { reason, transport } => {
+                    summary.errored += 1;
+                    debug!("Task {} queries errored: {}", task_hash, reason);
+                    if *transport {
+                        continue;
                     }
+                    reason
                 }
+            };
+
+            let errors = self.query_errors.entry(task_hash.clone()).or_default();
+            *errors += 1;
+            if *errors >= QUERY_ERROR_THRESHOLD {
+                self.query_errors.remove(task_hash);
+                info!(
+                    "[{}] Task {} queries keep erroring, cooling down: {}",
+                    self.chain_id, task_hash, reason
+                );
+                self.set_cooldown_task(task_hash.clone()).await;
             }
         }
 
-        Ok(ready_hashes.into_iter().collect::<Vec<String>>())
+        summary
     }
+
+    /// Run queries through `mod_generic`, true if every one of them is.
+    async fn batch_query(
+        &self,
+        queries: Vec<CosmosQuery>,
+        mod_generic_addr: Address,
+    ) -> Result<bool, Report> {
+        let res: QueryResponse = self
+            .client
+            .query(move |querier| {
+                let batch_query = BatchQueryMsg {
+                    batch_query: BatchQuery {
+                        queries: queries.to_owned(),
+                    },
+                };
+                let mod_generic_addr = mod_generic_addr.clone();
+                async move {
+                    querier
+                        .rpc_client
+                        .wasm_query::<BatchQueryMsg, QueryResponse>(
+                            batch_query,
+                            Some(mod_generic_addr),
+                        )
+                        .await
+                }
+            })
+            .await?;
+
+        Ok(res.result)
+    }
+}
+
+/// Count the transactions a proxy batch took, so sent is always succeeded plus failed.
+fn record_batch_metrics<T>(chain_id: &str, kind: &str, outcome: &BatchOutcome<T>) {
+    let succeeded = outcome.responses.len();
+    metrics::inc_proxy_batch(chain_id, kind, "sent", outcome.sent);
+    metrics::inc_proxy_batch(chain_id, kind, "succeeded", succeeded);
+    metrics::inc_proxy_batch(chain_id, kind, "failed", outcome.sent - succeeded);
+}
+
+///
+/// Evaluate tasks in groups of up to `group_size`, with at most `concurrency`
+/// evaluations in flight. A group that isn't ready as a whole gets split in half
+/// and evaluated again, until each task has its own result. Results come back in
+/// the tasks' original order.
+///
+/// RPC source errors apply to the whole group, since the RPC is unlikely to do
+/// better on a smaller one, any other error is split like a group that isn't ready.
+///
+async fn evaluate_grouped<T, F, Fut>(
+    tasks: Vec<T>,
+    group_size: usize,
+    concurrency: usize,
+    eval: F,
+) -> Vec<(T, QueryEvaluation)>
+where
+    T: Clone,
+    F: Fn(Vec<T>) -> Fut,
+    Fut: Future<Output = Result<bool, Report>>,
+{
+    let indexed: Vec<(usize, T)> = tasks.into_iter().enumerate().collect();
+    let mut pending: Vec<Vec<(usize, T)>> = indexed
+        .chunks(group_size.max(1))
+        .map(|chunk| chunk.to_vec())
+        .collect();
+    let mut evaluated = vec![];
+
+    while !pending.is_empty() {
+        let results: Vec<_> = stream::iter(pending)
+            .map(|group| {
+                let tasks = group.iter().map(|(_, task)| task.clone()).collect();
+                eval(tasks).map(move |res| (group, res))
+            })
+            .buffer_unordered(concurrency.max(1))
+            .collect()
+            .await;
+
+        pending = vec![];
+        for (mut group, res) in results {
+            let evaluation = match res {
+                Ok(true) => QueryEvaluation::Ready,
+                Err(err) if CroncatError::classify(&err).is_source_error() => {
+                    QueryEvaluation::Errored {
+                        reason: err.to_string(),
+                        transport: true,
+                    }
+                }
+                // Not ready, or a task's query failed, split to find the ones that are
+                _ if group.len() > 1 => {
+                    let rest = group.split_off(group.len() / 2);
+                    pending.push(group);
+                    pending.push(rest);
+                    continue;
+                }
+                Ok(false) => QueryEvaluation::NotReady,
+                Err(err) => QueryEvaluation::Errored {
+                    reason: err.to_string(),
+                    transport: false,
+                },
+            };
+            evaluated.extend(
+                group
+                    .into_iter()
+                    .map(|(index, task)| (index, task, evaluation.clone())),
+            );
+        }
+    }
+
+    evaluated.sort_by_key(|(index, _, _)| *index);
+    evaluated
+        .into_iter()
+        .map(|(_, task, evaluation)| (task, evaluation))
+        .collect()
 }
 
 ///
@@ -432,9 +602,10 @@ impl Tasks {
 ///
 pub async fn refresh_tasks_cache_loop(
     mut block_stream_rx: StatusStreamRx,
-    mut shutdown_rx: ShutdownRx,
+    shutdown_rx: ShutdownRx,
     chain_id: Arc<String>,
     tasks_client: Arc<Mutex<Tasks>>,
+    drain_timeout: Duration,
 ) -> Result<(), Report> {
     // initialize previous cache ASAP first
     // tasks_client.lock().await.load().await?;
@@ -442,8 +613,9 @@ pub async fn refresh_tasks_cache_loop(
     // TODO: Figure out best interval here!
     // TODO: Could actually clear this at THE block when we get expired
     let block_counter = AtomicIntervalCounter::new(10);
+    let mut loop_shutdown_rx = shutdown_rx.resubscribe();
     let task_handle: tokio::task::JoinHandle<Result<(), Report>> = tokio::task::spawn(async move {
-        while let Ok(_block) = block_stream_rx.recv().await {
+        while let Some(_block) = next_block(&mut block_stream_rx, &mut loop_shutdown_rx).await {
             block_counter.tick();
             if block_counter.is_at_interval() && tasks_client.lock().await.load().await? {
                 info!("[{}] Tasks Cache Reloaded", chain_id);
@@ -452,12 +624,7 @@ pub async fn refresh_tasks_cache_loop(
         Ok(())
     });
 
-    tokio::select! {
-        Ok(task) = task_handle => {task?}
-        _ = shutdown_rx.recv() => {}
-    }
-
-    Ok(())
+    drain_join(task_handle, shutdown_rx, drain_timeout).await
 }
 
 ///
@@ -465,16 +632,21 @@ pub async fn refresh_tasks_cache_loop(
 ///
 pub async fn scheduled_tasks_loop(
     mut block_stream_rx: StatusStreamRx,
-    mut shutdown_rx: ShutdownRx,
+    shutdown_rx: ShutdownRx,
     block_status: Arc<Mutex<AgentStatus>>,
     chain_id: Arc<String>,
     agent_client: Arc<Agent>,
     manager_client: Arc<Manager>,
     tasks_client_mut: Arc<Mutex<Tasks>>,
+    drain_timeout: Duration,
 ) -> Result<(), Report> {
+    let loop_chain_id = chain_id.clone();
+    let mut loop_shutdown_rx = shutdown_rx.resubscribe();
     let block_consumer_stream: JoinHandle<Result<(), Report>> = tokio::task::spawn(async move {
-        while let Ok(block) = block_stream_rx.recv().await {
-            let is_active = *block_status.lock().await == AgentStatus::Active;
+        while let Some(block) = next_block(&mut block_stream_rx, &mut loop_shutdown_rx).await {
+            // Paused by the low balance policy, wait until funded
+            let is_active =
+                *block_status.lock().await == AgentStatus::Active && !manager_client.is_paused();
 
             if is_active {
                 let tasks_failed = Arc::new(AtomicBool::new(false));
@@ -501,8 +673,7 @@ pub async fn scheduled_tasks_loop(
                         stats.3,
                     );
 
-                    // TODO: Limit batches to max gas 3_000_000-6_000_000 (also could be set per-chain since stargaze has higher limits for example)
-                    // Batch proxy_call's for known task counts
+                    // Batch proxy_call's for known task counts, split by the chain's gas limits
                     let tasks_failed = tasks_failed.clone();
                     let task_count: usize = u64::from(
                         tasks
@@ -511,24 +682,40 @@ pub async fn scheduled_tasks_loop(
                             .saturating_add(tasks.stats.num_cron_tasks),
                     ) as usize;
                     if task_count > 0 {
-                        match manager_client.proxy_call_batch(task_count).await {
-                            Ok(pc_res) => {
-                                debug!("Result: {:?}", pc_res.res.log);
-                                info!(
-                                    "Finished Scheduled Batch - TX: {}, Blk: {}, Evts: {}",
-                                    pc_res.tx_hash,
-                                    pc_res.height,
-                                    pc_res.events.len()
-                                );
-
-                                tasks_client.clean_ended_tasks_from_chain_tx(pc_res).await?;
-                            }
-                            Err(err) => {
-                                tasks_failed.store(true, SeqCst);
-                                // since we don't know the task hash, theres no cooldown/jail - handled onchain
-                                error!("Something went wrong during proxy_call_batch: {}", err);
-                            }
+                        let outcome = manager_client.proxy_call_batch(task_count).await;
+                        record_batch_metrics(&chain_id, BATCH_KIND_SCHEDULED, &outcome);
+                        for pc_res in outcome.responses {
+                            debug!("Result: {:?}", pc_res.res.log);
+                            info!(
+                                "Finished Scheduled Batch - TX: {}, Blk: {}, Evts: {}",
+                                pc_res.tx_hash,
+                                pc_res.height,
+                                pc_res.events.len()
+                            );
+
+                            tasks_client.clean_ended_tasks_from_chain_tx(pc_res).await?;
                         }
+                        if !outcome.failed.is_empty() {
+                            tasks_failed.store(true, SeqCst);
+                            monitor::record_failures(&chain_id, outcome.failed.len() as u64).await;
+                            // since we don't know the task hash, theres no cooldown/jail - handled onchain
+                            error!(
+                                "{} of {} proxy calls failed during proxy_call_batch",
+                                outcome.failed.len(),
+                                task_count
+                            );
+                        }
+                        if let Some(err) = outcome.error {
+                            tasks_failed.store(true, SeqCst);
+                            monitor::record_failures(&chain_id, 1).await;
+                            error!("Something went wrong during proxy_call_batch: {}", err);
+                        }
+                        alerts::record_batch(
+                            &chain_id,
+                            BATCH_KIND_SCHEDULED,
+                            tasks_failed.load(SeqCst),
+                        )
+                        .await;
                     }
                 } else {
                     info!(
@@ -537,16 +724,14 @@ pub async fn scheduled_tasks_loop(
                     );
                 }
             }
-
-            ping_uptime_monitor().await;
         }
 
         Ok(())
     });
 
-    tokio::select! {
-        _ = block_consumer_stream => {}
-        _ = shutdown_rx.recv() => {}
+    // Errors end the loop without stopping the agent
+    if let Err(err) = drain_join(block_consumer_stream, shutdown_rx, drain_timeout).await {
+        error!("[{}] Scheduled tasks loop stopped: {}", loop_chain_id, err);
     }
 
     Ok(())
@@ -559,17 +744,22 @@ pub async fn scheduled_tasks_loop(
 // - batch execute valid tasks
 pub async fn evented_tasks_loop(
     mut block_stream_rx: StatusStreamRx,
-    mut shutdown_rx: ShutdownRx,
+    shutdown_rx: ShutdownRx,
     block_status: Arc<Mutex<AgentStatus>>,
     chain_id: Arc<String>,
     manager_client: Arc<Manager>,
     tasks_client_mut: Arc<Mutex<Tasks>>,
     factory_client: Arc<Mutex<Factory>>,
+    drain_timeout: Duration,
 ) -> Result<(), Report> {
+    let loop_chain_id = chain_id.clone();
+    let mut loop_shutdown_rx = shutdown_rx.resubscribe();
     // TODO: Question for Seedyrom: can this while loop invalidate once block passed?
     let block_consumer_stream: JoinHandle<Result<(), Report>> = tokio::task::spawn(async move {
-        while let Ok(block) = block_stream_rx.recv().await {
-            let is_active = *block_status.lock().await == AgentStatus::Active;
+        while let Some(block) = next_block(&mut block_stream_rx, &mut loop_shutdown_rx).await {
+            // Paused by the low balance policy, wait until funded
+            let is_active =
+                *block_status.lock().await == AgentStatus::Active && !manager_client.is_paused();
 
             if is_active {
                 let tasks_failed = Arc::new(AtomicBool::new(false));
@@ -658,9 +848,24 @@ pub async fn evented_tasks_loop(
                 );
 
                 // Validate: get all
-                let mut task_hashes: Vec<String> = tasks_client
+                let evaluations = tasks_client
                     .validate_queries(tasks_with_queries, mod_generic_addr.as_ref())
                     .await?;
+                let summary = tasks_client.record_evaluations(&evaluations).await;
+                metrics::inc_query_evaluations(&chain_id, &summary);
+                debug!(
+                    "[{}] Evaluated queries, Block {}, ready: {}, not ready: {}, errored: {}",
+                    chain_id,
+                    header.latest_block_height,
+                    summary.ready,
+                    summary.not_ready,
+                    summary.errored,
+                );
+                let mut task_hashes: Vec<String> = evaluations
+                    .into_iter()
+                    .filter(|(_, evaluation)| *evaluation == QueryEvaluation::Ready)
+                    .map(|(task_hash, _)| task_hash)
+                    .collect();
                 debug!(
                     "--- validated: task_hashes {:?} {:?}",
                     header.latest_block_height, task_hashes
@@ -697,57 +902,106 @@ pub async fn evented_tasks_loop(
 
                 if !task_hashes.is_empty() {
                     // Batch proxy_call's for task_hashes
-                    // TODO: Limit batches to max gas 3_000_000-6_000_000 (also could be set per-chain since stargaze has higher limits for example)
                     let tasks_failed = tasks_failed.clone();
 
-                    // // NOTE: Disabled since 1 item in batch causes whole batch to fail
-                    match manager_client
-                        .proxy_call_evented_batch(task_hashes.clone())
-                        .await
-                    {
-                        Ok(pc_res) => {
-                            debug!("Result: {:?}", pc_res.res.log);
-                            info!(
-                                "Finished Evented Batch - TX: {}, Blk: {}, Evts: {}",
-                                pc_res.tx_hash,
-                                pc_res.height,
-                                pc_res.events.len()
-                            );
-
-                            tasks_client.clean_ended_tasks_from_chain_tx(pc_res).await?;
+                    // A failing batch gets bisected, so only the failing tasks come back
+                    let outcome = manager_client.proxy_call_evented_batch(task_hashes).await;
+                    record_batch_metrics(&chain_id, BATCH_KIND_EVENTED, &outcome);
+                    for pc_res in outcome.responses {
+                        debug!("Result: {:?}", pc_res.res.log);
+                        info!(
+                            "Finished Evented Batch - TX: {}, Blk: {}, Evts: {}",
+                            pc_res.tx_hash,
+                            pc_res.height,
+                            pc_res.events.len()
+                        );
+
+                        tasks_client.clean_ended_tasks_from_chain_tx(pc_res).await?;
+                    }
+                    if !outcome.failed.is_empty() {
+                        tasks_failed.store(true, SeqCst);
+                        monitor::record_failures(&chain_id, outcome.failed.len() as u64).await;
+                        debug!(
+                            "Isolated failing tasks during proxy_call_evented_batch: {:?}",
+                            outcome.failed
+                        );
+                        for task_hash in outcome.failed {
+                            // Sending to cooldown forces a task to only be attempted a few times before being jailed.
+                            tasks_client.set_cooldown_task(task_hash).await;
                         }
-                        Err(err) => {
-                            tasks_failed.store(true, SeqCst);
-                            let err_msg = err.to_string().to_lowercase();
-                            // Handle: "No tasks to be done in this slot" (just refresh task cache)
-                            if err_msg.contains("no tasks to be done in this slot") {
-                                tasks_client.store.clear_all()?;
-                                tasks_client.load_all_evented_tasks().await?;
-                            }
-                            if err_msg.contains("underflow") || err_msg.contains("overflow") {
-                                // unfortunately we need to add the WHOLE list to cooldown, so they get individually re-tried.
-                                // Hopefully a diff agent picks them up in another order so we execute it faster!
-                                for task_hash in task_hashes {
-                                    // Sending to cooldown forces a task to only be attempted a few times before being jailed.
-                                    tasks_client.set_cooldown_task(task_hash);
-                                }
-                            }
-                            debug!(
-                                "Something went wrong during proxy_call_evented_batch: {}",
-                                err
-                            );
+                    }
+                    if let Some(err) = outcome.error {
+                        tasks_failed.store(true, SeqCst);
+                        monitor::record_failures(&chain_id, 1).await;
+                        // Handle: "No tasks to be done in this slot" (just refresh task cache)
+                        if CroncatError::classify(&err).contract_error()
+                            == Some(&ContractError::NoTasksInSlot)
+                        {
+                            tasks_client.store.clear_all()?;
+                            tasks_client.load_all_evented_tasks().await?;
                         }
+                        debug!(
+                            "Something went wrong during proxy_call_evented_batch: {}",
+                            err
+                        );
                     }
+                    alerts::record_batch(&chain_id, BATCH_KIND_EVENTED, tasks_failed.load(SeqCst))
+                        .await;
                 }
+
+                let (cooldown, jailed) = tasks_client.store.get_task_health();
+                metrics::set_task_health(&chain_id, cooldown, jailed);
             }
         }
 
         Ok(())
     });
-    tokio::select! {
-        _ = block_consumer_stream => {}
-        _ = shutdown_rx.recv() => {}
+    // Errors end the loop without stopping the agent
+    if let Err(err) = drain_join(block_consumer_stream, shutdown_rx, drain_timeout).await {
+        error!("[{}] Evented tasks loop stopped: {}", loop_chain_id, err);
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex as StdMutex;
+
+    #[tokio::test]
+    async fn evaluate_grouped_isolates_each_task() {
+        let calls = StdMutex::new(0);
+        let evaluations = evaluate_grouped((1..=8).collect(), 4, 2, |group: Vec<u32>| {
+            *calls.lock().unwrap() += 1;
+            async move {
+                if group.contains(&8) {
+                    Err(eyre!("query wasm contract failed: Generic error"))
+                } else {
+                    Ok(!group.contains(&3))
+                }
+            }
+        })
+        .await;
+
+        let ready: Vec<u32> = evaluations
+            .iter()
+            .filter(|(_, evaluation)| *evaluation == QueryEvaluation::Ready)
+            .map(|(task, _)| *task)
+            .collect();
+        assert_eq!(ready, vec![1, 2, 4, 5, 6, 7]);
+        assert_eq!(evaluations[2], (3, QueryEvaluation::NotReady));
+        assert!(matches!(
+            evaluations[7],
+            (
+                8,
+                QueryEvaluation::Errored {
+                    transport: false,
+                    ..
+                }
+            )
+        ));
+        // [1..4] and [5..8], then [1, 2], [3, 4], [5, 6], [7, 8], then [3], [4], [7], [8]
+        assert_eq!(*calls.lock().unwrap(), 10);
+    }
+}
@@ -0,0 +1,124 @@
+//!
+//! Top up the agent from a treasury account when it runs low on gas,
+//! within a daily cap.
+//!
+
+use chrono::Utc;
+use color_eyre::Report;
+use cosmrs::bip32;
+use tokio::sync::Mutex;
+use tracing::info;
+
+use crate::{
+    alerts::{self, AlertEvent},
+    config::{ChainConfig, TreasuryConfig},
+    rpc::RpcClientService,
+    store::{treasury::LocalTreasuryStorage, txs::TxKind},
+    tracker,
+};
+
+///
+/// A keystore account that tops up the agent, spending at most `daily_cap` a day.
+///
+pub struct Treasury {
+    client: RpcClientService,
+    account_addr: String,
+    chain_id: String,
+    cfg: TreasuryConfig,
+    store: Mutex<LocalTreasuryStorage>,
+}
+
+impl Treasury {
+    pub async fn new(
+        chain_config: ChainConfig,
+        cfg: TreasuryConfig,
+        key: bip32::XPrv,
+    ) -> Result<Self, Report> {
+        let chain_id = chain_config.info.chain_id.clone();
+        // The treasury pays its own fees and sends from its own account
+        let chain_config = ChainConfig {
+            fee_granter: None,
+            authz_granter: None,
+            ..chain_config
+        };
+        let client = RpcClientService::new(chain_config, key, None).await;
+        let account_addr = client.account_id();
+
+        Ok(Self {
+            client,
+            account_addr,
+            store: Mutex::new(LocalTreasuryStorage::new(&chain_id)?),
+            chain_id,
+            cfg,
+        })
+    }
+
+    pub fn account_addr(&self) -> &String {
+        &self.account_addr
+    }
+
+    ///
+    /// Send the configured top-up to the agent, unless it would go over today's cap.
+    /// Returns the amount sent.
+    ///
+    pub async fn top_up(&self, agent_addr: &str, denom: &str) -> Result<Option<u64>, Report> {
+        let today = Utc::now().date_naive().to_string();
+        let mut store = self.store.lock().await;
+        let spent = store.spent_on(&today);
+        let amount = self.cfg.top_up_amount;
+
+        if !within_cap(spent, amount, self.cfg.daily_cap) {
+            alerts::emit(
+                &self.chain_id,
+                AlertEvent::TreasuryCapReached {
+                    spent,
+                    cap: self.cfg.daily_cap,
+                    denom: denom.to_string(),
+                },
+            )
+            .await;
+            return Ok(None);
+        }
+
+        // Count it before sending, a send that errors after broadcasting still counts
+        store.record(&today, amount)?;
+        info!(
+            "[{}] Topping up agent with {} {} from treasury {}",
+            self.chain_id, amount, denom, self.account_addr
+        );
+        let res = self
+            .client
+            .send_funds(agent_addr, &self.account_addr, denom, amount.into())
+            .await;
+        tracker::track(&self.chain_id, TxKind::TreasuryTopUp, vec![], res).await?;
+
+        alerts::emit(
+            &self.chain_id,
+            AlertEvent::TreasuryTopUp {
+                amount: amount.to_string(),
+                denom: denom.to_string(),
+            },
+        )
+        .await;
+
+        Ok(Some(amount))
+    }
+}
+
+/// Whether sending `amount` keeps the day's total within the cap.
+fn within_cap(spent: u64, amount: u64, cap: u64) -> bool {
+    matches!(spent.checked_add(amount), Some(total) if total <= cap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn top_ups_stay_within_cap() {
+        assert!(within_cap(0, 5, 10));
+        assert!(within_cap(5, 5, 10));
+        assert!(!within_cap(6, 5, 10));
+        assert!(!within_cap(u64::MAX, 1, u64::MAX));
+    }
+}
@@ -1,18 +1,278 @@
+//!
+//! Uptime monitor heartbeats, each chain pings its `uptime_ping_url` on an interval
+//! with a summary of how the agent is doing.
+//!
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use croncat_sdk_agents::types::AgentStatus;
 use lazy_static::lazy_static;
+use reqwest::header::CONTENT_TYPE;
+use serde::Serialize;
+use tokio::sync::Mutex;
 use tracing::{trace, warn};
+use url::Url;
+
+use crate::{
+    channels::{ShutdownRx, StatusStreamRx},
+    config::{ChainConfig, HeartbeatMethod},
+    errors::Report,
+};
+
+/// How long to wait on the uptime monitor before giving up on a ping.
+const PING_TIMEOUT_SECONDS: u64 = 10;
 
 lazy_static! {
-    static ref UPTIME_MONITOR_PING_URL: Option<String> =
-        std::env::var("UPTIME_MONITOR_PING_URL").ok();
-}
-
-pub async fn ping_uptime_monitor() {
-    if UPTIME_MONITOR_PING_URL.as_ref().is_some() {
-        trace!("Pinging uptime monitor...");
-        let _ = reqwest::get(UPTIME_MONITOR_PING_URL.as_ref().unwrap())
-            .await
-            .map_err(|err| {
-                warn!("Failed to ping uptime monitor: {}", err);
-            });
+    /// Fallback ping URL for chains without an `uptime_ping_url`.
+    static ref UPTIME_MONITOR_PING_URL: Option<Url> = std::env::var("UPTIME_MONITOR_PING_URL")
+        .ok()
+        .and_then(|url| url.parse().ok());
+
+    /// What the loops have reported since the last heartbeat, by chain ID.
+    static ref REPORTS: Mutex<HashMap<String, ChainReport>> = Mutex::new(HashMap::new());
+}
+
+/// Latest state reported by the agent loops, failures reset with every heartbeat.
+#[derive(Debug, Clone, Default)]
+struct ChainReport {
+    height: u64,
+    agent_status: Option<String>,
+    balance: Option<(u128, String)>,
+    failures: u64,
+}
+
+///
+/// The status sent along with each heartbeat.
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Heartbeat {
+    pub chain_id: String,
+    pub height: u64,
+    pub agent_status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub balance: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub denom: Option<String>,
+    /// Failed proxy calls since the last heartbeat.
+    pub failures: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Heartbeat {
+    /// The ping URL with the status appended as query params.
+    pub fn to_query_url(&self, url: &Url) -> Url {
+        let mut url = url.clone();
+        {
+            let mut query = url.query_pairs_mut();
+            query
+                .append_pair("chain_id", &self.chain_id)
+                .append_pair("height", &self.height.to_string())
+                .append_pair("agent_status", &self.agent_status)
+                .append_pair("failures", &self.failures.to_string());
+            if let (Some(balance), Some(denom)) = (&self.balance, &self.denom) {
+                query
+                    .append_pair("balance", balance)
+                    .append_pair("denom", denom);
+            }
+            if let Some(error) = &self.error {
+                query.append_pair("error", error);
+            }
+        }
+        url
+    }
+}
+
+/// Record the latest block height for the next heartbeat.
+async fn record_height(chain_id: &str, height: u64) {
+    REPORTS
+        .lock()
+        .await
+        .entry(chain_id.to_string())
+        .or_default()
+        .height = height;
+}
+
+/// Record the agent status for the next heartbeat.
+pub async fn record_agent_status(chain_id: &str, status: &AgentStatus) {
+    REPORTS
+        .lock()
+        .await
+        .entry(chain_id.to_string())
+        .or_default()
+        .agent_status = Some(format!("{status:?}").to_lowercase());
+}
+
+/// Record the agent native balance for the next heartbeat.
+pub async fn record_balance(chain_id: &str, balance: u128, denom: &str) {
+    REPORTS
+        .lock()
+        .await
+        .entry(chain_id.to_string())
+        .or_default()
+        .balance = Some((balance, denom.to_string()));
+}
+
+/// Record failed proxy calls for the next heartbeat.
+pub async fn record_failures(chain_id: &str, failures: u64) {
+    let mut reports = REPORTS.lock().await;
+    let report = reports.entry(chain_id.to_string()).or_default();
+    report.failures = report.failures.saturating_add(failures);
+}
+
+/// Build the heartbeat for a chain, resetting the failure count.
+async fn take_heartbeat(chain_id: &str) -> Heartbeat {
+    let mut reports = REPORTS.lock().await;
+    let report = reports.entry(chain_id.to_string()).or_default();
+    let (balance, denom) = match &report.balance {
+        Some((balance, denom)) => (Some(balance.to_string()), Some(denom.clone())),
+        None => (None, None),
+    };
+
+    Heartbeat {
+        chain_id: chain_id.to_string(),
+        height: report.height,
+        agent_status: report.agent_status.clone().unwrap_or_default(),
+        balance,
+        denom,
+        failures: std::mem::take(&mut report.failures),
+        error: None,
+    }
+}
+
+/// The ping URL for a chain, falling back to `UPTIME_MONITOR_PING_URL`.
+fn ping_url(config: &ChainConfig) -> Option<Url> {
+    config
+        .uptime_ping_url
+        .clone()
+        .or_else(|| UPTIME_MONITOR_PING_URL.clone())
+}
+
+///
+/// The URL for failure pings, `uptime_ping_fail_url` if set, otherwise
+/// `<uptime_ping_url>/fail` as used by healthchecks.io.
+///
+fn fail_url(config: &ChainConfig) -> Option<Url> {
+    config.uptime_ping_fail_url.clone().or_else(|| {
+        let mut url = ping_url(config)?;
+        url.path_segments_mut().ok()?.pop_if_empty().push("fail");
+        Some(url)
+    })
+}
+
+async fn send(client: &reqwest::Client, url: Url, method: HeartbeatMethod, heartbeat: &Heartbeat) {
+    trace!("Pinging uptime monitor...");
+    let request = match method {
+        HeartbeatMethod::Get => client.get(heartbeat.to_query_url(&url)),
+        HeartbeatMethod::Post => match serde_json::to_string(heartbeat) {
+            Ok(body) => client
+                .post(url)
+                .header(CONTENT_TYPE, "application/json")
+                .body(body),
+            Err(err) => {
+                warn!("Failed to encode heartbeat: {}", err);
+                return;
+            }
+        },
+    };
+
+    let _ = request
+        .timeout(Duration::from_secs(PING_TIMEOUT_SECONDS))
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err(|err| {
+            warn!("Failed to ping uptime monitor: {}", err);
+        });
+}
+
+///
+/// Ping the chain's uptime monitor every `uptime_ping_interval_seconds`
+/// with the latest block height, agent status, balance and failures.
+/// If no new block was seen since the last heartbeat, the fail URL is pinged instead.
+///
+pub async fn heartbeat_loop(
+    mut block_stream_rx: StatusStreamRx,
+    mut shutdown_rx: ShutdownRx,
+    chain_id: Arc<String>,
+    config: ChainConfig,
+) -> Result<(), Report> {
+    let url = match ping_url(&config) {
+        Some(url) => url,
+        None => return Ok(()),
+    };
+    let client = reqwest::Client::new();
+    // First ping after one interval, once there's a block height to report
+    let period = Duration::from_secs_f64(config.uptime_ping_interval_seconds);
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+    let mut last_height = 0;
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let mut heartbeat = take_heartbeat(&chain_id).await;
+                if heartbeat.height > last_height {
+                    last_height = heartbeat.height;
+                    send(&client, url.clone(), config.uptime_ping_method, &heartbeat).await;
+                } else if let Some(fail_url) = fail_url(&config) {
+                    // A stalled block stream isn't uptime
+                    heartbeat.error = Some(format!("No new block since height {last_height}"));
+                    send(&client, fail_url, config.uptime_ping_method, &heartbeat).await;
+                }
+            }
+            block = block_stream_rx.recv() => match block {
+                Ok(block) => {
+                    record_height(&chain_id, block.inner.sync_info.latest_block_height.value()).await
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {}
+                Err(_) => break,
+            },
+            _ = shutdown_rx.recv() => break,
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// Tell the uptime monitor the chain's pipeline went down with an error.
+///
+pub async fn ping_failure(chain_id: &str, config: &ChainConfig, err: &Report) {
+    let url = match fail_url(config) {
+        Some(url) => url,
+        None => return,
+    };
+
+    let mut heartbeat = take_heartbeat(chain_id).await;
+    heartbeat.error = Some(err.to_string());
+    send(
+        &reqwest::Client::new(),
+        url,
+        config.uptime_ping_method,
+        &heartbeat,
+    )
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heartbeat_query_params() {
+        let heartbeat = Heartbeat {
+            chain_id: "uni-6".to_string(),
+            height: 42,
+            agent_status: "active".to_string(),
+            balance: Some("1000".to_string()),
+            denom: Some("ujunox".to_string()),
+            failures: 2,
+            error: None,
+        };
+        let url = heartbeat.to_query_url(&"https://hc-ping.com/abc?x=1".parse().unwrap());
+        assert_eq!(
+            url.as_str(),
+            "https://hc-ping.com/abc?x=1&chain_id=uni-6&height=42&agent_status=active&failures=2&balance=1000&denom=ujunox"
+        );
     }
 }
@@ -0,0 +1,154 @@
+//!
+//! Chain info used by the agent, loaded from `config.yaml`, a local registry snapshot,
+//! or the remote [cosmos chain registry](https://github.com/cosmos/chain-registry) when opted in.
+//!
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use cosmos_chain_registry::ChainRegistry;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    errors::{eyre, Report},
+    store::{
+        file::{read_json, write_json},
+        get_storage_path,
+    },
+};
+
+/// Where the registry snapshot is stored, within the storage path.
+const REGISTRY_SNAPSHOT_FILENAME: &str = "registry.json";
+
+///
+/// The chain info we need, field names follow the chain registry `chain.json`
+/// so a raw registry entry can be used as-is.
+///
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainInfo {
+    #[serde(default)]
+    pub chain_id: String,
+    pub bech32_prefix: String,
+    pub fees: FeeTables,
+    #[serde(default)]
+    pub apis: Apis,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeeTables {
+    pub fee_tokens: Vec<FeeToken>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeeToken {
+    pub denom: String,
+    #[serde(default)]
+    pub fixed_min_gas_price: f32,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Apis {
+    #[serde(default)]
+    pub rpc: Vec<Rpc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rpc {
+    pub provider: Option<String>,
+    pub address: String,
+}
+
+impl From<cosmos_chain_registry::ChainInfo> for ChainInfo {
+    fn from(info: cosmos_chain_registry::ChainInfo) -> Self {
+        Self {
+            chain_id: info.chain_id,
+            bech32_prefix: info.bech32_prefix,
+            fees: FeeTables {
+                fee_tokens: info
+                    .fees
+                    .fee_tokens
+                    .into_iter()
+                    .map(|token| FeeToken {
+                        denom: token.denom,
+                        fixed_min_gas_price: token.fixed_min_gas_price,
+                    })
+                    .collect(),
+            },
+            apis: Apis {
+                rpc: info
+                    .apis
+                    .rpc
+                    .into_iter()
+                    .map(|rpc| Rpc {
+                        provider: rpc.provider,
+                        address: rpc.address,
+                    })
+                    .collect(),
+            },
+        }
+    }
+}
+
+///
+/// A local copy of the registry entries for the chains we run, keyed by chain id.
+///
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RegistrySnapshot {
+    pub chains: HashMap<String, ChainInfo>,
+}
+
+impl RegistrySnapshot {
+    /// The default snapshot location.
+    pub fn default_path() -> PathBuf {
+        get_storage_path().join(REGISTRY_SNAPSHOT_FILENAME)
+    }
+
+    ///
+    /// Load the snapshot at the given path, a missing file is an empty snapshot.
+    /// A snapshot that can't be read falls back to the previous one.
+    ///
+    pub fn load(path: &Path) -> Result<Self, Report> {
+        let snapshot =
+            read_json(path).map_err(|err| eyre!("Failed to load registry snapshot: {}", err))?;
+
+        Ok(snapshot.unwrap_or_default())
+    }
+
+    /// Write the snapshot to the given path, never leaving it half written.
+    pub fn write(&self, path: &Path) -> Result<(), Report> {
+        write_json(path, self)
+    }
+
+    pub fn get(&self, chain_id: &str) -> Option<ChainInfo> {
+        self.chains.get(chain_id).cloned()
+    }
+}
+
+///
+/// Fetch chain info from the remote registry.
+///
+pub fn fetch_remote(chain_ids: &[String]) -> Result<HashMap<String, ChainInfo>, Report> {
+    let registry = ChainRegistry::from_remote().map_err(|err| eyre!("{}", err))?;
+
+    let mut chains = HashMap::new();
+    for chain_id in chain_ids {
+        let info = registry
+            .get_by_chain_id(chain_id)
+            .map_err(|err| eyre!("Registry get_by_chain_id error: {}", err))?;
+        chains.insert(chain_id.clone(), info.into());
+    }
+
+    Ok(chains)
+}
+
+///
+/// Refresh the snapshot at `path` with the remote entries for `chain_ids`,
+/// keeping any other chains already in it.
+///
+pub fn sync(chain_ids: &[String], path: &Path) -> Result<(), Report> {
+    let mut snapshot = RegistrySnapshot::load(path)?;
+    snapshot.chains.extend(fetch_remote(chain_ids)?);
+    snapshot.write(path)
+}
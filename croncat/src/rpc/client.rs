@@ -11,22 +11,34 @@ use cosm_tome::modules::cosmwasm::model::ExecRequest;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use std::collections::HashMap;
+use std::future::Future;
 use std::str::FromStr;
+use std::time::Duration;
+use tokio::time::timeout;
 
-use crate::config::ChainConfig;
+use crate::config::{ChainConfig, SimulationPolicy};
+use crate::errors::CroncatError;
 use crate::utils::DERIVATION_PATH;
 
 /// The default RPC call timeout.
 pub const DEFAULT_TIMEOUT: f64 = 20.0;
 
+/// Memo sent along with proxy call batches.
+pub const BATCH_MEMO: &str = "GMEOW 😻 https://Cron.Cat";
+
 /// An RPC client for querying the croncat contract.
 #[derive(Clone)]
 pub struct RpcClient {
     pub(crate) client: CosmOrc<TendermintRPC>,
     pub(crate) contract_addr: Address,
-    key: Option<SigningKey>,
+    pub(crate) key: Option<SigningKey>,
     denom: Option<Denom>,
     pub(crate) timeout_secs: f64,
+    pub(crate) chain_cfg: CosmOrcChainConfig,
+    pub(crate) rpc_url: String,
+    simulation: SimulationPolicy,
+    /// Pays the fees for our transactions through the feegrant module, if set.
+    pub(crate) fee_granter: Option<Address>,
 }
 
 impl RpcClient {
@@ -43,21 +55,27 @@ impl RpcClient {
         );
 
         // Convert our config into a CosmOrc config with the specified rpc url.
-        let denom = cfg.info.fees.fee_tokens[0].denom.clone();
+        let denom = cfg.fee_denom.clone();
+        let chain_cfg = CosmOrcChainConfig {
+            denom: denom.clone(),
+            prefix: cfg.info.bech32_prefix.clone(),
+            chain_id: cfg.info.chain_id.clone(),
+            rpc_endpoint: Some(rpc_url.to_string()),
+            grpc_endpoint: None,
+            gas_price: cfg.gas_prices as f64,
+            gas_adjustment: cfg.gas_adjustment as f64,
+            derivation_path: DERIVATION_PATH.to_string(),
+        };
         let config = CosmOrcConfig {
-            chain_cfg: CosmOrcChainConfig {
-                denom: denom.clone(),
-                prefix: cfg.info.bech32_prefix.clone(),
-                chain_id: cfg.info.chain_id.clone(),
-                rpc_endpoint: Some(rpc_url.to_string()),
-                grpc_endpoint: None,
-                gas_price: cfg.gas_prices as f64,
-                gas_adjustment: cfg.gas_adjustment as f64,
-                derivation_path: DERIVATION_PATH.to_string(),
-            },
+            chain_cfg: chain_cfg.clone(),
             contract_deploy_info,
         };
         let contract_addr = cfg.factory.parse::<Address>()?;
+        let fee_granter = cfg
+            .fee_granter
+            .as_ref()
+            .map(|granter| granter.parse::<Address>())
+            .transpose()?;
 
         Ok(Self {
             client: CosmOrc::new_tendermint_rpc(config, true)?,
@@ -65,6 +83,10 @@ impl RpcClient {
             key: None,
             denom: Some(Denom::from_str(denom.as_str())?),
             timeout_secs: cfg.rpc_timeout_seconds.unwrap_or(DEFAULT_TIMEOUT),
+            chain_cfg,
+            rpc_url: rpc_url.to_string(),
+            simulation: cfg.simulation,
+            fee_granter,
         })
     }
 
@@ -110,7 +132,10 @@ impl RpcClient {
     //     self.call_wasm_query(self.contract_addr.clone(), msg).await
     // }
 
-    /// Execute a contract via RPC.
+    ///
+    /// Execute a contract via RPC. When the chain's [`SimulationPolicy`] simulates it, or a
+    /// fee granter needs the fee spelled out, we sign it ourselves at our next sequence.
+    ///
     pub async fn wasm_execute<S>(
         &self,
         msg: S,
@@ -120,40 +145,45 @@ impl RpcClient {
         S: Serialize,
     {
         if self.key.is_none() {
-            return Err(eyre!("No signing key set"));
+            return Err(CroncatError::Config("No signing key set".to_string()).into());
         }
 
         // Execute a message on the chain -- uses default contract_addr if not specified (factory address)
         // TODO: Assess support for batch settings
         let a = address.unwrap_or_else(|| self.contract_addr.clone());
+        let req = ExecRequest {
+            address: a,
+            msg: &msg,
+            funds: vec![],
+        };
+        if self.simulation.applies(false) || self.fee_granter.is_some() {
+            return self.execute_signed(&[req], "").await;
+        }
+
+        let _sequence = self.exclusive_sequence().await?;
         let response = self
-            .client
-            .client
-            .wasm_execute(
-                ExecRequest {
-                    address: a,
-                    msg: &msg,
-                    funds: vec![],
-                },
+            .cosm_orc_broadcast(self.client.client.wasm_execute(
+                req,
                 self.key.as_ref().unwrap(),
                 &TxOptions::default(),
-            )
+            ))
             .await?;
 
         // return the response data
         Ok(response.res)
     }
 
-    /// Execute batch via RPC.
+    /// Execute batch via RPC, `evented` batches may be simulated even when others aren't.
     pub async fn wasm_execute_batch<S>(
         &self,
         msgs: Vec<ExecRequest<S>>,
+        evented: bool,
     ) -> Result<ChainTxResponse, Report>
     where
         S: Serialize,
     {
         if self.key.is_none() {
-            return Err(eyre!("No signing key set"));
+            return Err(CroncatError::Config("No signing key set".to_string()).into());
         }
 
         let mut reqs = Vec::with_capacity(msgs.len());
@@ -163,17 +193,22 @@ impl RpcClient {
             reqs.push(m)
         }
 
-        let default_tx_options = TxOptions::default();
-        let tx_options = TxOptions {
-            memo: "GMEOW 😻 https://Cron.Cat".to_string(),
-            ..default_tx_options
-        };
+        if self.simulation.applies(evented) || self.fee_granter.is_some() {
+            return self.execute_signed(&reqs, BATCH_MEMO).await;
+        }
 
         // Execute a message on the chain -- uses default contract_addr if not specified (factory address)
+        let _sequence = self.exclusive_sequence().await?;
+        let tx_options = TxOptions {
+            memo: BATCH_MEMO.to_string(),
+            ..TxOptions::default()
+        };
         let response = self
-            .client
-            .client
-            .wasm_execute_batch(reqs, self.key.as_ref().unwrap(), &tx_options)
+            .cosm_orc_broadcast(self.client.client.wasm_execute_batch(
+                reqs,
+                self.key.as_ref().unwrap(),
+                &tx_options,
+            ))
             .await?;
 
         // return the response data
@@ -206,29 +241,65 @@ impl RpcClient {
         amount: u128,
     ) -> Result<ChainTxResponse, Report> {
         if self.key.is_none() {
-            return Err(eyre!("No signing key set"));
+            return Err(CroncatError::Config("No signing key set".to_string()).into());
         }
 
         let to = to.parse::<Address>()?;
         let from = from.parse::<Address>()?;
 
+        let req = SendRequest {
+            to,
+            from,
+            amounts: vec![Coin {
+                denom: Denom::from_str(denom)?,
+                amount,
+            }],
+        };
+        let _sequence = self.exclusive_sequence().await?;
+        let tx_options = self.send_tx_options(&req).await?;
         let response = self
-            .client
-            .client
-            .bank_send(
-                SendRequest {
-                    to,
-                    from,
-                    amounts: vec![Coin {
-                        denom: Denom::from_str(denom)?,
-                        amount,
-                    }],
-                },
+            .cosm_orc_broadcast(self.client.client.bank_send(
+                req,
                 self.key.as_ref().unwrap(),
-                &TxOptions::default(),
-            )
+                &tx_options,
+            ))
             .await?;
 
         Ok(response.res)
     }
+
+    ///
+    /// Broadcast through cosm-orc, which doesn't tell whether a failed transaction reached
+    /// the mempool. A timeout or an error from the source may still see it included, so those
+    /// come back as [`CroncatError::TxPending`] instead of being sent again through another source.
+    ///
+    async fn cosm_orc_broadcast<T, E>(
+        &self,
+        broadcast: impl Future<Output = Result<T, E>>,
+    ) -> Result<T, Report>
+    where
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        let pending = |message: String| CroncatError::TxPending {
+            hash: None,
+            message,
+        };
+
+        match timeout(Duration::from_secs_f64(self.timeout_secs), broadcast).await {
+            Ok(Ok(response)) => Ok(response),
+            Ok(Err(err)) => {
+                let error = CroncatError::classify(&Report::from(err));
+                if error.is_source_error() {
+                    Err(pending(format!("{error}, the transaction may still be included")).into())
+                } else {
+                    Err(error.into())
+                }
+            }
+            Err(_) => Err(pending(format!(
+                "Timeout ({}s) while broadcasting, the transaction may still be included",
+                self.timeout_secs
+            ))
+            .into()),
+        }
+    }
 }
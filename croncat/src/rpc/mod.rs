@@ -5,8 +5,10 @@
 
 pub mod client;
 pub mod querier;
+pub mod sequence;
 pub mod service;
 pub mod signer;
+pub mod tx;
 
 pub use client::RpcClient;
 pub use querier::Querier;
@@ -3,7 +3,7 @@
 //!
 
 use crate::config::ChainConfig;
-use crate::errors::{eyre, Report};
+use crate::errors::{CroncatError, Report};
 use crate::utils::normalize_rpc_url;
 use cosm_orc::orchestrator::Address;
 use serde::de::DeserializeOwned;
@@ -54,12 +54,12 @@ impl Querier {
         )
         .await
         .map_err(|err| {
-            eyre!(
+            CroncatError::Timeout(format!(
                 "Timeout ({}s) while querying contract: {}",
-                self.rpc_client.timeout_secs,
-                err
-            )
+                self.rpc_client.timeout_secs, err
+            ))
         })?
+        .map_err(|err| CroncatError::classify(&err).into())
     }
 }
 
@@ -0,0 +1,83 @@
+//!
+//! Hand out account sequences to concurrent broadcasts from the same key,
+//! so the scheduled and evented loops can land transactions in the same block
+//! without colliding on "account sequence mismatch".
+//!
+
+use std::{collections::HashMap, sync::Arc};
+
+use lazy_static::lazy_static;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+lazy_static! {
+    /// Local sequence state, by account address.
+    static ref SEQUENCES: Mutex<HashMap<String, SequenceManager>> = Mutex::new(HashMap::new());
+}
+
+/// What an account signs its next transaction with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccountSequence {
+    pub account_number: u64,
+    pub sequence: u64,
+}
+
+///
+/// Tracks the next sequence for one key, shared by every signer using it.
+/// `None` means it's unknown and has to be fetched from the chain.
+///
+#[derive(Clone, Default)]
+pub struct SequenceManager {
+    next: Arc<Mutex<Option<AccountSequence>>>,
+}
+
+impl SequenceManager {
+    /// The manager for an account, created the first time it's asked for.
+    pub async fn for_account(address: &str) -> Self {
+        SEQUENCES
+            .lock()
+            .await
+            .entry(address.to_string())
+            .or_default()
+            .clone()
+    }
+
+    /// Lock the sequence, broadcasts hold it until their transaction is in the mempool.
+    pub async fn lock(&self) -> OwnedMutexGuard<Option<AccountSequence>> {
+        self.next.clone().lock_owned().await
+    }
+}
+
+///
+/// Held while a broadcast fetches the sequence on its own, the local sequence
+/// is forgotten when it's dropped so the next broadcast resyncs with the chain.
+///
+pub struct ExclusiveSequence(pub(crate) OwnedMutexGuard<Option<AccountSequence>>);
+
+impl Drop for ExclusiveSequence {
+    fn drop(&mut self) {
+        *self.0 = None;
+    }
+}
+
+/// The sequence the chain expected, from an "account sequence mismatch" error.
+pub fn expected_sequence(log: &str) -> Option<u64> {
+    let (_, rest) = log.split_once("account sequence mismatch, expected ")?;
+    let digits: String = rest.chars().take_while(|c| c.is_ascii_digit()).collect();
+    digits.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_expected_sequence() {
+        assert_eq!(
+            expected_sequence(
+                "account sequence mismatch, expected 42, got 41: incorrect account sequence"
+            ),
+            Some(42)
+        );
+        assert_eq!(expected_sequence("out of gas in location: wasm"), None);
+    }
+}
@@ -3,10 +3,11 @@
 //! This uses multiple approaches to ensure that the service is always available.
 //!
 
+use crate::alerts::{self, AlertEvent};
 use crate::config::{ChainConfig, ChainDataSource};
-use crate::errors::{eyre, Report};
+use crate::errors::{ContractError, CroncatError, Report};
 use crate::logging::info;
-use crate::utils::{is_contract_error, is_error_fallible};
+use crate::metrics;
 use cosm_orc::orchestrator::{Address, ChainTxResponse};
 use cosm_tome::chain::coin::Coin;
 use cosmrs::bip32;
@@ -26,7 +27,8 @@ use super::Signer;
 type RpcSources = Arc<Mutex<HashMap<String, (ChainDataSource, bool)>>>;
 
 lazy_static::lazy_static! {
-    pub(crate) static ref RPC_SOURCES: RpcSources = Arc::new(Mutex::new(HashMap::new()));
+    /// Source pools keyed by chain ID, so every chain in the process keeps its own health state.
+    pub(crate) static ref RPC_SOURCES: Mutex<HashMap<String, RpcSources>> = Mutex::new(HashMap::new());
 }
 
 #[derive(Debug)]
@@ -57,23 +59,54 @@ pub struct RpcClientService {
 }
 
 impl RpcClientService {
-    pub async fn clear_sources() {
-        let mut sources = RPC_SOURCES.lock().await;
-        sources.clear();
+    /// Get the source pool for a chain, creating an empty one if needed.
+    async fn chain_sources(chain_id: &str) -> RpcSources {
+        RPC_SOURCES
+            .lock()
+            .await
+            .entry(chain_id.to_string())
+            .or_insert_with(|| Arc::new(Mutex::new(HashMap::new())))
+            .clone()
+    }
+
+    pub async fn clear_sources(chain_id: &str) {
+        let sources = Self::chain_sources(chain_id).await;
+        sources.lock().await.clear();
     }
 
     pub async fn cache_sources(chain_config: &ChainConfig) {
-        let mut global_sources = RPC_SOURCES.lock().await;
+        let sources = Self::chain_sources(&chain_config.info.chain_id).await;
+        let mut global_sources = sources.lock().await;
 
         if global_sources.is_empty() {
             let data_sources =
-                Self::pick_best_sources(chain_config, &chain_config.data_sources()).await;
+                Self::pick_best_sources(chain_config, &chain_config.rpc_sources()).await;
             for (provider, data_source) in data_sources.iter() {
+                metrics::set_rpc_source_health(
+                    &chain_config.info.chain_id,
+                    provider,
+                    !data_source.1,
+                );
                 global_sources.insert(provider.clone(), data_source.clone());
             }
         }
     }
 
+    /// Mark a source as bad, so it isn't picked again until sources are re-cached.
+    async fn mark_source_bad(&self, source_key: &str) {
+        let mut source_info = self.source_info.lock().await;
+        let (_, bad) = source_info.get_mut(source_key).unwrap();
+        *bad = true;
+        let all_bad = source_info.values().all(|(_, bad)| *bad);
+        drop(source_info);
+
+        let chain_id = &self.chain_config.info.chain_id;
+        metrics::set_rpc_source_health(chain_id, source_key, false);
+        if all_bad {
+            alerts::emit(chain_id, AlertEvent::NoRpcSources).await;
+        }
+    }
+
     pub async fn new(
         chain_config: ChainConfig,
         key: bip32::XPrv,
@@ -83,12 +116,13 @@ impl RpcClientService {
             .unwrap_or_else(|| Address::from_str(chain_config.clone().factory.as_str()).unwrap());
 
         Self::cache_sources(&chain_config).await;
+        let source_info = Self::chain_sources(&chain_config.info.chain_id).await;
 
         Self {
             key,
             chain_config,
             contract_addr,
-            source_info: RPC_SOURCES.clone(),
+            source_info,
         }
     }
 
@@ -147,7 +181,7 @@ impl RpcClientService {
         // Get the rankings
         let rankings = race_track.rankings();
         // Get the data sources
-        let data_sources = chain_config.data_sources();
+        let data_sources = chain_config.rpc_sources();
 
         // Create a map of data sources with their rankings and disqualified status
         let data_sources: HashMap<String, (ChainDataSource, bool)> = rankings
@@ -176,6 +210,10 @@ impl RpcClientService {
         data_sources
     }
 
+    pub fn chain_id(&self) -> &str {
+        &self.chain_config.info.chain_id
+    }
+
     pub fn key(&self) -> SigningKey {
         (&self.key).try_into().unwrap()
     }
@@ -195,6 +233,8 @@ impl RpcClientService {
     {
         let f = Box::new(f);
         let mut last_error = None;
+        // Sources that don't know the contract, but are otherwise healthy.
+        let mut skipped_sources = HashSet::new();
 
         loop {
             let source_keys = {
@@ -203,7 +243,7 @@ impl RpcClientService {
                 source_info
                     .keys()
                     .cloned()
-                    .filter(|k| !source_info.get(k).unwrap().1)
+                    .filter(|k| !source_info.get(k).unwrap().1 && !skipped_sources.contains(k))
                     .collect::<Vec<_>>()
             };
 
@@ -212,7 +252,9 @@ impl RpcClientService {
                     return Err(last_error.unwrap());
                 }
 
-                return Err(eyre!("No valid rpc sources available"));
+                return Err(
+                    CroncatError::Transport("No valid rpc sources available".to_string()).into(),
+                );
             }
 
             let source_key = source_keys
@@ -242,11 +284,7 @@ impl RpcClientService {
                         Ok(client) => client,
                         Err(e) => {
                             debug!("Failed to create RpcClient for {}: {}", source_key, e);
-                            {
-                                let mut source_info = self.source_info.lock().await;
-                                let (_, bad) = source_info.get_mut(&source_key).unwrap();
-                                *bad = true;
-                            }
+                            self.mark_source_bad(&source_key).await;
                             last_error = Some(e);
                             continue;
                         }
@@ -263,11 +301,7 @@ impl RpcClientService {
                         Ok(client) => client,
                         Err(e) => {
                             debug!("Failed to create RpcClient for {}: {}", source_key, e);
-                            {
-                                let mut source_info = self.source_info.lock().await;
-                                let (_, bad) = source_info.get_mut(&source_key).unwrap();
-                                *bad = true;
-                            }
+                            self.mark_source_bad(&source_key).await;
                             last_error = Some(e);
                             continue;
                         }
@@ -280,29 +314,48 @@ impl RpcClientService {
                 Ok(result) => {
                     return Ok(result);
                 }
-                Err(e) if is_error_fallible(&e) => {
-                    debug!("Error calling chain for {}: {}", source_key, e);
-                    break Err(e);
-                }
-                Err(e) if is_contract_error(&e) => {
-                    // push error up stack for specific handling
-                    debug!("Error calling contract for {}: {}", source_key, e);
-                    break Err(e);
-                }
                 Err(e) => {
-                    debug!("Error calling chain for {}: {}", source_key, e);
-                    // Handle cases for when query against a non-existant contract could stop this node
-                    if e.to_string().to_lowercase().contains("contract: not found") {
-                        continue;
-                    }
-                    // This will remove invalid providers if they have errors we dont know how to handle.
-                    {
-                        let mut source_info = self.source_info.lock().await;
-                        let (_, bad) = source_info.get_mut(&source_key).unwrap();
-                        *bad = true;
+                    let error = CroncatError::classify(&e);
+
+                    match error {
+                        // Handle cases for when query against a non-existant contract could stop this node
+                        CroncatError::Contract {
+                            kind: ContractError::ContractNotFound,
+                            ..
+                        } => {
+                            debug!("Contract not found on {}: {}", source_key, e);
+                            skipped_sources.insert(source_key);
+                            last_error = Some(error.into());
+                            continue;
+                        }
+                        // push error up stack for specific handling
+                        CroncatError::Contract { .. } => {
+                            debug!("Error calling contract for {}: {}", source_key, e);
+                            return Err(error.into());
+                        }
+                        CroncatError::AccountNotFound(_)
+                        | CroncatError::Sequence(_)
+                        | CroncatError::InsufficientFunds(_)
+                        | CroncatError::Config(_)
+                        | CroncatError::Stopped(_) => {
+                            debug!("Error calling chain for {}: {}", source_key, e);
+                            return Err(error.into());
+                        }
+                        // Broadcast already, sending it again through another source could pay twice
+                        CroncatError::TxPending { .. } | CroncatError::TxFailed { .. } => {
+                            debug!("Transaction not confirmed on {}: {}", source_key, e);
+                            return Err(error.into());
+                        }
+                        // This will remove invalid providers if they have errors we dont know how to handle.
+                        CroncatError::Transport(_)
+                        | CroncatError::Timeout(_)
+                        | CroncatError::Unknown(_) => {
+                            debug!("Error calling chain for {}: {}", source_key, e);
+                            self.mark_source_bad(&source_key).await;
+                            last_error = Some(error.into());
+                            continue;
+                        }
                     }
-                    last_error = Some(e);
-                    continue;
                 }
             }
         }
@@ -356,6 +409,22 @@ impl RpcClientService {
         Ok(balance)
     }
 
+    /// What's left of a fee grant in `denom`, `None` when it has no spend limit.
+    pub async fn query_fee_allowance(
+        &self,
+        granter: &str,
+        grantee: &str,
+        denom: &str,
+    ) -> Result<Option<u128>, Report> {
+        self.query(move |querier| async move {
+            querier
+                .rpc_client
+                .query_fee_allowance(granter, grantee, denom)
+                .await
+        })
+        .await
+    }
+
     /// Send funds to an address.
     pub async fn send_funds(
         &self,
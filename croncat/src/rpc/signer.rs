@@ -2,9 +2,9 @@
 //! RPC client service that can be used to execute and query the croncat on chain.
 //!
 
-use super::client::RpcClient;
+use super::client::{RpcClient, BATCH_MEMO};
 use crate::config::ChainConfig;
-use crate::errors::{eyre, Report};
+use crate::errors::{CroncatError, Report};
 use crate::utils::normalize_rpc_url;
 use cosm_orc::orchestrator::{Address, ChainTxResponse};
 use cosm_tome::modules::cosmwasm::model::ExecRequest;
@@ -12,14 +12,15 @@ use cosmrs::bip32;
 use cosmrs::crypto::secp256k1::SigningKey;
 use cosmrs::AccountId;
 use serde::Serialize;
-use std::time::Duration;
-use tokio::time::timeout;
+use std::str::FromStr;
 
 #[derive(Clone, Debug)]
 pub struct Signer {
     pub rpc_client: RpcClient,
     pub contract_addr: Address,
     pub account_id: AccountId,
+    /// Account we execute on behalf of through an authz grant, if any.
+    pub granter: Option<AccountId>,
 }
 
 impl Signer {
@@ -37,20 +38,22 @@ impl Signer {
         let account_id = signing_key
             .public_key()
             .account_id(&cfg.info.bech32_prefix)?;
+        let granter = cfg
+            .authz_granter
+            .as_deref()
+            .map(AccountId::from_str)
+            .transpose()?;
 
         // Create a new RPC client
         let mut rpc_client = RpcClient::new(&cfg, rpc_url.as_str())?;
         rpc_client.set_key(key_bytes);
-        rpc_client.set_denom(
-            cfg.denom
-                .unwrap_or_else(|| cfg.info.fees.fee_tokens[0].denom.clone())
-                .as_str(),
-        );
+        rpc_client.set_denom(cfg.denom.as_ref().unwrap_or(&cfg.fee_denom));
 
         Ok(Self {
             account_id,
             contract_addr,
             rpc_client,
+            granter,
         })
     }
 
@@ -63,18 +66,21 @@ impl Signer {
         S: Serialize,
     {
         let a = address.unwrap_or_else(|| self.contract_addr.clone());
-        let res = timeout(
-            Duration::from_secs_f64(self.rpc_client.timeout_secs),
-            self.rpc_client.wasm_execute(msg, Some(a)),
-        )
-        .await
-        .map_err(|err| {
-            eyre!(
-                "Timeout ({}s) while executing wasm: {}",
-                self.rpc_client.timeout_secs,
-                err
-            )
-        })??;
+        let execute = async {
+            match &self.granter {
+                Some(granter) => {
+                    let req = ExecRequest {
+                        address: a,
+                        msg: &msg,
+                        funds: vec![],
+                    };
+                    self.rpc_client.authz_execute(&[req], granter, "").await
+                }
+                None => self.rpc_client.wasm_execute(&msg, Some(a)).await,
+            }
+        };
+        // Each step bounds itself, a timeout after the broadcast mustn't look retryable
+        let res = execute.await.map_err(|err| CroncatError::classify(&err))?;
 
         Ok(res)
     }
@@ -82,23 +88,39 @@ impl Signer {
     pub async fn execute_batch<S>(
         &self,
         msgs: Vec<ExecRequest<S>>,
+        evented: bool,
     ) -> Result<ChainTxResponse, Report>
     where
         S: Serialize,
     {
-        let res = timeout(
-            Duration::from_secs_f64(self.rpc_client.timeout_secs),
-            self.rpc_client.wasm_execute_batch(msgs),
-        )
-        .await
-        .map_err(|err| {
-            eyre!(
-                "Timeout ({}s) while executing wasm: {}",
-                self.rpc_client.timeout_secs,
-                err
-            )
-        })??;
+        let execute = async {
+            match &self.granter {
+                Some(granter) => {
+                    self.rpc_client
+                        .authz_execute(&msgs, granter, BATCH_MEMO)
+                        .await
+                }
+                None => self.rpc_client.wasm_execute_batch(msgs, evented).await,
+            }
+        };
+        let res = execute.await.map_err(|err| CroncatError::classify(&err))?;
 
         Ok(res)
     }
+
+    /// Simulate a batch without broadcasting it, returning the gas it would use.
+    pub async fn simulate_batch<S>(&self, msgs: &[ExecRequest<S>]) -> Result<u64, Report>
+    where
+        S: Serialize,
+    {
+        let simulate = async {
+            match &self.granter {
+                Some(granter) => self.rpc_client.authz_simulate_gas(msgs, granter).await,
+                None => self.rpc_client.simulate_gas(msgs).await,
+            }
+        };
+        let gas_used = simulate.await.map_err(|err| CroncatError::classify(&err))?;
+
+        Ok(gas_used)
+    }
 }
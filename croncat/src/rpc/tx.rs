@@ -0,0 +1,817 @@
+//!
+//! Build and simulate transactions ourselves, so a batch that would revert
+//! gets rejected before we pay to broadcast it. Simulated executions are also
+//! signed and broadcast here, with sequences from the [`SequenceManager`],
+//! along with authz executions since cosm-orc can't wrap messages in `MsgExec`.
+//!
+
+use std::future::Future;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::Utc;
+use cosm_orc::orchestrator::{ChainTxResponse, Coin, Key};
+use cosm_tome::chain::fee::Fee;
+use cosm_tome::chain::request::TxOptions;
+use cosm_tome::chain::response::ChainResponse;
+use cosm_tome::modules::bank::model::SendRequest;
+use cosm_tome::modules::cosmwasm::model::ExecRequest;
+use cosmos_sdk_proto::cosmos::auth::v1beta1::{
+    BaseAccount, QueryAccountRequest, QueryAccountResponse,
+};
+use cosmos_sdk_proto::cosmos::authz::v1beta1::MsgExec;
+use cosmos_sdk_proto::cosmos::base::v1beta1::Coin as ProtoCoin;
+use cosmos_sdk_proto::cosmos::feegrant::v1beta1::{
+    AllowedMsgAllowance, BasicAllowance, PeriodicAllowance, QueryAllowanceRequest,
+    QueryAllowanceResponse,
+};
+use cosmos_sdk_proto::cosmos::tx::v1beta1::{SimulateRequest, SimulateResponse};
+use cosmos_sdk_proto::cosmos::vesting::v1beta1::{
+    ContinuousVestingAccount, DelayedVestingAccount, PeriodicVestingAccount, PermanentLockedAccount,
+};
+use cosmrs::bank::MsgSend;
+use cosmrs::cosmwasm::MsgExecuteContract;
+use cosmrs::crypto::secp256k1::SigningKey;
+use cosmrs::tx::{self, Msg, SignDoc, SignerInfo};
+use cosmrs::AccountId;
+use prost::Message;
+use prost_types::Any;
+use serde::Serialize;
+use tendermint_rpc::abci::transaction::Hash;
+use tendermint_rpc::endpoint::abci_query::AbciQuery;
+use tendermint_rpc::endpoint::tx::Response as TxQueryResponse;
+use tendermint_rpc::{Client, HttpClient};
+use tokio::time::{timeout, Instant};
+use tracing::debug;
+
+use super::client::RpcClient;
+use super::sequence::{expected_sequence, AccountSequence, ExclusiveSequence, SequenceManager};
+use crate::errors::{eyre, CroncatError, Report, TxReceipt, SDK_CODESPACE};
+
+const ACCOUNT_QUERY_PATH: &str = "/cosmos.auth.v1beta1.Query/Account";
+const SIMULATE_PATH: &str = "/cosmos.tx.v1beta1.Service/Simulate";
+const ALLOWANCE_QUERY_PATH: &str = "/cosmos.feegrant.v1beta1.Query/Allowance";
+
+/// Codespace of the fee grant module, and its ErrNoAllowance code.
+const FEEGRANT_CODESPACE: &str = "feegrant";
+const FEEGRANT_ERR_NO_ALLOWANCE: u32 = 5;
+/// ErrKeyNotFound, what a gRPC NotFound query error becomes over ABCI.
+const SDK_ERR_KEY_NOT_FOUND: u32 = 38;
+
+const BASIC_ALLOWANCE_TYPE_URL: &str = "/cosmos.feegrant.v1beta1.BasicAllowance";
+const PERIODIC_ALLOWANCE_TYPE_URL: &str = "/cosmos.feegrant.v1beta1.PeriodicAllowance";
+const ALLOWED_MSG_ALLOWANCE_TYPE_URL: &str = "/cosmos.feegrant.v1beta1.AllowedMsgAllowance";
+const MSG_EXEC_TYPE_URL: &str = "/cosmos.authz.v1beta1.MsgExec";
+
+const BASE_ACCOUNT_TYPE_URL: &str = "/cosmos.auth.v1beta1.BaseAccount";
+const CONTINUOUS_VESTING_ACCOUNT_TYPE_URL: &str =
+    "/cosmos.vesting.v1beta1.ContinuousVestingAccount";
+const DELAYED_VESTING_ACCOUNT_TYPE_URL: &str = "/cosmos.vesting.v1beta1.DelayedVestingAccount";
+const PERIODIC_VESTING_ACCOUNT_TYPE_URL: &str = "/cosmos.vesting.v1beta1.PeriodicVestingAccount";
+const PERMANENT_LOCKED_ACCOUNT_TYPE_URL: &str = "/cosmos.vesting.v1beta1.PermanentLockedAccount";
+
+/// How often to check whether a broadcast transaction made it into a block.
+const TX_POLL_INTERVAL_MILLIS: u64 = 1000;
+
+impl RpcClient {
+    ///
+    /// Simulate the execute messages, returning the gas they used.
+    /// A message that would revert comes back as a classified [`CroncatError`].
+    ///
+    pub async fn simulate_gas<S>(&self, reqs: &[ExecRequest<S>]) -> Result<u64, Report>
+    where
+        S: Serialize,
+    {
+        let sender = self
+            .signing_key()?
+            .public_key()
+            .account_id(&self.chain_cfg.prefix)?;
+        let msgs = reqs
+            .iter()
+            .map(|req| to_any(&sender, req))
+            .collect::<Result<Vec<_>, Report>>()?;
+
+        self.simulate_msgs(msgs).await
+    }
+
+    /// Simulate already encoded messages signed by our key, at the chain's sequence.
+    async fn simulate_msgs(&self, msgs: Vec<Any>) -> Result<u64, Report> {
+        let signing_key = self.signing_key()?;
+        let sender = signing_key
+            .public_key()
+            .account_id(&self.chain_cfg.prefix)?;
+        let client = HttpClient::new(self.rpc_url.as_str())?;
+        let account = base_account(&client, &sender).await?;
+
+        self.simulate_signed(&client, &signing_key, msgs, account)
+            .await
+    }
+
+    /// Simulate encoded messages signed at the given sequence, returning the gas they used.
+    async fn simulate_signed(
+        &self,
+        client: &HttpClient,
+        signing_key: &SigningKey,
+        msgs: Vec<Any>,
+        account: AccountSequence,
+    ) -> Result<u64, Report> {
+        // Fees aren't checked during simulation, only the signature and sequence are
+        let body = tx::Body::new(msgs, "", 0u32);
+        let auth_info = SignerInfo::single_direct(Some(signing_key.public_key()), account.sequence)
+            .auth_info(tx::Fee::from_amount_and_gas(
+                cosmrs::Coin {
+                    denom: self.chain_cfg.denom.parse()?,
+                    amount: 0,
+                },
+                0u64,
+            ));
+        let sign_doc = SignDoc::new(
+            &body,
+            &auth_info,
+            &self.chain_cfg.chain_id.parse()?,
+            account.account_number,
+        )?;
+        let tx_bytes = sign_doc.sign(signing_key)?.to_bytes()?;
+
+        #[allow(deprecated)]
+        let simulated: SimulateResponse = abci_query(
+            client,
+            SIMULATE_PATH,
+            SimulateRequest { tx: None, tx_bytes },
+        )
+        .await
+        .map_err(|err| CroncatError::classify(&err).context("Simulation failed"))?;
+
+        simulated
+            .gas_info
+            .map(|gas_info| gas_info.gas_used)
+            .ok_or_else(|| eyre!("Simulation returned no gas info"))
+    }
+
+    /// Transaction options for a bank send, only simulated when there's a fee granter.
+    pub(crate) async fn send_tx_options(&self, req: &SendRequest) -> Result<TxOptions, Report> {
+        if self.fee_granter.is_none() {
+            return Ok(TxOptions::default());
+        }
+
+        let amount = req
+            .amounts
+            .iter()
+            .map(|coin| {
+                Ok(cosmrs::Coin {
+                    denom: coin.denom.to_string().parse()?,
+                    amount: coin.amount,
+                })
+            })
+            .collect::<Result<Vec<_>, Report>>()?;
+        let msg = MsgSend {
+            from_address: AccountId::from_str(req.from.to_string().as_str())?,
+            to_address: AccountId::from_str(req.to.to_string().as_str())?,
+            amount,
+        };
+
+        let gas_used = self.simulate_msgs(vec![msg.to_any()?]).await?;
+        let (gas_limit, amount) = self.gas_and_fee(gas_used);
+
+        Ok(TxOptions {
+            fee: Some(Fee {
+                amount: vec![Coin {
+                    denom: self.chain_cfg.denom.parse()?,
+                    amount,
+                }],
+                gas_limit,
+                payer: None,
+                granter: self.fee_granter.clone(),
+            }),
+            ..TxOptions::default()
+        })
+    }
+
+    /// Gas limit and fee amount for the simulated gas.
+    fn gas_and_fee(&self, gas_used: u64) -> (u64, u128) {
+        let gas_limit = (gas_used as f64 * self.chain_cfg.gas_adjustment).ceil() as u64;
+        let amount = (gas_limit as f64 * self.chain_cfg.gas_price).ceil() as u128;
+        (gas_limit, amount)
+    }
+
+    ///
+    /// Simulate, sign and broadcast the execute messages at our next local sequence,
+    /// so they can share a block with our other transactions.
+    ///
+    pub(crate) async fn execute_signed<S>(
+        &self,
+        reqs: &[ExecRequest<S>],
+        memo: &str,
+    ) -> Result<ChainTxResponse, Report>
+    where
+        S: Serialize,
+    {
+        let sender = self
+            .signing_key()?
+            .public_key()
+            .account_id(&self.chain_cfg.prefix)?;
+        let msgs = reqs
+            .iter()
+            .map(|req| to_any(&sender, req))
+            .collect::<Result<Vec<_>, Report>>()?;
+
+        self.broadcast_msgs(msgs, memo).await
+    }
+
+    ///
+    /// Execute the messages on behalf of `granter`, wrapped in an authz `MsgExec` signed
+    /// by our key. These are always simulated, since we build and broadcast them ourselves.
+    ///
+    pub async fn authz_execute<S>(
+        &self,
+        reqs: &[ExecRequest<S>],
+        granter: &AccountId,
+        memo: &str,
+    ) -> Result<ChainTxResponse, Report>
+    where
+        S: Serialize,
+    {
+        let exec = self.authz_exec(reqs, granter)?;
+
+        self.broadcast_msgs(vec![exec], memo).await
+    }
+
+    /// Simulate the execute messages on behalf of `granter`, returning the gas they used.
+    pub async fn authz_simulate_gas<S>(
+        &self,
+        reqs: &[ExecRequest<S>],
+        granter: &AccountId,
+    ) -> Result<u64, Report>
+    where
+        S: Serialize,
+    {
+        let exec = self.authz_exec(reqs, granter)?;
+
+        self.simulate_msgs(vec![exec]).await
+    }
+
+    /// The execute messages sent as `granter`, wrapped in a `MsgExec` for our key.
+    fn authz_exec<S>(&self, reqs: &[ExecRequest<S>], granter: &AccountId) -> Result<Any, Report>
+    where
+        S: Serialize,
+    {
+        let grantee = self
+            .signing_key()?
+            .public_key()
+            .account_id(&self.chain_cfg.prefix)?;
+        let msgs = reqs
+            .iter()
+            .map(|req| to_any(granter, req))
+            .collect::<Result<Vec<_>, Report>>()?;
+
+        Ok(Any {
+            type_url: MSG_EXEC_TYPE_URL.to_string(),
+            value: MsgExec {
+                grantee: grantee.to_string(),
+                msgs,
+            }
+            .encode_to_vec(),
+        })
+    }
+
+    ///
+    /// Broadcast messages signed with the next sequence from the key's [`SequenceManager`].
+    /// The sequence stays locked until the transaction passes `CheckTx`, so concurrent
+    /// broadcasts reach the mempool in order, then we wait for it to land in a block.
+    /// A sequence mismatch resyncs to the sequence the chain expected and retries once.
+    /// Once the transaction may have been broadcast, errors are never [source errors],
+    /// so it isn't sent again through another source.
+    ///
+    /// [source errors]: CroncatError::is_source_error
+    ///
+    async fn broadcast_msgs(&self, msgs: Vec<Any>, memo: &str) -> Result<ChainTxResponse, Report> {
+        let signing_key = self.signing_key()?;
+        let sender = signing_key
+            .public_key()
+            .account_id(&self.chain_cfg.prefix)?;
+        let client = HttpClient::new(self.rpc_url.as_str())?;
+        let sequences = SequenceManager::for_account(sender.as_ref()).await;
+
+        let (hash, fee) = {
+            let mut next = sequences.lock().await;
+            let mut resynced = false;
+            loop {
+                let account = match *next {
+                    Some(account) => account,
+                    None => {
+                        self.before_broadcast(base_account(&client, &sender))
+                            .await?
+                    }
+                };
+
+                match self
+                    .sign_and_send(&client, &signing_key, msgs.clone(), memo, account)
+                    .await
+                {
+                    Ok(sent) => {
+                        *next = Some(AccountSequence {
+                            sequence: account.sequence + 1,
+                            ..account
+                        });
+                        break sent;
+                    }
+                    // We can't tell which sequence the chain is at now
+                    Err(err)
+                        if matches!(
+                            err.downcast_ref::<CroncatError>(),
+                            Some(CroncatError::TxPending { .. })
+                        ) =>
+                    {
+                        *next = None;
+                        return Err(err);
+                    }
+                    Err(err) => {
+                        match expected_sequence(&err.to_string()) {
+                            Some(sequence) if !resynced => {
+                                debug!(
+                                    "Sequence mismatch for {}, resyncing to {}",
+                                    sender, sequence
+                                );
+                                resynced = true;
+                                *next = Some(AccountSequence {
+                                    sequence,
+                                    ..account
+                                });
+                            }
+                            // Nothing went into the mempool, the sequence is still free
+                            _ => {
+                                *next = Some(account);
+                                return Err(err);
+                            }
+                        }
+                    }
+                }
+            }
+        };
+
+        self.wait_for_tx(&client, hash, fee).await
+    }
+
+    ///
+    /// Simulate for the gas, then sign and broadcast without waiting for a block.
+    /// Returns the hash and the fee amount paid.
+    ///
+    async fn sign_and_send(
+        &self,
+        client: &HttpClient,
+        signing_key: &SigningKey,
+        msgs: Vec<Any>,
+        memo: &str,
+        account: AccountSequence,
+    ) -> Result<(Hash, u128), Report> {
+        let gas_used = self
+            .before_broadcast(self.simulate_signed(client, signing_key, msgs.clone(), account))
+            .await?;
+        let (gas_limit, amount) = self.gas_and_fee(gas_used);
+        let mut fee = tx::Fee::from_amount_and_gas(
+            cosmrs::Coin {
+                denom: self.chain_cfg.denom.parse()?,
+                amount,
+            },
+            gas_limit,
+        );
+        fee.granter = self
+            .fee_granter
+            .as_ref()
+            .map(|granter| AccountId::from_str(granter.to_string().as_str()))
+            .transpose()?;
+
+        let body = tx::Body::new(msgs, memo, 0u32);
+        let auth_info = SignerInfo::single_direct(Some(signing_key.public_key()), account.sequence)
+            .auth_info(fee);
+        let sign_doc = SignDoc::new(
+            &body,
+            &auth_info,
+            &self.chain_cfg.chain_id.parse()?,
+            account.account_number,
+        )?;
+        let tx_bytes = sign_doc.sign(signing_key)?.to_bytes()?;
+
+        // No answer doesn't mean the node didn't take it
+        let res = timeout(
+            Duration::from_secs_f64(self.timeout_secs),
+            client.broadcast_tx_sync(tx_bytes.into()),
+        )
+        .await
+        .map_err(|_| CroncatError::TxPending {
+            hash: None,
+            message: format!(
+                "No answer to the broadcast after {}s, the transaction may still be included",
+                self.timeout_secs
+            ),
+        })??;
+        // CheckTx only runs the SDK's ante handlers, the response carries no codespace
+        if res.code.is_err() {
+            return Err(CroncatError::from_abci(
+                SDK_CODESPACE,
+                res.code.value(),
+                &res.log.to_string(),
+            )
+            .into());
+        }
+
+        Ok((res.hash, amount))
+    }
+
+    ///
+    /// Poll for a broadcast transaction until it's in a block. A transaction that
+    /// failed in the block still paid `fee`, which is kept in the error's receipt.
+    ///
+    async fn wait_for_tx(
+        &self,
+        client: &HttpClient,
+        hash: Hash,
+        fee: u128,
+    ) -> Result<ChainTxResponse, Report> {
+        let deadline = Instant::now() + Duration::from_secs_f64(self.timeout_secs);
+        loop {
+            tokio::time::sleep(Duration::from_millis(TX_POLL_INTERVAL_MILLIS)).await;
+            // Not found until it's been included
+            if let Ok(res) = client.tx(hash, false).await {
+                let result = &res.tx_result;
+                if result.code.is_err() {
+                    let cause = CroncatError::from_abci(
+                        &result.codespace.to_string(),
+                        result.code.value(),
+                        &result.log.to_string(),
+                    );
+                    let receipt = TxReceipt {
+                        hash: res.hash.to_string(),
+                        height: res.height.value(),
+                        gas_wanted: result.gas_wanted.value(),
+                        gas_used: result.gas_used.value(),
+                        fee,
+                    };
+                    return Err(CroncatError::tx_failed(cause, receipt).into());
+                }
+                return Ok(chain_tx_response(res));
+            }
+            if Instant::now() >= deadline {
+                return Err(CroncatError::TxPending {
+                    hash: Some(hash.to_string()),
+                    message: format!(
+                        "Transaction {hash} wasn't included after {}s",
+                        self.timeout_secs
+                    ),
+                }
+                .into());
+            }
+        }
+    }
+
+    /// Bound a call made before anything was broadcast, timing out is safe to retry.
+    async fn before_broadcast<T>(
+        &self,
+        call: impl Future<Output = Result<T, Report>>,
+    ) -> Result<T, Report> {
+        timeout(Duration::from_secs_f64(self.timeout_secs), call)
+            .await
+            .map_err(|_| {
+                CroncatError::Timeout(format!(
+                    "Timeout ({}s) while preparing the transaction",
+                    self.timeout_secs
+                ))
+            })?
+    }
+
+    ///
+    /// Lock our sequence for a broadcast that fetches the sequence itself, like cosm-orc does.
+    /// Waits for our transactions in the mempool to land first, since the chain's sequence
+    /// doesn't count them until then. The local sequence resyncs once the guard is dropped.
+    ///
+    pub(crate) async fn exclusive_sequence(&self) -> Result<ExclusiveSequence, Report> {
+        let sender = self
+            .signing_key()?
+            .public_key()
+            .account_id(&self.chain_cfg.prefix)?;
+        let guard = SequenceManager::for_account(sender.as_ref())
+            .await
+            .lock()
+            .await;
+
+        if let Some(local) = *guard {
+            let client = HttpClient::new(self.rpc_url.as_str())?;
+            let deadline = Instant::now() + Duration::from_secs_f64(self.timeout_secs);
+            while self
+                .before_broadcast(base_account(&client, &sender))
+                .await?
+                .sequence
+                < local.sequence
+                && Instant::now() < deadline
+            {
+                tokio::time::sleep(Duration::from_millis(TX_POLL_INTERVAL_MILLIS)).await;
+            }
+        }
+
+        Ok(ExclusiveSequence(guard))
+    }
+
+    ///
+    /// What's left of the fee grant from `granter` to `grantee` in `denom`,
+    /// `None` when the grant has no spend limit.
+    ///
+    pub async fn query_fee_allowance(
+        &self,
+        granter: &str,
+        grantee: &str,
+        denom: &str,
+    ) -> Result<Option<u128>, Report> {
+        let client = HttpClient::new(self.rpc_url.as_str())?;
+        let res = abci_query_raw(
+            &client,
+            ALLOWANCE_QUERY_PATH,
+            QueryAllowanceRequest {
+                granter: granter.to_string(),
+                grantee: grantee.to_string(),
+            },
+        )
+        .await?;
+
+        // Missing, revoked and pruned expired grants leave nothing to pay gas with,
+        // so the low balance policy applies instead of stopping the agent
+        let codespace = res.codespace.to_string();
+        let no_grant = matches!(
+            (codespace.as_str(), res.code.value()),
+            (FEEGRANT_CODESPACE, FEEGRANT_ERR_NO_ALLOWANCE)
+                | (SDK_CODESPACE, SDK_ERR_KEY_NOT_FOUND)
+        );
+        if no_grant {
+            debug!("No fee grant from {} to {}", granter, grantee);
+            return Ok(Some(0));
+        }
+        let response: QueryAllowanceResponse = decode_abci(res)?;
+        let allowance = match response.allowance.and_then(|grant| grant.allowance) {
+            Some(allowance) => allowance,
+            None => return Ok(Some(0)),
+        };
+
+        remaining_allowance(&allowance, denom, Utc::now().timestamp())
+    }
+
+    fn signing_key(&self) -> Result<SigningKey, Report> {
+        match self.key.as_ref().map(|key| &key.key) {
+            Some(Key::Raw(bytes)) => Ok(SigningKey::from_bytes(bytes)?),
+            _ => Err(CroncatError::Config("No signing key set".to_string()).into()),
+        }
+    }
+}
+
+fn to_any<S>(sender: &AccountId, req: &ExecRequest<S>) -> Result<Any, Report>
+where
+    S: Serialize,
+{
+    let funds = req
+        .funds
+        .iter()
+        .map(|coin| {
+            Ok(cosmrs::Coin {
+                denom: coin.denom.to_string().parse()?,
+                amount: coin.amount,
+            })
+        })
+        .collect::<Result<Vec<_>, Report>>()?;
+
+    let msg = MsgExecuteContract {
+        sender: sender.clone(),
+        contract: AccountId::from_str(req.address.to_string().as_str())?,
+        msg: serde_json::to_vec(&req.msg)?,
+        funds,
+    };
+
+    Ok(msg.to_any()?)
+}
+
+///
+/// What's left to spend in `denom` under a fee allowance at `now` (unix seconds),
+/// `None` when it has no spend limit.
+///
+fn remaining_allowance(allowance: &Any, denom: &str, now: i64) -> Result<Option<u128>, Report> {
+    match allowance.type_url.as_str() {
+        BASIC_ALLOWANCE_TYPE_URL => {
+            let basic = BasicAllowance::decode(allowance.value.as_slice())?;
+            basic_remaining(&basic, denom, now)
+        }
+        PERIODIC_ALLOWANCE_TYPE_URL => {
+            let periodic = PeriodicAllowance::decode(allowance.value.as_slice())?;
+            let basic = match &periodic.basic {
+                Some(basic) => basic_remaining(basic, denom, now)?,
+                None => None,
+            };
+            // The period's spend resets to the full limit once the reset time has passed
+            let reset = matches!(&periodic.period_reset, Some(reset) if reset.seconds <= now);
+            let can_spend = if reset {
+                &periodic.period_spend_limit
+            } else {
+                &periodic.period_can_spend
+            };
+            let period = limited_amount(&periodic.period_spend_limit, can_spend, denom)?;
+
+            Ok(match (basic, period) {
+                (Some(basic), Some(period)) => Some(basic.min(period)),
+                (basic, period) => basic.or(period),
+            })
+        }
+        ALLOWED_MSG_ALLOWANCE_TYPE_URL => {
+            let allowed = AllowedMsgAllowance::decode(allowance.value.as_slice())?;
+            let inner = allowed
+                .allowance
+                .ok_or_else(|| eyre!("Fee allowance is missing its inner allowance"))?;
+            remaining_allowance(&inner, denom, now)
+        }
+        type_url => Err(eyre!("Unsupported fee allowance: {}", type_url)),
+    }
+}
+
+fn basic_remaining(basic: &BasicAllowance, denom: &str, now: i64) -> Result<Option<u128>, Report> {
+    if matches!(&basic.expiration, Some(expiration) if expiration.seconds <= now) {
+        return Ok(Some(0));
+    }
+    limited_amount(&basic.spend_limit, &basic.spend_limit, denom)
+}
+
+/// Amount of `denom` left, `None` when there's no `limit` at all.
+fn limited_amount(
+    limit: &[ProtoCoin],
+    remaining: &[ProtoCoin],
+    denom: &str,
+) -> Result<Option<u128>, Report> {
+    if limit.is_empty() {
+        return Ok(None);
+    }
+    match remaining.iter().find(|coin| coin.denom == denom) {
+        Some(coin) => Ok(Some(coin.amount.parse()?)),
+        None => Ok(Some(0)),
+    }
+}
+
+/// The account number and sequence to sign with, from the chain.
+async fn base_account(client: &HttpClient, address: &AccountId) -> Result<AccountSequence, Report> {
+    let account: QueryAccountResponse = abci_query(
+        client,
+        ACCOUNT_QUERY_PATH,
+        QueryAccountRequest {
+            address: address.to_string(),
+        },
+    )
+    .await?;
+    let account = account
+        .account
+        .ok_or_else(|| CroncatError::AccountNotFound(format!("Account {address} not found")))?;
+
+    let account = decode_base_account(&account)?;
+
+    Ok(AccountSequence {
+        account_number: account.account_number,
+        sequence: account.sequence,
+    })
+}
+
+/// The base account of a plain or vesting account.
+fn decode_base_account(account: &Any) -> Result<BaseAccount, Report> {
+    let value = account.value.as_slice();
+    let vesting = match account.type_url.as_str() {
+        BASE_ACCOUNT_TYPE_URL => return Ok(BaseAccount::decode(value)?),
+        CONTINUOUS_VESTING_ACCOUNT_TYPE_URL => {
+            ContinuousVestingAccount::decode(value)?.base_vesting_account
+        }
+        DELAYED_VESTING_ACCOUNT_TYPE_URL => {
+            DelayedVestingAccount::decode(value)?.base_vesting_account
+        }
+        PERIODIC_VESTING_ACCOUNT_TYPE_URL => {
+            PeriodicVestingAccount::decode(value)?.base_vesting_account
+        }
+        PERMANENT_LOCKED_ACCOUNT_TYPE_URL => {
+            PermanentLockedAccount::decode(value)?.base_vesting_account
+        }
+        type_url => return Err(eyre!("Unsupported account type: {}", type_url)),
+    };
+
+    vesting
+        .and_then(|vesting| vesting.base_account)
+        .ok_or_else(|| eyre!("Vesting account is missing its base account"))
+}
+
+fn chain_tx_response(res: TxQueryResponse) -> ChainTxResponse {
+    let result = res.tx_result;
+    ChainTxResponse {
+        res: ChainResponse {
+            code: result.code.into(),
+            data: Some(result.data.value().to_vec()),
+            log: result.log.to_string(),
+        },
+        events: result.events.into_iter().map(Into::into).collect(),
+        gas_wanted: result.gas_wanted.value(),
+        gas_used: result.gas_used.value(),
+        tx_hash: res.hash.to_string(),
+        height: res.height.value(),
+    }
+}
+
+async fn abci_query<Req, Res>(client: &HttpClient, path: &str, req: Req) -> Result<Res, Report>
+where
+    Req: Message,
+    Res: Message + Default,
+{
+    decode_abci(abci_query_raw(client, path, req).await?)
+}
+
+/// The raw ABCI query response, for callers that handle some error codes themselves.
+async fn abci_query_raw<Req: Message>(
+    client: &HttpClient,
+    path: &str,
+    req: Req,
+) -> Result<AbciQuery, Report> {
+    Ok(client
+        .abci_query(Some(path.parse()?), req.encode_to_vec(), None, false)
+        .await?)
+}
+
+/// Decode an ABCI query response, classifying the error code if it failed.
+fn decode_abci<Res: Message + Default>(res: AbciQuery) -> Result<Res, Report> {
+    if res.code.is_err() {
+        return Err(CroncatError::from_abci(
+            &res.codespace.to_string(),
+            res.code.value(),
+            &res.log.to_string(),
+        )
+        .into());
+    }
+
+    Ok(Res::decode(res.value.as_slice())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prost_types::Timestamp;
+
+    fn coins(amount: &str) -> Vec<ProtoCoin> {
+        vec![ProtoCoin {
+            denom: "ujunox".to_string(),
+            amount: amount.to_string(),
+        }]
+    }
+
+    fn encode<M: Message>(type_url: &str, msg: M) -> Any {
+        Any {
+            type_url: type_url.to_string(),
+            value: msg.encode_to_vec(),
+        }
+    }
+
+    #[test]
+    fn fee_allowance_remaining() {
+        let basic = BasicAllowance {
+            spend_limit: coins("500"),
+            expiration: None,
+        };
+        let unlimited = encode(BASIC_ALLOWANCE_TYPE_URL, BasicAllowance::default());
+        assert_eq!(remaining_allowance(&unlimited, "ujunox", 0).unwrap(), None);
+        assert_eq!(
+            remaining_allowance(
+                &encode(BASIC_ALLOWANCE_TYPE_URL, basic.clone()),
+                "ujunox",
+                0
+            )
+            .unwrap(),
+            Some(500)
+        );
+
+        let periodic = PeriodicAllowance {
+            basic: Some(basic),
+            period: None,
+            period_spend_limit: coins("200"),
+            period_can_spend: coins("50"),
+            period_reset: Some(Timestamp {
+                seconds: 100,
+                nanos: 0,
+            }),
+        };
+        let periodic = encode(PERIODIC_ALLOWANCE_TYPE_URL, periodic);
+        assert_eq!(
+            remaining_allowance(&periodic, "ujunox", 0).unwrap(),
+            Some(50)
+        );
+        assert_eq!(
+            remaining_allowance(&periodic, "ujunox", 100).unwrap(),
+            Some(200)
+        );
+
+        let allowed = encode(
+            ALLOWED_MSG_ALLOWANCE_TYPE_URL,
+            AllowedMsgAllowance {
+                allowance: Some(periodic),
+                allowed_messages: vec![],
+            },
+        );
+        assert_eq!(
+            remaining_allowance(&allowed, "ujunox", 0).unwrap(),
+            Some(50)
+        );
+    }
+}
@@ -5,16 +5,23 @@
 
 use bip39::Mnemonic;
 use color_eyre::eyre::eyre;
-use cosmrs::{bip32, crypto::secp256k1::SigningKey};
+use cosmrs::{
+    bip32::{self, secp256k1::ecdsa::VerifyingKey, PublicKey as _},
+    crypto::PublicKey,
+};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fs, path::PathBuf};
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
 
-use crate::{errors::Report, utils::DERIVATION_PATH};
+use crate::{
+    errors::{CroncatError, Report},
+    utils::DERIVATION_PATH,
+};
 
-use super::get_storage_path;
-
-/// Where our [`LocalAgentStorage`] will be stored.
-const LOCAL_STORAGE_AGENTS_FILENAME: &str = "./agents.json";
+use super::{
+    backend::{self, StorageBackend},
+    get_storage_path,
+    keystore::EncryptedSecret,
+};
 
 /// Alias String as [`AccountId`] in this module only.
 type AccountId = String;
@@ -24,7 +31,9 @@ type LocalAgentStorageData = HashMap<AccountId, LocalAgentStorageEntry>;
 
 #[derive(Serialize, Deserialize, Clone)]
 struct KeyPair {
-    private_key: String,
+    // left out once the entry is encrypted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    private_key: Option<String>,
     public_key: String,
 }
 
@@ -37,11 +46,15 @@ impl std::fmt::Debug for KeyPair {
     }
 }
 
-/// Store the keypair and the payable account idea for a stored agent
+/// Store the keypair and the payable account idea for a stored agent,
+/// the mnemonic is either in plain text or encrypted with a passphrase.
 #[derive(Serialize, Deserialize, Clone)]
 pub struct LocalAgentStorageEntry {
     keypair: KeyPair,
-    pub mnemonic: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mnemonic: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encrypted_mnemonic: Option<EncryptedSecret>,
     pub payable_account_id: Option<String>,
 }
 
@@ -50,55 +63,46 @@ impl std::fmt::Debug for LocalAgentStorageEntry {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("LocalAgentStorageEntry")
             .field("keypair", &self.keypair)
+            .field("encrypted_mnemonic", &self.encrypted_mnemonic)
             .field("payable_account_id", &self.payable_account_id)
             .finish()
     }
 }
 
 /// Store key pairs on disk and allow access to the data.
+#[derive(Clone)]
 pub struct LocalAgentStorage {
     pub path: PathBuf,
     data: LocalAgentStorageData,
+    // unlocks encrypted entries
+    passphrase: Option<String>,
+    backend: Arc<dyn StorageBackend>,
 }
 
 impl LocalAgentStorage {
     /// Create a new [`LocalAgentStorage`] instance with the default directory.
-    pub fn new() -> Self {
+    pub fn new() -> Result<Self, Report> {
         Self::from_path(get_storage_path())
     }
 
     /// Create a [`LocalAgentStorage`] instance at a specified path,
     /// if the data already exists at the directory we load it.
-    pub fn from_path(path: PathBuf) -> Self {
-        let agent_data_file = path.join(LOCAL_STORAGE_AGENTS_FILENAME);
-
-        // Load from the agent data file if it exists
-        if agent_data_file.exists() {
-            let json_data = fs::read_to_string(agent_data_file).unwrap();
-            let data =
-                serde_json::from_str(json_data.as_str()).expect("Failed to parse agent JSON data");
-            Self { path, data }
-        } else {
-            // Otherwise create a new hashmap
-            Self {
-                path,
-                data: HashMap::new(),
-            }
-        }
+    pub fn from_path(path: PathBuf) -> Result<Self, Report> {
+        let backend = backend::for_path(&path)?;
+        let data = backend
+            .load_agents()
+            .map_err(|err| CroncatError::Config(format!("Failed to load agents: {err}")))?;
+        Ok(Self {
+            path,
+            data,
+            passphrase: None,
+            backend,
+        })
     }
 
-    /// Write our data to disk at the specified location.
-    fn write_to_disk(&self) -> Result<(), Report> {
-        let agent_data_file = self.path.join(LOCAL_STORAGE_AGENTS_FILENAME);
-
-        // Create the directory to store our data if it doesn't exist
-        if let Some(p) = agent_data_file.parent() {
-            fs::create_dir_all(p)?
-        };
-
-        fs::write(agent_data_file, serde_json::to_string_pretty(&self.data)?)?;
-
-        Ok(())
+    /// Hand the agents that changed to the storage backend.
+    fn write_to_disk(&self, changed: &[AccountId]) -> Result<(), Report> {
+        self.backend.save_agents(&self.data, changed)
     }
 
     /// Insert a new agent into the data map.
@@ -115,18 +119,30 @@ impl LocalAgentStorage {
                 &DERIVATION_PATH.parse()?,
             )?;
             let public_key = key.public_key().to_string(cosmrs::bip32::Prefix::XPRV);
-            let private_key = key.to_string(cosmrs::bip32::Prefix::XPRV).to_string();
-            let keypair = KeyPair {
-                public_key,
-                private_key,
-            };
-
             let mnemonic = mnemonic.to_string();
 
-            let new_key = LocalAgentStorageEntry {
-                keypair,
-                mnemonic,
-                payable_account_id: None,
+            // Keep the new agent encrypted like the rest of an unlocked keystore
+            let new_key = if let Some(passphrase) = &self.passphrase {
+                LocalAgentStorageEntry {
+                    keypair: KeyPair {
+                        public_key,
+                        private_key: None,
+                    },
+                    mnemonic: None,
+                    encrypted_mnemonic: Some(EncryptedSecret::encrypt(&mnemonic, passphrase)?),
+                    payable_account_id: None,
+                }
+            } else {
+                let private_key = key.to_string(cosmrs::bip32::Prefix::XPRV).to_string();
+                LocalAgentStorageEntry {
+                    keypair: KeyPair {
+                        public_key,
+                        private_key: Some(private_key),
+                    },
+                    mnemonic: Some(mnemonic),
+                    encrypted_mnemonic: None,
+                    payable_account_id: None,
+                }
             };
             self.data.insert(account_id, new_key.clone());
             Ok(Some(new_key))
@@ -142,33 +158,64 @@ impl LocalAgentStorage {
         match self.get(&account_id) {
             Some(_) => Err(eyre!(r#"Agent "{account_id}" already created"#)),
             None => {
+                self.check_passphrase()?;
                 let validated_mnemonic = if let Some(phrase) = mnemonic {
                     Mnemonic::parse_normalized(&phrase)
                 } else {
                     Mnemonic::generate(24)
                 }?;
                 self.insert(account_id.clone(), validated_mnemonic)?;
-                self.write_to_disk()?;
+                self.write_to_disk(&[account_id])?;
                 Ok(())
             }
         }
     }
 
-    pub fn display_account(&self, account_id: &str) {
-        let new_account = self.get(account_id);
-        println!(
-            "Agent JSON: {}",
-            serde_json::to_string_pretty(&new_account).unwrap()
-        );
+    ///
+    /// Make sure a new agent won't mix plain text and encrypted entries,
+    /// or entries encrypted with different passphrases.
+    ///
+    fn check_passphrase(&self) -> Result<(), Report> {
+        let encrypted = self
+            .data
+            .values()
+            .find_map(|entry| entry.encrypted_mnemonic.as_ref());
+
+        match (encrypted, &self.passphrase) {
+            (Some(encrypted), Some(passphrase)) => {
+                encrypted.decrypt(passphrase)?;
+                Ok(())
+            }
+            (Some(_), None) => Err(CroncatError::Config(
+                "Agent keystore is encrypted, a passphrase is needed to add agents".to_string(),
+            )
+            .into()),
+            (None, _) => Ok(()),
+        }
+    }
+
+    /// Print the public key and the address on each chain, never the secrets.
+    pub fn display_account(
+        &self,
+        account_id: &AccountId,
+        prefixes: &[(String, String)],
+    ) -> Result<(), Report> {
+        let entry = self
+            .get(account_id)
+            .ok_or_else(|| CroncatError::Config(format!("Agent not found: {account_id}")))?;
+
+        println!("Agent: {account_id}");
+        println!("Public key: {}", entry.keypair.public_key);
+        for (chain_id, prefix) in prefixes {
+            let account_addr = self.get_agent_signing_account_addr(account_id, prefix.clone())?;
+            println!("{chain_id}: {account_addr}");
+        }
+
+        Ok(())
     }
 
     pub fn get_agent_signing_key(&self, account_id: &AccountId) -> Result<bip32::XPrv, Report> {
-        let entry = if let Some(entry) = self.get(account_id) {
-            entry
-        } else {
-            return Err(eyre!("Agent not found: {}", account_id));
-        };
-        let mnemonic: Mnemonic = entry.mnemonic.parse()?;
+        let mnemonic = self.get_agent_mnemonic(account_id)?;
         let key =
             cosmrs::bip32::XPrv::derive_from_path(mnemonic.to_seed(""), &DERIVATION_PATH.parse()?)?;
         Ok(key)
@@ -178,22 +225,83 @@ impl LocalAgentStorage {
         let entry = if let Some(entry) = self.get(account_id) {
             entry
         } else {
-            return Err(eyre!("Agent not found: {}", account_id));
+            return Err(CroncatError::Config(format!("Agent not found: {account_id}")).into());
+        };
+
+        let mnemonic: Mnemonic = match (&entry.mnemonic, &entry.encrypted_mnemonic) {
+            (Some(mnemonic), _) => mnemonic.parse()?,
+            (None, Some(encrypted)) => {
+                let passphrase = self.passphrase.as_ref().ok_or_else(|| {
+                    CroncatError::Config(format!("Agent keystore is locked: {account_id}"))
+                })?;
+                encrypted.decrypt(passphrase)?.parse()?
+            }
+            (None, None) => {
+                return Err(
+                    CroncatError::Config(format!("Agent has no mnemonic: {account_id}")).into(),
+                )
+            }
         };
-        let mnemonic: Mnemonic = entry.mnemonic.parse()?;
         Ok(mnemonic)
     }
 
+    /// Whether the agent's mnemonic is encrypted, and needs a passphrase to unlock.
+    pub fn is_encrypted(&self, account_id: &str) -> bool {
+        matches!(self.get(account_id), Some(entry) if entry.encrypted_mnemonic.is_some())
+    }
+
+    /// Whether any agent in the keystore is encrypted.
+    pub fn has_encrypted(&self) -> bool {
+        self.data
+            .values()
+            .any(|entry| entry.encrypted_mnemonic.is_some())
+    }
+
+    /// Set the passphrase used to unlock encrypted agents.
+    pub fn unlock(&mut self, passphrase: String) {
+        self.passphrase = Some(passphrase);
+    }
+
+    ///
+    /// Encrypt every plain text agent with the passphrase, dropping the plain text
+    /// mnemonic and private key from disk. Returns the agents that were encrypted.
+    ///
+    pub fn encrypt_all(&mut self, passphrase: &str) -> Result<Vec<AccountId>, Report> {
+        let mut encrypted = vec![];
+        for (account_id, entry) in self.data.iter_mut() {
+            if let Some(mnemonic) = entry.mnemonic.take() {
+                entry.encrypted_mnemonic = Some(EncryptedSecret::encrypt(&mnemonic, passphrase)?);
+                entry.keypair.private_key = None;
+                encrypted.push(account_id.clone());
+            }
+        }
+
+        encrypted.sort();
+        if !encrypted.is_empty() {
+            self.write_to_disk(&encrypted)?;
+        }
+
+        Ok(encrypted)
+    }
+
+    ///
+    /// The agent address for a chain prefix, derived from the stored public key
+    /// so encrypted agents don't need to be unlocked.
+    ///
     pub fn get_agent_signing_account_addr(
         &self,
         account_id: &AccountId,
         prefix: String,
     ) -> Result<String, Report> {
-        let key = self.get_agent_signing_key(account_id)?;
-        let signing_key: SigningKey = key.try_into()?;
+        let entry = self
+            .get(account_id)
+            .ok_or_else(|| CroncatError::Config(format!("Agent not found: {account_id}")))?;
+
+        // The public key is stored as an extended key, the key bytes are the compressed point
+        let extended: bip32::ExtendedKey = entry.keypair.public_key.parse()?;
+        let verifying_key = VerifyingKey::from_bytes(extended.key_bytes)?;
 
-        Ok(signing_key
-            .public_key()
+        Ok(PublicKey::from(verifying_key)
             .account_id(prefix.as_str())?
             .to_string())
     }
@@ -203,9 +311,3 @@ impl LocalAgentStorage {
         self.data.get(account_id)
     }
 }
-
-impl Default for LocalAgentStorage {
-    fn default() -> Self {
-        Self::new()
-    }
-}
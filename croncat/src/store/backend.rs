@@ -0,0 +1,138 @@
+//!
+//! Where the agent, factory cache and evented task stores persist their data.
+//! JSON files are the default, `CRONCAT_STORAGE=sqlite` switches to an embedded
+//! SQLite database that imports the JSON files the first time it's opened.
+//!
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use croncat_sdk_tasks::types::TaskInfo;
+use lazy_static::lazy_static;
+
+use crate::errors::{eyre, CroncatError, Report};
+
+use super::{
+    agent::LocalAgentStorageEntry,
+    factory::LocalCacheStorageEntry,
+    json::JsonBackend,
+    sqlite::SqliteBackend,
+    tasks::{CooldownTask, EventType, LocalEventsStorageEntry},
+};
+
+/// Picks the storage backend, `json` or `sqlite`.
+const STORAGE_ENV: &str = "CRONCAT_STORAGE";
+
+lazy_static! {
+    /// Open backends, by storage directory.
+    static ref BACKENDS: Mutex<HashMap<PathBuf, Arc<dyn StorageBackend>>> =
+        Mutex::new(HashMap::new());
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StorageKind {
+    #[default]
+    Json,
+    Sqlite,
+}
+
+impl StorageKind {
+    /// The backend set with `CRONCAT_STORAGE`, JSON files if unset.
+    pub fn from_env() -> Result<Self, Report> {
+        match std::env::var(STORAGE_ENV) {
+            Err(_) => Ok(Self::Json),
+            Ok(kind) => match kind.to_lowercase().as_str() {
+                "" | "json" => Ok(Self::Json),
+                "sqlite" => Ok(Self::Sqlite),
+                _ => Err(eyre!("Unknown {}: {}", STORAGE_ENV, kind)),
+            },
+        }
+    }
+}
+
+///
+/// A change to the evented task cache, besides its expiry. Backends that can update
+/// in place apply these, the rest write out the whole entry they're given alongside.
+///
+#[derive(Debug, Clone, Copy)]
+pub enum EventChange<'a> {
+    /// Tasks were added to an index.
+    PutTasks {
+        kind: EventType,
+        index: u64,
+        tasks: &'a [(String, TaskInfo)],
+    },
+    /// A task was removed from every index, and from cooldown and jail.
+    RemoveTask(&'a str),
+    /// Every index was cleared, cooldown and jail are kept.
+    ClearTasks,
+    /// Indexes up to and including `index`, and the 0th, were cleared.
+    ClearIndexes { kind: EventType, index: u64 },
+    /// A task went into cooldown, or its cooldown moved.
+    PutCooldown(&'a CooldownTask),
+    /// A task was jailed, leaving cooldown.
+    Jail(&'a str),
+}
+
+///
+/// Persistence for the local stores, the stores keep their data in memory
+/// and hand every mutation to the backend.
+///
+pub trait StorageBackend: Send + Sync {
+    fn load_agents(&self) -> Result<HashMap<String, LocalAgentStorageEntry>, Report>;
+
+    /// Save the agents that changed, `agents` holds all of them.
+    fn save_agents(
+        &self,
+        agents: &HashMap<String, LocalAgentStorageEntry>,
+        changed: &[String],
+    ) -> Result<(), Report>;
+
+    fn load_cache(&self, scope: &str) -> Result<Option<LocalCacheStorageEntry>, Report>;
+
+    fn save_cache(&self, scope: &str, entry: &LocalCacheStorageEntry) -> Result<(), Report>;
+
+    fn load_events(&self, scope: &str) -> Result<Option<LocalEventsStorageEntry>, Report>;
+
+    /// Save changes to the evented task cache, `entry` is the cache once they're applied.
+    fn save_events(
+        &self,
+        scope: &str,
+        entry: &LocalEventsStorageEntry,
+        changes: &[EventChange],
+    ) -> Result<(), Report>;
+}
+
+/// Open a backend of the given kind for a storage directory.
+pub fn open(kind: StorageKind, path: &Path) -> Result<Arc<dyn StorageBackend>, Report> {
+    Ok(match kind {
+        StorageKind::Json => Arc::new(JsonBackend::new(path.to_path_buf())),
+        StorageKind::Sqlite => Arc::new(SqliteBackend::open(path)?),
+    })
+}
+
+///
+/// The backend for a storage directory, picked with `CRONCAT_STORAGE`.
+/// Stores sharing a directory share the backend.
+///
+pub fn for_path(path: &Path) -> Result<Arc<dyn StorageBackend>, Report> {
+    let mut backends = BACKENDS.lock().unwrap();
+    if let Some(backend) = backends.get(path) {
+        return Ok(backend.clone());
+    }
+
+    let backend = StorageKind::from_env()
+        .and_then(|kind| open(kind, path))
+        .map_err(|err| {
+            CroncatError::Config(format!(
+                "Failed to open storage at {}: {}",
+                path.display(),
+                err
+            ))
+        })?;
+    backends.insert(path.to_path_buf(), backend.clone());
+    Ok(backend)
+}
@@ -0,0 +1,161 @@
+//!
+//! Daily rewards and fees for a chain, so we can tell whether running
+//! an agent there pays off.
+//!
+
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::PathBuf};
+
+use crate::errors::{CroncatError, Report};
+
+use super::{
+    file::{read_json, write_json},
+    get_storage_path,
+};
+
+/// Where our [`LocalEarningsStorage`] will be stored.
+const LOCAL_STORAGE_FILENAME: &str = "earnings.json";
+
+/// What the agent earned and spent in a UTC day.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DayEarnings {
+    /// Rewards accrued in the manager contract.
+    pub rewards: u128,
+    /// Rewards withdrawn to the payable account.
+    pub withdrawn: u128,
+    /// Fees paid for every transaction.
+    pub fees: u128,
+    /// The part of `fees` paid for proxy calls.
+    pub proxy_fees: u128,
+    pub proxy_txs: u64,
+}
+
+impl DayEarnings {
+    /// Rewards less every fee paid.
+    pub fn net(&self) -> i128 {
+        self.rewards as i128 - self.fees as i128
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct LocalEarningsStorageEntry {
+    /// Reward balance last seen in the manager contract.
+    pub last_balance: Option<u128>,
+    pub days: BTreeMap<String, DayEarnings>,
+}
+
+/// Store the earnings on disk and allow access to the data.
+pub struct LocalEarningsStorage {
+    pub path: PathBuf,
+    data: LocalEarningsStorageEntry,
+}
+
+impl LocalEarningsStorage {
+    /// Create a new [`LocalEarningsStorage`] instance for the chain in the default directory.
+    pub fn new(chain_id: &str) -> Result<Self, Report> {
+        Self::from_path(get_storage_path().join(chain_id))
+    }
+
+    /// Create a [`LocalEarningsStorage`] instance at a specified path,
+    /// if the data already exists at the directory we load it.
+    pub fn from_path(path: PathBuf) -> Result<Self, Report> {
+        let data_file = path.join(LOCAL_STORAGE_FILENAME);
+
+        let data = read_json(&data_file)
+            .map_err(|err| CroncatError::Config(format!("Failed to load earnings: {err}")))?
+            .unwrap_or_default();
+
+        Ok(Self { path, data })
+    }
+
+    /// Earnings by day, oldest first.
+    pub fn days(&self) -> &BTreeMap<String, DayEarnings> {
+        &self.data.days
+    }
+
+    ///
+    /// Record the reward balance the agent has in the manager contract,
+    /// any increase since the last one counts as rewards for the day.
+    ///
+    pub fn record_balance(&mut self, day: &str, balance: u128) -> Result<(), Report> {
+        let accrued = match self.data.last_balance {
+            Some(last) => balance.saturating_sub(last),
+            None => 0,
+        };
+        self.data.last_balance = Some(balance);
+        if accrued > 0 {
+            self.day(day).rewards += accrued;
+        }
+        self.write_to_disk()
+    }
+
+    ///
+    /// Record rewards withdrawn from the manager contract, anything accrued
+    /// since the balance was last seen counts as rewards too.
+    ///
+    pub fn record_withdrawal(&mut self, day: &str, amount: u128) -> Result<(), Report> {
+        let accrued = amount.saturating_sub(self.data.last_balance.unwrap_or(amount));
+        self.data.last_balance = Some(0);
+        let earnings = self.day(day);
+        earnings.rewards += accrued;
+        earnings.withdrawn += amount;
+        self.write_to_disk()
+    }
+
+    /// Record the fee paid for a transaction.
+    pub fn record_fee(&mut self, day: &str, fee: u128, proxy_call: bool) -> Result<(), Report> {
+        let earnings = self.day(day);
+        earnings.fees += fee;
+        if proxy_call {
+            earnings.proxy_fees += fee;
+            earnings.proxy_txs += 1;
+        }
+        self.write_to_disk()
+    }
+
+    fn day(&mut self, day: &str) -> &mut DayEarnings {
+        self.data.days.entry(day.to_string()).or_default()
+    }
+
+    /// Write our data to disk at the specified location.
+    fn write_to_disk(&self) -> Result<(), Report> {
+        write_json(&self.path.join(LOCAL_STORAGE_FILENAME), &self.data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accrues_rewards_from_balance_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut storage = LocalEarningsStorage::from_path(dir.path().to_path_buf()).unwrap();
+
+        // The first balance is where we start counting from
+        storage.record_balance("2023-03-01", 100).unwrap();
+        storage.record_balance("2023-03-01", 150).unwrap();
+        storage.record_fee("2023-03-01", 20, true).unwrap();
+        // 30 more accrued before withdrawing all 180
+        storage.record_withdrawal("2023-03-01", 180).unwrap();
+        storage.record_balance("2023-03-02", 40).unwrap();
+        storage.record_fee("2023-03-02", 5, false).unwrap();
+
+        let days = LocalEarningsStorage::from_path(dir.path().to_path_buf())
+            .unwrap()
+            .days()
+            .clone();
+        assert_eq!(
+            days["2023-03-01"],
+            DayEarnings {
+                rewards: 80,
+                withdrawn: 180,
+                fees: 20,
+                proxy_fees: 20,
+                proxy_txs: 1,
+            }
+        );
+        assert_eq!(days["2023-03-02"].rewards, 40);
+        assert_eq!(days["2023-03-02"].net(), 35);
+    }
+}
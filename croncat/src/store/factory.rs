@@ -2,12 +2,12 @@ use chrono::Utc;
 use color_eyre::{eyre::eyre, Report};
 use croncat_sdk_factory::msg::ContractMetadataInfo;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fs, path::PathBuf};
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
 
-use super::get_storage_path;
-
-/// Where our [`LocalCacheStorage`] will be stored.
-const LOCAL_STORAGE_FILENAME: &str = "cache.json";
+use super::{
+    backend::{self, StorageBackend},
+    get_storage_path,
+};
 
 /// Store the factory data cache
 #[derive(Serialize, Deserialize, Clone)]
@@ -32,71 +32,53 @@ pub struct LocalCacheStorage {
     pub path: PathBuf,
     pub path_prefix: Option<String>,
     data: Option<LocalCacheStorageEntry>,
+    backend: Arc<dyn StorageBackend>,
 }
 
 impl LocalCacheStorage {
     /// Create a new [`LocalCacheStorage`] instance with the default directory.
-    pub fn new(path_prefix: Option<String>) -> Self {
+    pub fn new(path_prefix: Option<String>) -> Result<Self, Report> {
         let p = get_storage_path();
-        Self {
+        Ok(Self {
+            backend: backend::for_path(&p)?,
             path: p,
             path_prefix,
             data: None,
-        }
+        })
     }
 
     /// Create a [`LocalCacheStorage`] instance at a specified path,
     /// if the data already exists at the directory we load it.
-    pub fn from_path(&self, path: PathBuf) -> Self {
-        let data_file = path
-            .join(self.path_prefix.clone().unwrap_or_default())
-            .join(LOCAL_STORAGE_FILENAME);
+    pub fn from_path(&self, path: PathBuf) -> Result<Self, Report> {
+        let backend = backend::for_path(&path)?;
+        let data = backend
+            .load_cache(&self.path_prefix.clone().unwrap_or_default())
+            .map_err(|err| eyre!("Failed to load factory cache: {}", err))?;
+        Ok(Self {
+            path,
+            path_prefix: self.path_prefix.clone(),
+            data,
+            backend,
+        })
+    }
 
-        // Load from the agent data file if it exists
-        if data_file.exists() {
-            let json_data = fs::read_to_string(data_file).unwrap();
-            let data =
-                serde_json::from_str(json_data.as_str()).expect("Failed to parse agent JSON data");
-            Self {
-                path,
-                path_prefix: self.path_prefix.clone(),
-                data,
-            }
+    /// Write any loaded data to disk, nothing to do if none was loaded.
+    pub fn flush(&self) -> Result<(), Report> {
+        if self.data.is_some() {
+            self.write_to_disk()
         } else {
-            // Otherwise create a new hashmap
-            Self {
-                path,
-                path_prefix: self.path_prefix.clone(),
-                data: None,
-            }
+            Ok(())
         }
     }
 
-    /// Write our data to disk at the specified location.
+    /// Hand our data to the storage backend.
     pub fn write_to_disk(&self) -> Result<(), Report> {
-        if self.data.is_none() {
-            return Err(eyre!("No factory data to write"));
-        }
-        let data_file = self
-            .path
-            .join(self.path_prefix.clone().unwrap_or_default())
-            .join(LOCAL_STORAGE_FILENAME);
-
-        // Create the directory to store our data if it doesn't exist
-        if let Some(p) = data_file.parent() {
-            fs::create_dir_all(p)?
-        };
-
-        let r = fs::write(
-            data_file,
-            serde_json::to_string_pretty(&self.data.clone().unwrap())?,
-        );
-
-        if r.is_ok() {
-            Ok(())
-        } else {
-            Err(eyre!(r.unwrap_err()))
-        }
+        let data = self
+            .data
+            .as_ref()
+            .ok_or_else(|| eyre!("No factory data to write"))?;
+        self.backend
+            .save_cache(&self.path_prefix.clone().unwrap_or_default(), data)
     }
 
     /// Insert a item into the data map.
@@ -165,9 +147,3 @@ impl LocalCacheStorage {
         }
     }
 }
-
-impl Default for LocalCacheStorage {
-    fn default() -> Self {
-        Self::new(None)
-    }
-}
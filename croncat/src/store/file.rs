@@ -0,0 +1,158 @@
+//!
+//! Crash-safe JSON files. Writes go to a temp file that's synced and renamed over
+//! the live one, keeping the previous version as a `.bak` to recover from. The
+//! backup is swapped in by a rename as well, so a crash never leaves it torn.
+//!
+
+use std::{
+    ffi::OsString,
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::error;
+
+use crate::errors::{eyre, Report};
+
+/// `<file>.<extension>`, next to the file.
+fn sibling(path: &Path, extension: &str) -> PathBuf {
+    let mut name = path.file_name().map(OsString::from).unwrap_or_default();
+    name.push(".");
+    name.push(extension);
+    path.with_file_name(name)
+}
+
+/// The previous version of a file.
+pub fn backup_path(path: &Path) -> PathBuf {
+    sibling(path, "bak")
+}
+
+///
+/// Replace a file without ever leaving it half written. The new contents are
+/// synced to a temp file first, the current file becomes the backup, then the
+/// temp file is renamed into place.
+///
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), Report> {
+    // Create the directory to store our data if it doesn't exist
+    let dir = path
+        .parent()
+        .ok_or_else(|| eyre!("No directory for {}", path.display()))?;
+    fs::create_dir_all(dir)?;
+
+    if path.exists() {
+        backup(path)?;
+    }
+
+    let tmp = sibling(path, "tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&tmp, path)?;
+
+    // Make the rename itself durable
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+
+    Ok(())
+}
+
+///
+/// Make the current version of a file its backup. The file is hard linked in, it's
+/// replaced rather than written to so the link keeps the old contents. Where links
+/// aren't supported it's copied and synced instead. Either way the new backup only
+/// replaces the old one by a rename.
+///
+fn backup(path: &Path) -> Result<(), Report> {
+    let tmp = sibling(path, "bak.tmp");
+    // Left over from a crash while taking the backup
+    if tmp.exists() {
+        fs::remove_file(&tmp)?;
+    }
+    if fs::hard_link(path, &tmp).is_err() {
+        fs::copy(path, &tmp)?;
+        File::open(&tmp)?.sync_all()?;
+    }
+    fs::rename(&tmp, backup_path(path))?;
+
+    Ok(())
+}
+
+/// Write a value as pretty JSON with [`write_atomic`].
+pub fn write_json<T: Serialize>(path: &Path, data: &T) -> Result<(), Report> {
+    write_atomic(path, serde_json::to_string_pretty(data)?.as_bytes())
+}
+
+fn parse_json<T: DeserializeOwned>(path: &Path) -> Result<T, Report> {
+    let json_data = fs::read_to_string(path)?;
+    serde_json::from_str(json_data.as_str())
+        .map_err(|err| eyre!("Failed to parse {}: {}", path.display(), err))
+}
+
+///
+/// Read a JSON file, `None` if it doesn't exist. A file that can't be read falls
+/// back to its backup, which is put back in place and the broken file kept as `.corrupt`.
+///
+pub fn read_json<T: DeserializeOwned>(path: &Path) -> Result<Option<T>, Report> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let err = match parse_json(path) {
+        Ok(data) => return Ok(Some(data)),
+        Err(err) => err,
+    };
+
+    let backup = backup_path(path);
+    if !backup.exists() {
+        return Err(err);
+    }
+    let data = parse_json(&backup).map_err(|backup_err| {
+        eyre!(
+            "{}, and the backup can't be read either: {}",
+            err,
+            backup_err
+        )
+    })?;
+
+    let corrupt = sibling(path, "corrupt");
+    error!(
+        "{}! Recovered the previous version from {}, the broken file is kept at {}",
+        err,
+        backup.display(),
+        corrupt.display()
+    );
+    fs::rename(path, &corrupt)?;
+    write_atomic(path, &fs::read(&backup)?)?;
+
+    Ok(Some(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recovers_from_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("agents.json");
+
+        write_json(&path, &vec![1]).unwrap();
+        write_json(&path, &vec![1, 2]).unwrap();
+        assert_eq!(
+            read_json::<Vec<u32>>(&backup_path(&path)).unwrap(),
+            Some(vec![1])
+        );
+
+        // A torn write leaves the live file unreadable
+        fs::write(&path, "[1, 2").unwrap();
+        assert_eq!(read_json::<Vec<u32>>(&path).unwrap(), Some(vec![1]));
+        assert_eq!(read_json::<Vec<u32>>(&path).unwrap(), Some(vec![1]));
+        assert!(sibling(&path, "corrupt").exists());
+        assert_eq!(
+            read_json::<Vec<u32>>(&dir.path().join("missing.json")).unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,92 @@
+//!
+//! The JSON file backend, every save writes out the whole file with [`write_json`].
+//!
+
+use std::{collections::HashMap, fs, path::PathBuf};
+
+use crate::errors::Report;
+
+use super::{
+    agent::LocalAgentStorageEntry,
+    backend::{EventChange, StorageBackend},
+    factory::LocalCacheStorageEntry,
+    file::{read_json, write_json},
+    tasks::LocalEventsStorageEntry,
+};
+
+/// Where agents are stored, in the storage directory.
+const AGENTS_FILENAME: &str = "agents.json";
+
+/// Where the factory cache is stored, in the chain's directory.
+const CACHE_FILENAME: &str = "cache.json";
+
+/// Where the evented task cache is stored, in the chain's directory.
+const EVENTS_FILENAME: &str = "events.json";
+
+pub struct JsonBackend {
+    pub path: PathBuf,
+}
+
+impl JsonBackend {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Chain directories holding a factory or evented task cache.
+    pub fn scopes(&self) -> Result<Vec<String>, Report> {
+        if !self.path.exists() {
+            return Ok(vec![]);
+        }
+
+        let mut scopes = vec![];
+        for entry in fs::read_dir(&self.path)? {
+            let entry = entry?;
+            let has_cache = [CACHE_FILENAME, EVENTS_FILENAME]
+                .iter()
+                .any(|filename| entry.path().join(filename).exists());
+            if has_cache {
+                if let Some(scope) = entry.file_name().to_str() {
+                    scopes.push(scope.to_string());
+                }
+            }
+        }
+        scopes.sort();
+
+        Ok(scopes)
+    }
+}
+
+impl StorageBackend for JsonBackend {
+    fn load_agents(&self) -> Result<HashMap<String, LocalAgentStorageEntry>, Report> {
+        Ok(read_json(&self.path.join(AGENTS_FILENAME))?.unwrap_or_default())
+    }
+
+    fn save_agents(
+        &self,
+        agents: &HashMap<String, LocalAgentStorageEntry>,
+        _changed: &[String],
+    ) -> Result<(), Report> {
+        write_json(&self.path.join(AGENTS_FILENAME), agents)
+    }
+
+    fn load_cache(&self, scope: &str) -> Result<Option<LocalCacheStorageEntry>, Report> {
+        read_json(&self.path.join(scope).join(CACHE_FILENAME))
+    }
+
+    fn save_cache(&self, scope: &str, entry: &LocalCacheStorageEntry) -> Result<(), Report> {
+        write_json(&self.path.join(scope).join(CACHE_FILENAME), entry)
+    }
+
+    fn load_events(&self, scope: &str) -> Result<Option<LocalEventsStorageEntry>, Report> {
+        read_json(&self.path.join(scope).join(EVENTS_FILENAME))
+    }
+
+    fn save_events(
+        &self,
+        scope: &str,
+        entry: &LocalEventsStorageEntry,
+        _changes: &[EventChange],
+    ) -> Result<(), Report> {
+        write_json(&self.path.join(scope).join(EVENTS_FILENAME), entry)
+    }
+}
@@ -0,0 +1,147 @@
+//!
+//! Passphrase encryption for agent secrets, scrypt derives the key and
+//! ChaCha20-Poly1305 seals the secret.
+//!
+
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use rand::{rngs::OsRng, RngCore};
+use scrypt::Params;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::{eyre, CroncatError, Report};
+
+/// The key derivation function name stored alongside the secret.
+const KDF_SCRYPT: &str = "scrypt";
+
+/// The cipher name stored alongside the secret.
+const CIPHER_CHACHA20POLY1305: &str = "chacha20poly1305";
+
+/// scrypt cost, 2^15 iterations takes well under a second.
+const SCRYPT_LOG_N: u8 = 15;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+
+///
+/// A secret sealed with a passphrase, everything needed to open it again
+/// besides the passphrase is stored with it.
+///
+#[derive(Serialize, Deserialize, Clone)]
+pub struct EncryptedSecret {
+    pub kdf: String,
+    pub log_n: u8,
+    pub r: u32,
+    pub p: u32,
+    pub salt: String,
+    pub cipher: String,
+    pub nonce: String,
+    pub ciphertext: String,
+}
+
+/// Hide the ciphertext when debug printing.
+impl std::fmt::Debug for EncryptedSecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncryptedSecret")
+            .field("kdf", &self.kdf)
+            .field("cipher", &self.cipher)
+            .finish()
+    }
+}
+
+impl EncryptedSecret {
+    /// Seal the secret with a key derived from the passphrase.
+    pub fn encrypt(secret: &str, passphrase: &str) -> Result<Self, Report> {
+        Self::encrypt_with_cost(secret, passphrase, SCRYPT_LOG_N)
+    }
+
+    fn encrypt_with_cost(secret: &str, passphrase: &str, log_n: u8) -> Result<Self, Report> {
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut salt);
+        OsRng.fill_bytes(&mut nonce);
+
+        let key = derive_key(passphrase, &salt, log_n, SCRYPT_R, SCRYPT_P)?;
+        let ciphertext = ChaCha20Poly1305::new(Key::from_slice(&key))
+            .encrypt(Nonce::from_slice(&nonce), secret.as_bytes())
+            .map_err(|_| eyre!("Failed to encrypt agent secret"))?;
+
+        Ok(Self {
+            kdf: KDF_SCRYPT.to_string(),
+            log_n,
+            r: SCRYPT_R,
+            p: SCRYPT_P,
+            salt: hex::encode(salt),
+            cipher: CIPHER_CHACHA20POLY1305.to_string(),
+            nonce: hex::encode(nonce),
+            ciphertext: hex::encode(ciphertext),
+        })
+    }
+
+    /// Open the secret, a wrong passphrase fails authentication.
+    pub fn decrypt(&self, passphrase: &str) -> Result<String, Report> {
+        if self.kdf != KDF_SCRYPT || self.cipher != CIPHER_CHACHA20POLY1305 {
+            return Err(CroncatError::Config(format!(
+                "Unsupported keystore format: {} / {}",
+                self.kdf, self.cipher
+            ))
+            .into());
+        }
+
+        let salt = hex::decode(&self.salt)?;
+        let nonce = hex::decode(&self.nonce)?;
+        let ciphertext = hex::decode(&self.ciphertext)?;
+        if nonce.len() != NONCE_LEN {
+            return Err(CroncatError::Config("Invalid keystore nonce".to_string()).into());
+        }
+
+        let key = derive_key(passphrase, &salt, self.log_n, self.r, self.p)?;
+        let secret = ChaCha20Poly1305::new(Key::from_slice(&key))
+            .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+            .map_err(|_| {
+                CroncatError::Config("Failed to unlock agent keystore, wrong passphrase?".into())
+            })?;
+
+        Ok(String::from_utf8(secret)?)
+    }
+}
+
+fn derive_key(
+    passphrase: &str,
+    salt: &[u8],
+    log_n: u8,
+    r: u32,
+    p: u32,
+) -> Result<[u8; KEY_LEN], Report> {
+    let params = Params::new(log_n, r, p).map_err(|err| eyre!("Invalid scrypt params: {}", err))?;
+    let mut key = [0u8; KEY_LEN];
+    scrypt::scrypt(passphrase.as_bytes(), salt, &params, &mut key)
+        .map_err(|err| eyre!("Failed to derive keystore key: {}", err))?;
+
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_encrypt_and_decrypt() {
+        let secret = "fiscal abuse awful amused strategy";
+        // Cheap cost, so the test stays fast
+        let sealed = EncryptedSecret::encrypt_with_cost(secret, "hunter2", 4).unwrap();
+        assert_ne!(sealed.ciphertext, hex::encode(secret));
+        assert_eq!(sealed.decrypt("hunter2").unwrap(), secret);
+
+        let err = sealed.decrypt("hunter3").unwrap_err();
+        assert!(matches!(
+            CroncatError::classify(&err),
+            CroncatError::Config(_)
+        ));
+    }
+}
@@ -0,0 +1,116 @@
+//!
+//! Advisory lock files, so only one process runs an agent on a chain
+//! against the same storage directory.
+//!
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+    process,
+};
+
+use fs2::FileExt;
+
+use crate::errors::{CroncatError, Report};
+
+use super::get_storage_path;
+
+///
+/// Held for as long as the process works with an agent on a chain. The OS lock
+/// goes with the open file, so it's released on drop or when the process exits.
+///
+#[derive(Debug)]
+pub struct StorageLock {
+    pub path: PathBuf,
+    file: File,
+}
+
+impl StorageLock {
+    /// Lock the agent on a chain in the default directory.
+    pub fn acquire(agent_id: &str, chain_id: &str) -> Result<Self, Report> {
+        let path = get_storage_path()
+            .join(chain_id)
+            .join(format!("{agent_id}.lock"));
+        Self::take(path, &format!("Agent {agent_id} on {chain_id}"))
+    }
+
+    ///
+    /// Take an exclusive lock on the lock file, then write our PID to it. The PID
+    /// is only there to tell whoever finds the lock taken who holds it.
+    ///
+    fn take(path: PathBuf, holder: &str) -> Result<Self, Report> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        // Don't truncate, the PID belongs to the holder until we have the lock
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        if let Err(err) = file.try_lock_exclusive() {
+            if err.kind() != fs2::lock_contended_error().kind() {
+                return Err(err.into());
+            }
+            let mut pid = String::new();
+            file.read_to_string(&mut pid)?;
+            let pid = match pid.trim() {
+                "" => "another process".to_string(),
+                pid => format!("PID {pid}"),
+            };
+            return Err(CroncatError::Config(format!(
+                "{holder} is already running as {pid}, holding the lock at {}",
+                path.display()
+            ))
+            .into());
+        }
+
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        writeln!(file, "{}", process::id())?;
+        file.sync_all()?;
+
+        Ok(Self { path, file })
+    }
+}
+
+impl Drop for StorageLock {
+    ///
+    /// Clear our PID but leave the file, removing it could let one process lock
+    /// the old file while another creates and locks a new one.
+    ///
+    fn drop(&mut self) {
+        let _ = self.file.set_len(0);
+        let _ = self.file.unlock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_one_holder() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("agent.lock");
+
+        // A lock file left behind by a process that's gone isn't held
+        fs::write(&path, "4294967295\n").unwrap();
+        let lock = StorageLock::take(path.clone(), "Agent").unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!("{}\n", process::id())
+        );
+
+        let err = StorageLock::take(path.clone(), "Agent").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains(&format!("already running as PID {}", process::id())));
+
+        drop(lock);
+        StorageLock::take(path, "Agent").unwrap();
+    }
+}
@@ -6,9 +6,19 @@ use std::path::PathBuf;
 pub const LOCAL_STORAGE_DEFAULT_DIR: &str = "/.croncatd";
 
 pub mod agent;
+pub mod backend;
+pub mod earnings;
 pub mod factory;
+pub mod file;
+pub mod json;
+pub mod keystore;
+pub mod lock;
 pub mod logs;
+pub mod sqlite;
 pub mod tasks;
+pub mod treasury;
+pub mod txs;
+pub mod unregistered;
 
 pub fn get_storage_path() -> PathBuf {
     let mut home = std::env::var("HOME").unwrap();
@@ -0,0 +1,462 @@
+//!
+//! The embedded SQLite backend. Saves run in a transaction and only touch
+//! the rows that changed, evented tasks get a row each so the cache can be queried.
+//!
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+    sync::Mutex,
+};
+
+use croncat_sdk_tasks::types::TaskInfo;
+use rusqlite::{params, Connection, OptionalExtension};
+use tracing::info;
+
+use crate::errors::Report;
+
+use super::{
+    agent::LocalAgentStorageEntry,
+    backend::{EventChange, StorageBackend},
+    factory::LocalCacheStorageEntry,
+    json::JsonBackend,
+    tasks::{CooldownTask, EventType, LocalEventsStorageEntry},
+};
+
+/// The database file, in the storage directory.
+const DATABASE_FILENAME: &str = "croncat.db";
+
+/// Bumped with every schema change, 0 is a new database.
+const SCHEMA_VERSION: i64 = 1;
+
+const SCHEMA: &str = "
+    PRAGMA journal_mode = WAL;
+    CREATE TABLE IF NOT EXISTS agents (
+        account_id TEXT PRIMARY KEY,
+        entry TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS factory_cache (
+        scope TEXT PRIMARY KEY,
+        entry TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS event_meta (
+        scope TEXT PRIMARY KEY,
+        expires INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS event_tasks (
+        scope TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        idx INTEGER NOT NULL,
+        task_hash TEXT NOT NULL,
+        task TEXT NOT NULL,
+        PRIMARY KEY (scope, kind, idx, task_hash)
+    );
+    CREATE INDEX IF NOT EXISTS event_tasks_by_hash ON event_tasks (scope, task_hash);
+    CREATE TABLE IF NOT EXISTS cooldown_tasks (
+        scope TEXT NOT NULL,
+        task_hash TEXT NOT NULL,
+        idx INTEGER NOT NULL,
+        expires INTEGER NOT NULL,
+        PRIMARY KEY (scope, task_hash)
+    );
+    CREATE TABLE IF NOT EXISTS jailed_tasks (
+        scope TEXT NOT NULL,
+        task_hash TEXT NOT NULL,
+        PRIMARY KEY (scope, task_hash)
+    );
+";
+
+pub struct SqliteBackend {
+    conn: Mutex<Connection>,
+}
+
+fn kind_name(kind: &EventType) -> &'static str {
+    match kind {
+        EventType::Block => "block",
+        EventType::Time => "time",
+    }
+}
+
+impl SqliteBackend {
+    ///
+    /// Open the database in a storage directory, creating it if needed.
+    /// A new database imports the JSON files already in the directory.
+    ///
+    pub fn open(path: &Path) -> Result<Self, Report> {
+        fs::create_dir_all(path)?;
+        let mut conn = Connection::open(path.join(DATABASE_FILENAME))?;
+        conn.execute_batch(SCHEMA)?;
+
+        let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version == 0 {
+            let tx = conn.transaction()?;
+            migrate_json(&tx, &JsonBackend::new(path.to_path_buf()))?;
+            tx.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+            tx.commit()?;
+        }
+
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+}
+
+/// Import everything the JSON backend stored, the files are left in place.
+fn migrate_json(conn: &Connection, json: &JsonBackend) -> Result<(), Report> {
+    let agents = json.load_agents()?;
+    let account_ids: Vec<String> = agents.keys().cloned().collect();
+    put_agents(conn, &agents, &account_ids)?;
+
+    let scopes = json.scopes()?;
+    for scope in &scopes {
+        if let Some(entry) = json.load_cache(scope)? {
+            put_cache(conn, scope, &entry)?;
+        }
+        if let Some(entry) = json.load_events(scope)? {
+            put_events(conn, scope, &entry)?;
+        }
+    }
+
+    if !agents.is_empty() || !scopes.is_empty() {
+        info!(
+            "Imported {} agents and {} chain caches from JSON storage",
+            agents.len(),
+            scopes.len()
+        );
+    }
+
+    Ok(())
+}
+
+fn put_agents(
+    conn: &Connection,
+    agents: &HashMap<String, LocalAgentStorageEntry>,
+    account_ids: &[String],
+) -> Result<(), Report> {
+    for account_id in account_ids {
+        match agents.get(account_id) {
+            Some(entry) => conn.execute(
+                "INSERT INTO agents (account_id, entry) VALUES (?1, ?2)
+                 ON CONFLICT (account_id) DO UPDATE SET entry = excluded.entry",
+                params![account_id, serde_json::to_string(entry)?],
+            )?,
+            None => conn.execute(
+                "DELETE FROM agents WHERE account_id = ?1",
+                params![account_id],
+            )?,
+        };
+    }
+    Ok(())
+}
+
+fn put_cache(conn: &Connection, scope: &str, entry: &LocalCacheStorageEntry) -> Result<(), Report> {
+    conn.execute(
+        "INSERT INTO factory_cache (scope, entry) VALUES (?1, ?2)
+         ON CONFLICT (scope) DO UPDATE SET entry = excluded.entry",
+        params![scope, serde_json::to_string(entry)?],
+    )?;
+    Ok(())
+}
+
+/// Replace a scope's evented task cache with the whole entry.
+fn put_events(
+    conn: &Connection,
+    scope: &str,
+    entry: &LocalEventsStorageEntry,
+) -> Result<(), Report> {
+    put_expires(conn, scope, entry.expires)?;
+    conn.execute("DELETE FROM event_tasks WHERE scope = ?1", params![scope])?;
+    conn.execute(
+        "DELETE FROM cooldown_tasks WHERE scope = ?1",
+        params![scope],
+    )?;
+    conn.execute("DELETE FROM jailed_tasks WHERE scope = ?1", params![scope])?;
+
+    for (kind, indexes) in [
+        (EventType::Block, &entry.height_based),
+        (EventType::Time, &entry.time_based),
+    ] {
+        for (index, tasks) in indexes {
+            let tasks: Vec<_> = tasks.clone().into_iter().collect();
+            put_tasks(conn, scope, &kind, *index, &tasks)?;
+        }
+    }
+    for cooldown in &entry.cooldown_tasks {
+        put_cooldown(conn, scope, cooldown)?;
+    }
+    for task_hash in &entry.jailed_tasks {
+        jail(conn, scope, task_hash)?;
+    }
+
+    Ok(())
+}
+
+fn put_expires(conn: &Connection, scope: &str, expires: i64) -> Result<(), Report> {
+    conn.execute(
+        "INSERT INTO event_meta (scope, expires) VALUES (?1, ?2)
+         ON CONFLICT (scope) DO UPDATE SET expires = excluded.expires",
+        params![scope, expires],
+    )?;
+    Ok(())
+}
+
+fn put_tasks(
+    conn: &Connection,
+    scope: &str,
+    kind: &EventType,
+    index: u64,
+    tasks: &[(String, TaskInfo)],
+) -> Result<(), Report> {
+    let mut stmt = conn.prepare_cached(
+        "INSERT INTO event_tasks (scope, kind, idx, task_hash, task) VALUES (?1, ?2, ?3, ?4, ?5)
+         ON CONFLICT (scope, kind, idx, task_hash) DO UPDATE SET task = excluded.task",
+    )?;
+    for (task_hash, task) in tasks {
+        stmt.execute(params![
+            scope,
+            kind_name(kind),
+            index as i64,
+            task_hash,
+            serde_json::to_string(task)?
+        ])?;
+    }
+    Ok(())
+}
+
+fn put_cooldown(conn: &Connection, scope: &str, cooldown: &CooldownTask) -> Result<(), Report> {
+    conn.execute(
+        "INSERT INTO cooldown_tasks (scope, task_hash, idx, expires) VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT (scope, task_hash) DO UPDATE SET idx = excluded.idx, expires = excluded.expires",
+        params![scope, cooldown.task_hash, cooldown.index, cooldown.expires],
+    )?;
+    Ok(())
+}
+
+fn jail(conn: &Connection, scope: &str, task_hash: &str) -> Result<(), Report> {
+    conn.execute(
+        "DELETE FROM cooldown_tasks WHERE scope = ?1 AND task_hash = ?2",
+        params![scope, task_hash],
+    )?;
+    conn.execute(
+        "INSERT OR IGNORE INTO jailed_tasks (scope, task_hash) VALUES (?1, ?2)",
+        params![scope, task_hash],
+    )?;
+    Ok(())
+}
+
+fn apply_event_change(conn: &Connection, scope: &str, change: &EventChange) -> Result<(), Report> {
+    match change {
+        EventChange::PutTasks { kind, index, tasks } => {
+            put_tasks(conn, scope, kind, *index, tasks)?;
+        }
+        EventChange::RemoveTask(task_hash) => {
+            for table in ["event_tasks", "cooldown_tasks", "jailed_tasks"] {
+                conn.execute(
+                    &format!("DELETE FROM {table} WHERE scope = ?1 AND task_hash = ?2"),
+                    params![scope, task_hash],
+                )?;
+            }
+        }
+        EventChange::ClearTasks => {
+            conn.execute("DELETE FROM event_tasks WHERE scope = ?1", params![scope])?;
+        }
+        EventChange::ClearIndexes { kind, index } => {
+            conn.execute(
+                "DELETE FROM event_tasks WHERE scope = ?1 AND kind = ?2 AND (idx <= ?3 OR idx = 0)",
+                params![scope, kind_name(kind), *index as i64],
+            )?;
+        }
+        EventChange::PutCooldown(cooldown) => put_cooldown(conn, scope, cooldown)?,
+        EventChange::Jail(task_hash) => jail(conn, scope, task_hash)?,
+    }
+    Ok(())
+}
+
+impl StorageBackend for SqliteBackend {
+    fn load_agents(&self) -> Result<HashMap<String, LocalAgentStorageEntry>, Report> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT account_id, entry FROM agents")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+
+        let mut agents = HashMap::new();
+        for row in rows {
+            let (account_id, entry) = row?;
+            agents.insert(account_id, serde_json::from_str(&entry)?);
+        }
+        Ok(agents)
+    }
+
+    fn save_agents(
+        &self,
+        agents: &HashMap<String, LocalAgentStorageEntry>,
+        changed: &[String],
+    ) -> Result<(), Report> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        put_agents(&tx, agents, changed)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn load_cache(&self, scope: &str) -> Result<Option<LocalCacheStorageEntry>, Report> {
+        let conn = self.conn.lock().unwrap();
+        let entry: Option<String> = conn
+            .query_row(
+                "SELECT entry FROM factory_cache WHERE scope = ?1",
+                params![scope],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(entry
+            .map(|entry| serde_json::from_str(&entry))
+            .transpose()?)
+    }
+
+    fn save_cache(&self, scope: &str, entry: &LocalCacheStorageEntry) -> Result<(), Report> {
+        let conn = self.conn.lock().unwrap();
+        put_cache(&conn, scope, entry)
+    }
+
+    fn load_events(&self, scope: &str) -> Result<Option<LocalEventsStorageEntry>, Report> {
+        let conn = self.conn.lock().unwrap();
+        let expires: Option<i64> = conn
+            .query_row(
+                "SELECT expires FROM event_meta WHERE scope = ?1",
+                params![scope],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(expires) = expires else {
+            return Ok(None);
+        };
+
+        let mut height_based: BTreeMap<u64, HashMap<String, _>> = BTreeMap::new();
+        let mut time_based: BTreeMap<u64, HashMap<String, _>> = BTreeMap::new();
+        let mut stmt =
+            conn.prepare("SELECT kind, idx, task_hash, task FROM event_tasks WHERE scope = ?1")?;
+        let rows = stmt.query_map(params![scope], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?;
+        for row in rows {
+            let (kind, index, task_hash, task) = row?;
+            let indexes = if kind == kind_name(&EventType::Time) {
+                &mut time_based
+            } else {
+                &mut height_based
+            };
+            indexes
+                .entry(index as u64)
+                .or_default()
+                .insert(task_hash, serde_json::from_str(&task)?);
+        }
+
+        let mut stmt = conn.prepare(
+            "SELECT task_hash, idx, expires FROM cooldown_tasks WHERE scope = ?1 ORDER BY rowid",
+        )?;
+        let cooldown_tasks = stmt
+            .query_map(params![scope], |row| {
+                Ok(CooldownTask {
+                    task_hash: row.get(0)?,
+                    index: row.get(1)?,
+                    expires: row.get(2)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut stmt =
+            conn.prepare("SELECT task_hash FROM jailed_tasks WHERE scope = ?1 ORDER BY rowid")?;
+        let jailed_tasks = stmt
+            .query_map(params![scope], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+
+        Ok(Some(LocalEventsStorageEntry {
+            expires,
+            height_based,
+            time_based,
+            cooldown_tasks,
+            jailed_tasks,
+        }))
+    }
+
+    fn save_events(
+        &self,
+        scope: &str,
+        entry: &LocalEventsStorageEntry,
+        changes: &[EventChange],
+    ) -> Result<(), Report> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        put_expires(&tx, scope, entry.expires)?;
+        for change in changes {
+            apply_event_change(&tx, scope, change)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn imports_json_and_applies_changes() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+        let entry = LocalEventsStorageEntry {
+            expires: 100,
+            height_based: BTreeMap::new(),
+            time_based: BTreeMap::new(),
+            cooldown_tasks: vec![CooldownTask {
+                index: 0,
+                expires: 30,
+                task_hash: "cooling".to_string(),
+            }],
+            jailed_tasks: vec!["jailed".to_string()],
+        };
+        JsonBackend::new(dir.clone())
+            .save_events("uni-6", &entry, &[])
+            .unwrap();
+
+        let backend = SqliteBackend::open(&dir).unwrap();
+        let cooldown = CooldownTask {
+            index: 1,
+            expires: 60,
+            task_hash: "other".to_string(),
+        };
+        backend
+            .save_events(
+                "uni-6",
+                &entry,
+                &[
+                    EventChange::Jail("cooling"),
+                    EventChange::PutCooldown(&cooldown),
+                ],
+            )
+            .unwrap();
+        drop(backend);
+
+        // Reopening doesn't import the JSON again
+        let loaded = SqliteBackend::open(&dir)
+            .unwrap()
+            .load_events("uni-6")
+            .unwrap()
+            .unwrap();
+        assert_eq!(loaded.expires, 100);
+        assert_eq!(loaded.jailed_tasks, vec!["jailed", "cooling"]);
+        let cooling: Vec<_> = loaded
+            .cooldown_tasks
+            .iter()
+            .map(|c| (c.task_hash.as_str(), c.index))
+            .collect();
+        assert_eq!(cooling, vec![("other", 1)]);
+    }
+}
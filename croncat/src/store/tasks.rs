@@ -5,15 +5,16 @@ use croncat_sdk_tasks::types::{BoundaryHeight, BoundaryTime, TaskInfo};
 use serde::{Deserialize, Serialize};
 use std::{
     collections::{BTreeMap, HashMap},
-    fs,
     ops::Bound::{Excluded, Included},
     path::PathBuf,
+    sync::Arc,
 };
 
-use super::get_storage_path;
+use super::{
+    backend::{self, EventChange, StorageBackend},
+    get_storage_path,
+};
 
-/// Where our [`LocalEventStorage`] will be stored.
-const LOCAL_STORAGE_FILENAME: &str = "events.json";
 const MAXIMUM_COOLDOWN_INDEX: u8 = 5;
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -48,26 +49,7 @@ impl std::fmt::Debug for LocalEventsStorageEntry {
     }
 }
 
-pub fn load_data_from_path(
-    path: PathBuf,
-    path_prefix: &Option<String>,
-) -> Option<LocalEventsStorageEntry> {
-    let data_file = path
-        .join(path_prefix.clone().unwrap_or_default())
-        .join(LOCAL_STORAGE_FILENAME);
-
-    // Load from the agent data file if it exists
-    if data_file.exists() {
-        let json_data = fs::read_to_string(data_file).unwrap();
-        let data =
-            serde_json::from_str(json_data.as_str()).expect("Failed to parse agent JSON data");
-        data
-    } else {
-        // Otherwise create a new hashmap
-        None
-    }
-}
-
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum EventType {
     Block,
     Time,
@@ -78,74 +60,53 @@ pub struct LocalEventStorage {
     pub path: PathBuf,
     pub path_prefix: Option<String>,
     data: Option<LocalEventsStorageEntry>,
+    backend: Arc<dyn StorageBackend>,
 }
 
 impl LocalEventStorage {
     /// Create a new [`LocalEventStorage`] instance with the default directory.
-    pub fn new(path_prefix: Option<String>) -> Self {
-        let p = get_storage_path();
-        let data = load_data_from_path(p.clone(), &path_prefix);
-        Self {
-            path: p,
-            path_prefix,
-            data,
-        }
+    pub fn new(path_prefix: Option<String>) -> Result<Self, Report> {
+        Self::load(get_storage_path(), path_prefix)
     }
 
     /// Create a [`LocalEventStorage`] instance at a specified path,
     /// if the data already exists at the directory we load it.
-    pub fn from_path(&self, path: PathBuf) -> Self {
-        let data_file = path
-            .join(self.path_prefix.clone().unwrap_or_default())
-            .join(LOCAL_STORAGE_FILENAME);
-
-        // Load from the agent data file if it exists
-        if data_file.exists() {
-            let json_data = fs::read_to_string(data_file).unwrap();
-            let data =
-                serde_json::from_str(json_data.as_str()).expect("Failed to parse agent JSON data");
-            Self {
-                path,
-                path_prefix: self.path_prefix.clone(),
-                data,
-            }
-        } else {
-            // Otherwise create a new hashmap
-            Self {
-                path,
-                path_prefix: self.path_prefix.clone(),
-                data: None,
-            }
-        }
+    pub fn from_path(&self, path: PathBuf) -> Result<Self, Report> {
+        Self::load(path, self.path_prefix.clone())
     }
 
-    /// Write our data to disk at the specified location.
-    pub fn write_to_disk(&self) -> Result<(), Report> {
-        if self.data.is_none() {
-            return Err(eyre!("No factory data to write"));
-        }
-        let data_file = self
-            .path
-            .join(self.path_prefix.clone().unwrap_or_default())
-            .join(LOCAL_STORAGE_FILENAME);
-
-        // Create the directory to store our data if it doesn't exist
-        if let Some(p) = data_file.parent() {
-            fs::create_dir_all(p)?
-        };
-
-        let r = fs::write(
-            data_file,
-            serde_json::to_string_pretty(&self.data.clone().unwrap())?,
-        );
+    fn load(path: PathBuf, path_prefix: Option<String>) -> Result<Self, Report> {
+        let backend = backend::for_path(&path)?;
+        let data = backend
+            .load_events(&path_prefix.clone().unwrap_or_default())
+            .map_err(|err| eyre!("Failed to load evented tasks: {}", err))?;
+        Ok(Self {
+            path,
+            path_prefix,
+            data,
+            backend,
+        })
+    }
 
-        if r.is_ok() {
-            Ok(())
+    /// Write any loaded data to disk, nothing to do if none was loaded.
+    pub fn flush(&self) -> Result<(), Report> {
+        if self.data.is_some() {
+            self.save(&[])
         } else {
-            Err(eyre!(r.unwrap_err()))
+            Ok(())
         }
     }
 
+    /// Hand our changes to the storage backend.
+    fn save(&self, changes: &[EventChange]) -> Result<(), Report> {
+        let data = self
+            .data
+            .as_ref()
+            .ok_or_else(|| eyre!("No events data to write"))?;
+        self.backend
+            .save_events(&self.path_prefix.clone().unwrap_or_default(), data, changes)
+    }
+
     /// Insert a items into the data set.
     pub fn update_expiry(&mut self) -> Result<(), Report> {
         // Expires after 1 hour, updates any time we get new data
@@ -161,9 +122,7 @@ impl LocalEventStorage {
             jailed_tasks: prev.jailed_tasks.clone(),
         });
 
-        self.clear_empty_indexes()?;
-        self.write_to_disk()?;
-        Ok(())
+        self.clear_empty_indexes()
     }
 
     /// Insert a items into the data set.
@@ -176,75 +135,36 @@ impl LocalEventStorage {
         // Expires after 1 hour, updates any time we get new data
         let dt = Utc::now();
         let expires = dt.timestamp().saturating_add(60); // 1 min
+        let events: Vec<(String, TaskInfo)> = events
+            .into_iter()
+            .filter(|(k, _)| !self.is_jailed_task(k))
+            .collect();
 
-        if let Some(mut data) = self.data.clone() {
-            match kind {
-                EventType::Block => {
-                    let mut event_range = data
-                        .height_based
-                        .get(&index)
-                        .unwrap_or(&HashMap::new())
-                        .to_owned();
-                    for (k, v) in events {
-                        if !self.is_jailed_task(&k) {
-                            event_range.insert(k, v);
-                        }
-                    }
-                    data.height_based.insert(index, event_range);
-                }
-                EventType::Time => {
-                    let mut event_range = data
-                        .time_based
-                        .get(&index)
-                        .unwrap_or(&HashMap::new())
-                        .to_owned();
-                    for (k, v) in events {
-                        if !self.is_jailed_task(&k) {
-                            event_range.insert(k, v);
-                        }
-                    }
-                    data.time_based.insert(index, event_range);
-                }
-            }
-            data.expires = expires;
-            self.data = Some(data);
-        } else {
-            // dont ever clear cooldown or jailed tasks, those need to be longer living
-            let data = &self.data;
-            let (cooldown_tasks, jailed_tasks) = if let Some(data) = data {
-                (data.cooldown_tasks.clone(), data.jailed_tasks.clone())
-            } else {
-                (vec![], vec![])
-            };
-
-            let mut height_based: BTreeMap<u64, HashMap<String, TaskInfo>> = BTreeMap::new();
-            let mut time_based: BTreeMap<u64, HashMap<String, TaskInfo>> = BTreeMap::new();
-            let mut items: HashMap<String, TaskInfo> = HashMap::new();
-
-            for (k, v) in events {
-                items.insert(k, v);
-            }
-
-            match kind {
-                EventType::Block => {
-                    height_based.insert(index, items);
-                }
-                EventType::Time => {
-                    time_based.insert(index, items);
-                }
-            }
-            self.data = Some(LocalEventsStorageEntry {
-                expires,
-                height_based,
-                time_based,
-                cooldown_tasks,
-                jailed_tasks,
-            });
-        }
-
-        self.clear_empty_indexes()?;
-        self.write_to_disk()?;
-        Ok(())
+        // Start the cache if there isn't one yet
+        let data = self.data.get_or_insert_with(|| LocalEventsStorageEntry {
+            expires,
+            height_based: BTreeMap::new(),
+            time_based: BTreeMap::new(),
+            cooldown_tasks: vec![],
+            jailed_tasks: vec![],
+        });
+        let indexed = match kind {
+            EventType::Block => &mut data.height_based,
+            EventType::Time => &mut data.time_based,
+        };
+        indexed
+            .entry(index)
+            .or_default()
+            .extend(events.iter().cloned());
+        data.expires = expires;
+        data.height_based.retain(|_, v| !v.is_empty());
+        data.time_based.retain(|_, v| !v.is_empty());
+
+        self.save(&[EventChange::PutTasks {
+            kind,
+            index,
+            tasks: &events,
+        }])
     }
 
     /// Clear all data, helpful for refreshing all data
@@ -269,8 +189,7 @@ impl LocalEventStorage {
             cooldown_tasks,
             jailed_tasks,
         });
-        self.write_to_disk()?;
-        Ok(())
+        self.save(&[EventChange::ClearTasks])
     }
 
     /// Cleaning up empty indexs
@@ -285,8 +204,8 @@ impl LocalEventStorage {
             self.data = Some(data);
         }
 
-        self.write_to_disk()?;
-        Ok(())
+        // Empty indexes are only a thing in memory, nothing changed for the backend
+        self.save(&[])
     }
 
     /// Remove ended tasks from cache, return the vec of task hashes for agent to
@@ -352,7 +271,11 @@ impl LocalEventStorage {
                 data.jailed_tasks.retain(|th| th != hash);
             }
 
-            self.write_to_disk()?;
+            let changes: Vec<EventChange> = cleared
+                .iter()
+                .map(|hash| EventChange::RemoveTask(hash))
+                .collect();
+            self.save(&changes)?;
         }
 
         Ok(cleared)
@@ -376,8 +299,7 @@ impl LocalEventStorage {
             }
         }
 
-        self.write_to_disk()?;
-        Ok(())
+        self.save(&[EventChange::RemoveTask(&task_hash)])
     }
 
     /// Clear all data less than or equal to an index, but NOT 0th index
@@ -399,8 +321,10 @@ impl LocalEventStorage {
             self.data = Some(data);
         }
 
-        self.write_to_disk()?;
-        Ok(())
+        self.save(&[EventChange::ClearIndexes {
+            kind,
+            index: *index,
+        }])
     }
 
     /// Retrieve data, only if not expired
@@ -466,42 +390,58 @@ impl LocalEventStorage {
         }
     }
 
-    /// inserts a new or updated cooldown task
-    pub fn set_cooldown_task(&mut self, task_hash: String) {
-        let mut data = self.data.clone().expect("No local data found!");
+    /// inserts a new or updated cooldown task, returns whether the task got jailed
+    pub fn set_cooldown_task(&mut self, task_hash: String) -> bool {
+        let data = self.data.as_mut().expect("No local data found!");
         let dt = Utc::now();
 
-        if let Some(task) = data
+        let position = data
             .cooldown_tasks
-            .iter_mut()
-            .find(|t| t.task_hash == task_hash)
-        {
+            .iter()
+            .position(|t| t.task_hash == task_hash);
+        let cooldown = match position {
             // if index is too high, jail task!
-            if task.index >= MAXIMUM_COOLDOWN_INDEX {
-                data.jailed_tasks.push(task_hash.clone());
-
+            Some(position) if data.cooldown_tasks[position].index >= MAXIMUM_COOLDOWN_INDEX => {
+                // Remove task item by task hash from cache in every occurrence
+                for tasks in data
+                    .height_based
+                    .values_mut()
+                    .chain(data.time_based.values_mut())
+                {
+                    tasks.remove(&task_hash);
+                }
                 // remove from the cooldown set
-                data.cooldown_tasks
-                    .retain(|c| c.task_hash != task_hash.clone());
+                data.cooldown_tasks.remove(position);
+                data.jailed_tasks.push(task_hash.clone());
 
-                // Remove task item by task hash from cache in every occurrence
-                self.remove_task_by_hash(task_hash).unwrap();
-            } else {
+                self.save(&[
+                    EventChange::RemoveTask(&task_hash),
+                    EventChange::Jail(&task_hash),
+                ])
+                .unwrap();
+                return true;
+            }
+            Some(position) => {
+                let task = &mut data.cooldown_tasks[position];
                 task.index += 1;
                 let idx = i64::from(task.index);
                 task.expires = dt.timestamp().saturating_add(idx * idx * 30); // exponential backoff
+                task.clone()
             }
-        } else {
-            let index = 0;
-            data.cooldown_tasks.push(CooldownTask {
-                index,
-                task_hash,
-                expires: dt.timestamp().saturating_add(30), // 30 secs,
-            });
-        }
+            None => {
+                let task = CooldownTask {
+                    index: 0,
+                    task_hash,
+                    expires: dt.timestamp().saturating_add(30), // 30 secs,
+                };
+                data.cooldown_tasks.push(task.clone());
+                task
+            }
+        };
 
-        self.data = Some(data);
-        self.write_to_disk().unwrap();
+        self.save(&[EventChange::PutCooldown(&cooldown)]).unwrap();
+
+        false
     }
 
     /// retrieves a ready cooldown task for re-evaluation
@@ -540,6 +480,16 @@ impl LocalEventStorage {
             .map_or(false, |data| data.jailed_tasks.contains(task_hash))
     }
 
+    /// Totals for cooldown and jailed tasks
+    pub fn get_task_health(&self) -> (u64, u64) {
+        self.data.as_ref().map_or((0, 0), |data| {
+            (
+                data.cooldown_tasks.len() as u64,
+                data.jailed_tasks.len() as u64,
+            )
+        })
+    }
+
     /// Totals for 0th and ranged task amounts
     pub fn get_stats(&self) -> (u64, u64, u64, u64) {
         if let Some(data) = self.data.clone() {
@@ -596,9 +546,3 @@ impl LocalEventStorage {
         }
     }
 }
-
-impl Default for LocalEventStorage {
-    fn default() -> Self {
-        Self::new(None)
-    }
-}
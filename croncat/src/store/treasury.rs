@@ -0,0 +1,73 @@
+//!
+//! How much the treasury has sent today, kept on disk so restarts
+//! don't reset the daily cap.
+//!
+
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use crate::errors::{CroncatError, Report};
+
+use super::{
+    file::{read_json, write_json},
+    get_storage_path,
+};
+
+/// Where our [`LocalTreasuryStorage`] will be stored.
+const LOCAL_STORAGE_FILENAME: &str = "treasury.json";
+
+/// Amount sent from the treasury on a given UTC day.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct LocalTreasuryStorageEntry {
+    pub day: String,
+    pub spent: u64,
+}
+
+/// Store the treasury spend on disk and allow access to the data.
+pub struct LocalTreasuryStorage {
+    pub path: PathBuf,
+    data: LocalTreasuryStorageEntry,
+}
+
+impl LocalTreasuryStorage {
+    /// Create a new [`LocalTreasuryStorage`] instance for the chain in the default directory.
+    pub fn new(chain_id: &str) -> Result<Self, Report> {
+        Self::from_path(get_storage_path().join(chain_id))
+    }
+
+    /// Create a [`LocalTreasuryStorage`] instance at a specified path,
+    /// if the data already exists at the directory we load it.
+    pub fn from_path(path: PathBuf) -> Result<Self, Report> {
+        let data_file = path.join(LOCAL_STORAGE_FILENAME);
+
+        let data = read_json(&data_file)
+            .map_err(|err| CroncatError::Config(format!("Failed to load treasury: {err}")))?
+            .unwrap_or_default();
+
+        Ok(Self { path, data })
+    }
+
+    /// Amount sent on the given day.
+    pub fn spent_on(&self, day: &str) -> u64 {
+        if self.data.day == day {
+            self.data.spent
+        } else {
+            0
+        }
+    }
+
+    /// Add to the amount sent on the given day, and write it to disk.
+    pub fn record(&mut self, day: &str, amount: u64) -> Result<(), Report> {
+        let spent = self.spent_on(day).saturating_add(amount);
+        self.data = LocalTreasuryStorageEntry {
+            day: day.to_string(),
+            spent,
+        };
+        self.write_to_disk()
+    }
+
+    /// Write our data to disk at the specified location.
+    fn write_to_disk(&self) -> Result<(), Report> {
+        write_json(&self.path.join(LOCAL_STORAGE_FILENAME), &self.data)
+    }
+}
@@ -0,0 +1,178 @@
+//!
+//! Append-only receipts for every transaction the agent sends, one JSON line
+//! per transaction in a file per day under `<chain_id>/txs`.
+//!
+
+use color_eyre::Report;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::PathBuf,
+};
+
+use super::get_storage_path;
+
+/// Directory the receipts are kept in, under the chain directory.
+const LOCAL_STORAGE_DIRNAME: &str = "txs";
+
+/// What a transaction was sent for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TxKind {
+    ProxyCall,
+    ScheduledBatch,
+    EventedBatch,
+    Register,
+    CheckIn,
+    Unregister,
+    Update,
+    WithdrawReward,
+    SendFunds,
+    TreasuryTopUp,
+}
+
+/// How a transaction ended up.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum TxOutcome {
+    Included { height: u64 },
+    Failed { error: String },
+    TimedOut { error: String },
+}
+
+/// A receipt for one transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxRecord {
+    pub timestamp: String,
+    pub kind: TxKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tx_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub task_hashes: Vec<String>,
+    pub gas_wanted: u64,
+    pub gas_used: u64,
+    /// Fee paid, in `denom`.
+    pub fee: u128,
+    pub denom: String,
+    #[serde(flatten)]
+    pub outcome: TxOutcome,
+}
+
+impl TxRecord {
+    pub fn is_failure(&self) -> bool {
+        !matches!(self.outcome, TxOutcome::Included { .. })
+    }
+}
+
+/// Store transaction receipts on disk and allow access to the data.
+pub struct LocalTxStorage {
+    pub path: PathBuf,
+}
+
+impl LocalTxStorage {
+    /// Create a new [`LocalTxStorage`] instance for the chain in the default directory.
+    pub fn new(chain_id: &str) -> Self {
+        Self::from_path(get_storage_path().join(chain_id))
+    }
+
+    /// Create a [`LocalTxStorage`] instance for the chain directory at a specified path.
+    pub fn from_path(path: PathBuf) -> Self {
+        Self {
+            path: path.join(LOCAL_STORAGE_DIRNAME),
+        }
+    }
+
+    /// Append a receipt to today's file.
+    pub fn append(&self, record: &TxRecord) -> Result<(), Report> {
+        fs::create_dir_all(&self.path)?;
+        let file_path = self
+            .path
+            .join(format!("{}.jsonl", chrono::Utc::now().format("%Y-%m-%d")));
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(file_path)?;
+        writeln!(file, "{}", serde_json::to_string(record)?)?;
+
+        Ok(())
+    }
+
+    /// The latest receipts, newest first, optionally only failures.
+    pub fn recent(&self, limit: usize, failures_only: bool) -> Result<Vec<TxRecord>, Report> {
+        if !self.path.exists() {
+            return Ok(vec![]);
+        }
+
+        let mut files = fs::read_dir(&self.path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        files.retain(|path| path.extension().is_some_and(|ext| ext == "jsonl"));
+        files.sort();
+
+        let mut records = vec![];
+        for file in files.iter().rev() {
+            let contents = fs::read_to_string(file)?;
+            // Skip a line cut short by a crash, rather than losing the whole day
+            let day = contents
+                .lines()
+                .rev()
+                .filter_map(|line| serde_json::from_str::<TxRecord>(line).ok())
+                .filter(|record| !failures_only || record.is_failure());
+            for record in day {
+                if records.len() >= limit {
+                    return Ok(records);
+                }
+                records.push(record);
+            }
+        }
+
+        Ok(records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn appends_and_reads_newest_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = LocalTxStorage::from_path(dir.path().to_path_buf());
+        let record = |tx_hash: &str, outcome: TxOutcome| TxRecord {
+            timestamp: "2023-03-01T00:00:00Z".to_string(),
+            kind: TxKind::ScheduledBatch,
+            tx_hash: Some(tx_hash.to_string()),
+            task_hashes: vec![],
+            gas_wanted: 200,
+            gas_used: 150,
+            fee: 10,
+            denom: "ujunox".to_string(),
+            outcome,
+        };
+
+        storage
+            .append(&record("A", TxOutcome::Included { height: 1 }))
+            .unwrap();
+        storage
+            .append(&record(
+                "B",
+                TxOutcome::Failed {
+                    error: "out of gas".to_string(),
+                },
+            ))
+            .unwrap();
+        storage
+            .append(&record("C", TxOutcome::Included { height: 2 }))
+            .unwrap();
+
+        let hashes = |records: Vec<TxRecord>| {
+            records
+                .into_iter()
+                .filter_map(|record| record.tx_hash)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(hashes(storage.recent(2, false).unwrap()), vec!["C", "B"]);
+        assert_eq!(hashes(storage.recent(10, true).unwrap()), vec!["B"]);
+    }
+}
@@ -0,0 +1,64 @@
+//!
+//! Agents the low balance policy unregistered, kept on disk so a restart
+//! keeps waiting for funds instead of failing on the missing agent.
+//!
+
+use color_eyre::Report;
+use std::{collections::HashMap, path::PathBuf};
+
+use super::{
+    file::{read_json, write_json},
+    get_storage_path,
+};
+
+/// Where our [`LocalUnregisteredStorage`] will be stored.
+const LOCAL_STORAGE_FILENAME: &str = "unregistered.json";
+
+/// The payable account to register again with, by agent address.
+type LocalUnregisteredStorageData = HashMap<String, String>;
+
+/// Store the unregistered agents on disk and allow access to the data.
+pub struct LocalUnregisteredStorage {
+    pub path: PathBuf,
+    data: LocalUnregisteredStorageData,
+}
+
+impl LocalUnregisteredStorage {
+    /// Create a new [`LocalUnregisteredStorage`] instance for the chain in the default directory.
+    pub fn new(chain_id: &str) -> Result<Self, Report> {
+        Self::from_path(get_storage_path().join(chain_id))
+    }
+
+    /// Create a [`LocalUnregisteredStorage`] instance at a specified path,
+    /// if the data already exists at the directory we load it.
+    pub fn from_path(path: PathBuf) -> Result<Self, Report> {
+        let data = read_json(&path.join(LOCAL_STORAGE_FILENAME))?.unwrap_or_default();
+
+        Ok(Self { path, data })
+    }
+
+    /// The payable account to register the agent again with, if it was unregistered.
+    pub fn get(&self, account_id: &str) -> Option<&String> {
+        self.data.get(account_id)
+    }
+
+    /// Remember the agent was unregistered, and write it to disk.
+    pub fn insert(&mut self, account_id: &str, payable_account_id: &str) -> Result<(), Report> {
+        self.data
+            .insert(account_id.to_string(), payable_account_id.to_string());
+        self.write_to_disk()
+    }
+
+    /// Forget the agent once it's registered again, and write it to disk.
+    pub fn remove(&mut self, account_id: &str) -> Result<(), Report> {
+        if self.data.remove(account_id).is_some() {
+            self.write_to_disk()?;
+        }
+        Ok(())
+    }
+
+    /// Write our data to disk at the specified location.
+    fn write_to_disk(&self) -> Result<(), Report> {
+        write_json(&self.path.join(LOCAL_STORAGE_FILENAME), &self.data)
+    }
+}
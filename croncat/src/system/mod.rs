@@ -3,7 +3,8 @@
 //!
 
 use croncat_pipeline::{try_flat_join, Dispatcher, ProviderSystem, Sequencer};
-use std::sync::Arc;
+use croncat_sdk_agents::types::AgentStatus;
+use std::{sync::Arc, time::Duration};
 use tokio::{
     sync::{broadcast, mpsc, Mutex},
     task::JoinHandle,
@@ -12,18 +13,22 @@ use tokio::{
 use tracing::{debug, error};
 
 use crate::{
+    alerts,
     channels::ShutdownTx,
     config::ChainConfig,
-    errors::{eyre, Report},
+    errors::{eyre, ContractError, CroncatError, Report},
     logging::info,
+    metrics,
     modules::{
         agent::{check_status_loop, Agent},
         factory::{refresh_factory_loop, Factory},
         manager::Manager,
-        polling::poll_stream_blocks,
+        polling::{poll_stream_blocks, ws_stream_blocks},
         tasks::{evented_tasks_loop, refresh_tasks_cache_loop, scheduled_tasks_loop, Tasks},
     },
+    monitor::{self, heartbeat_loop},
     rpc::RpcClientService,
+    store::unregistered::LocalUnregisteredStorage,
     tokio,
 };
 
@@ -46,13 +51,44 @@ pub async fn run(
     // Get the status of the agent
     let account_id = agent.account_id();
     let account_addr = account_id.clone();
-    let status = agent.get_status(account_addr).await?;
+    let mut unregistered = LocalUnregisteredStorage::new(chain_id)?;
+    let status = match agent.get_status(account_addr).await {
+        Ok(status) => {
+            // Registered again by hand while we were down
+            unregistered.remove(account_id)?;
+            status
+        }
+        // Unregistered for a low balance before a restart, the status loop waits for funds
+        Err(err)
+            if unregistered.get(account_id).is_some()
+                && CroncatError::classify(&err).contract_error()
+                    == Some(&ContractError::AgentNotRegistered) =>
+        {
+            info!(
+                "[{}] Agent unregistered for a low balance, waiting for funds",
+                chain_id
+            );
+            manager.pause();
+            AgentStatus::Pending
+        }
+        Err(err) => return Err(err),
+    };
 
     info!("[{}] Agent: {}", chain_id, account_id);
     info!("[{}] Current Status: {:?}", chain_id, status);
 
+    alerts::register(chain_id, config.alerts.clone()).await;
+    metrics::set_agent_status(chain_id, &status);
+    monitor::record_agent_status(chain_id, &status).await;
     let status = Arc::new(Mutex::new(status));
 
+    // Serve metrics for this chain, if configured
+    if let Some(metrics_address) = config.metrics_address {
+        metrics::spawn_exporter(chain_id, metrics_address).await;
+    }
+
+    let drain_timeout = Duration::from_secs_f64(config.drain_timeout_seconds);
+
     // Create a channel for block sources
     let (block_source_tx, block_source_rx) = mpsc::unbounded_channel();
 
@@ -61,15 +97,27 @@ pub async fn run(
 
     // For each RPC endpoint, spawn a task to stream blocks from it
     for (provider, data_source) in &config.data_sources() {
-        info!(
-            "[{}] Starting polling task for {} {}",
-            chain_id, provider, data_source.rpc
-        );
-
-        provider_system.add_provider_stream(
-            provider,
-            poll_stream_blocks(data_source.rpc.clone(), config.block_polling_seconds),
-        );
+        if data_source.is_websocket() {
+            info!(
+                "[{}] Starting websocket task for {} {}",
+                chain_id, provider, data_source.rpc
+            );
+
+            provider_system.add_provider_stream(
+                provider,
+                ws_stream_blocks(data_source.rpc.clone(), config.websocket_timeout_seconds),
+            );
+        } else {
+            info!(
+                "[{}] Starting polling task for {} {}",
+                chain_id, provider, data_source.rpc
+            );
+
+            provider_system.add_provider_stream(
+                provider,
+                poll_stream_blocks(data_source.rpc.clone(), config.block_polling_seconds),
+            );
+        }
     }
 
     // TODO: FIXME
@@ -115,6 +163,7 @@ pub async fn run(
 
         async move {
             while let Ok(status) = block_stream.recv().await {
+                metrics::inc_blocks_seen(&chain_id);
                 debug!(
                     "[{}] Processing block (height: {})",
                     chain_id, status.inner.sync_info.latest_block_height,
@@ -133,6 +182,7 @@ pub async fn run(
             shutdown_rx,
             Arc::new(chain_id.clone()),
             factory.clone(),
+            drain_timeout,
         )
     });
 
@@ -169,6 +219,7 @@ pub async fn run(
             agent.clone(),
             manager.clone(),
             tasks.clone(),
+            drain_timeout,
         )
     });
 
@@ -188,6 +239,7 @@ pub async fn run(
                     manager.clone(),
                     tasks.clone(),
                     factory.clone(),
+                    drain_timeout,
                 )
             })
         } else {
@@ -209,6 +261,7 @@ pub async fn run(
                     shutdown_rx,
                     Arc::new(chain_id.clone()),
                     tasks.clone(),
+                    drain_timeout,
                 )
             })
         } else {
@@ -218,19 +271,35 @@ pub async fn run(
         empty_task()
     };
 
-    // Ctrl-C handler
-    let ctrl_c_handle: JoinHandle<Result<(), Report>> = tokio::task::spawn({
+    // Uptime monitor heartbeats
+    let heartbeat_handle = tokio::task::spawn({
+        let shutdown_rx = shutdown_tx.subscribe();
+        let block_stream_rx = dispatcher_tx.subscribe();
+
+        heartbeat_loop(
+            block_stream_rx,
+            shutdown_rx,
+            Arc::new(chain_id.clone()),
+            config.clone(),
+        )
+    });
+
+    // Signal handler, loops get to finish their current block before stopping
+    let signal_handle: JoinHandle<Result<(), Report>> = tokio::task::spawn({
         let shutdown_tx = shutdown_tx.clone();
         let chain_id = chain_id.clone();
 
         async move {
-            tokio::signal::ctrl_c()
+            let signal = shutdown_signal()
                 .await
-                .map_err(|err| eyre!("[{}] Failed to wait for Ctrl-C: {}", chain_id, err))?;
+                .map_err(|err| eyre!("[{}] Failed to wait for signals: {}", chain_id, err))?;
+            info!(
+                "[{}] Received {}, draining for up to {:?}...",
+                chain_id, signal, drain_timeout
+            );
             shutdown_tx
                 .send(())
                 .map_err(|err| eyre!("[{}] Failed to send shutdown signal: {}", chain_id, err))?;
-            info!("[{}] Shutting down...", chain_id);
 
             Ok(())
         }
@@ -238,7 +307,7 @@ pub async fn run(
 
     // Try to join all the system tasks.
     let system_status = try_flat_join!(
-        ctrl_c_handle,
+        signal_handle,
         sequencer_handle,
         dispatcher_handle,
         provider_system_handle,
@@ -247,16 +316,23 @@ pub async fn run(
         task_runner_handle,
         evented_task_runner_handle,
         tasks_cache_check_handle,
+        heartbeat_handle,
     );
 
     // Kill the info stream.
     block_stream_info_handle.abort();
 
+    // The loops are done with the stores, make sure everything is on disk.
+    let flush_status = flush_stores(factory, tasks).await;
+    log_final_state(chain_id, &status, tasks).await;
+    let system_status = system_status.and(flush_status);
+
     // If any of the tasks failed, we need to propagate the error.
     match system_status {
         Ok(_) => Ok(()),
         Err(err) => {
             error!("croncatd shutdown with error");
+            monitor::ping_failure(chain_id, config, &err).await;
             Err(err)
         }
     }
@@ -293,7 +369,7 @@ pub async fn run_retry(
         Ok(_) => Ok(()),
         Err(err) => {
             // Clear the cache and recache RPC sources
-            RpcClientService::clear_sources().await;
+            RpcClientService::clear_sources(chain_id).await;
             RpcClientService::cache_sources(config).await;
 
             Err(err)
@@ -317,6 +393,68 @@ pub async fn run_retry(
     // Ok(())
 }
 
+///
+/// Wait for SIGINT, SIGTERM or SIGHUP, returning the signal received.
+///
+#[cfg(unix)]
+pub async fn shutdown_signal() -> Result<&'static str, Report> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sighup = signal(SignalKind::hangup())?;
+
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => {
+            result?;
+            Ok("SIGINT")
+        }
+        _ = sigterm.recv() => Ok("SIGTERM"),
+        _ = sighup.recv() => Ok("SIGHUP"),
+    }
+}
+
+///
+/// Wait for Ctrl-C, the only signal available off unix.
+///
+#[cfg(not(unix))]
+pub async fn shutdown_signal() -> Result<&'static str, Report> {
+    tokio::signal::ctrl_c().await?;
+    Ok("Ctrl-C")
+}
+
+/// Write the factory and task caches to disk.
+async fn flush_stores(
+    factory: &Arc<Mutex<Factory>>,
+    tasks: &Arc<Mutex<Tasks>>,
+) -> Result<(), Report> {
+    factory.lock().await.store.flush()?;
+    tasks.lock().await.store.flush()?;
+
+    Ok(())
+}
+
+/// Log where the agent left off.
+async fn log_final_state(
+    chain_id: &str,
+    status: &Arc<Mutex<AgentStatus>>,
+    tasks: &Arc<Mutex<Tasks>>,
+) {
+    let tasks = tasks.lock().await;
+    let (cooldown, jailed) = tasks.store.get_task_health();
+    let (h0, hr, t0, tr) = tasks.store.get_stats();
+    info!(
+        "[{}] Final state :: Status: {:?}, H0: {}, HR: {}, T0: {}, TR: {}, Cooldown: {}, Jailed: {}",
+        chain_id,
+        *status.lock().await,
+        h0,
+        hr,
+        t0,
+        tr,
+        cooldown,
+        jailed,
+    );
+}
+
 #[inline(always)]
 fn empty_task() -> JoinHandle<Result<(), Report>> {
     tokio::task::spawn(async { Ok(()) })
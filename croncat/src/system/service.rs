@@ -1,7 +1,7 @@
 use std::{
     fs::{self, File},
     io::Write,
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
 use color_eyre::{eyre::eyre, Report};
@@ -13,12 +13,21 @@ use crate::store::get_storage_path;
 /// Name of the daemon service directory.
 const DAEMON_SERVICES_DIR_NAME: &str = "system-services";
 
+/// Service name suffix used when a single unit runs every configured chain.
+const ALL_CHAINS_SERVICE_NAME: &str = "all";
+
 /// The croncat system service daemon.
 pub struct DaemonService;
 
 impl DaemonService {
-    /// Create a new daemon service file at the given path with chain ID.
-    pub fn create(path: Option<String>, chain_id: &String, no_frills: bool) -> Result<(), Report> {
+    /// Create a new daemon service file at the given path with chain ID,
+    /// or a single service running every configured chain when no chain ID is given.
+    pub fn create(
+        path: Option<String>,
+        chain_id: Option<&String>,
+        config_path: &Path,
+        no_frills: bool,
+    ) -> Result<(), Report> {
         // Check the platform to make sure we're on Linux.
         if cfg!(not(target_os = "linux")) {
             return Err(eyre!(
@@ -36,12 +45,25 @@ impl DaemonService {
         // Create the daemon service directory at the given path if it doesn't exist.
         fs::create_dir_all(&path)?;
 
+        // Either run a single chain, or every chain in one process.
+        let config_arg = format!("--config \"{}\"", config_path.display());
+        let (service_name, go_args) = match chain_id {
+            Some(chain_id) => (
+                chain_id.clone(),
+                format!("{config_arg} --chain-id {chain_id} go"),
+            ),
+            None => (
+                ALL_CHAINS_SERVICE_NAME.to_string(),
+                format!("{config_arg} go --all"),
+            ),
+        };
+
         // Create the service file based on the chain ID.
-        let service_file_path = Self::create_service_file(path, chain_id)?;
+        let service_file_path = Self::create_service_file(path, &service_name, &go_args)?;
 
         info!(
-            "Created croncatd service file for chain ID {} at {}",
-            chain_id, &service_file_path
+            "Created croncatd service file for {} at {}",
+            service_name, &service_file_path
         );
 
         // Link the service file to the systemd directory.
@@ -50,12 +72,16 @@ impl DaemonService {
         info!("Linked croncatd service file to systemd directory");
 
         // Print a nice little message if we're not in no-frills mode.
-        Self::print_next_steps(chain_id, no_frills);
+        Self::print_next_steps(&service_name, no_frills);
 
         Ok(())
     }
 
-    fn create_service_file(path: PathBuf, chain_id: &String) -> Result<String, Report> {
+    fn create_service_file(
+        path: PathBuf,
+        chain_id: &String,
+        go_args: &String,
+    ) -> Result<String, Report> {
         // Get the current user's name.
         let user = whoami::username();
         // Get the full path to the croncatd service directory.
@@ -79,12 +105,11 @@ impl DaemonService {
                     Type=simple
                     User={user}
                     WorkingDirectory={service_dir}
-                    ExecStart={exe_path} go
+                    ExecStart={exe_path} {go_args}
                     StandardOutput=append:/var/log/croncatd-{chain_id}.log
                     StandardError=append:/var/log/croncatd-{chain_id}-error.log
                     Restart=on-failure
                     RestartSec=60
-                    KillSignal=SIGINT
                     TimeoutStopSec=45
                     KillMode=mixed
 
@@ -92,6 +117,7 @@ impl DaemonService {
                     WantedBy=multi-user.target
                 "},
                 chain_id = chain_id,
+                go_args = go_args,
                 user = user,
                 service_dir = full_service_dir_path.to_str().ok_or_else(|| eyre!(
                     "Could not convert daemon service directory path to string",
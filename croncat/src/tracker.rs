@@ -0,0 +1,130 @@
+//!
+//! Receipts for every transaction the agent sends, with the gas and fee it cost
+//! and whether it made it into a block, kept in the chain's [`LocalTxStorage`].
+//!
+
+use std::collections::HashMap;
+
+use chrono::Utc;
+use cosm_orc::orchestrator::ChainTxResponse;
+use lazy_static::lazy_static;
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use crate::{
+    accounting,
+    config::ChainConfig,
+    errors::{CroncatError, Report},
+    store::txs::{LocalTxStorage, TxKind, TxOutcome, TxRecord},
+};
+
+lazy_static! {
+    /// Receipt stores and fee settings, by chain ID.
+    static ref TRACKERS: Mutex<HashMap<String, Tracker>> = Mutex::new(HashMap::new());
+}
+
+struct Tracker {
+    store: LocalTxStorage,
+    gas_price: f64,
+    denom: String,
+}
+
+impl Tracker {
+    fn record(
+        &self,
+        kind: TxKind,
+        task_hashes: Vec<String>,
+        result: &Result<ChainTxResponse, Report>,
+    ) -> TxRecord {
+        let mut record = TxRecord {
+            timestamp: Utc::now().to_rfc3339(),
+            kind,
+            tx_hash: None,
+            task_hashes,
+            gas_wanted: 0,
+            gas_used: 0,
+            fee: 0,
+            denom: self.denom.clone(),
+            outcome: TxOutcome::Included { height: 0 },
+        };
+
+        match result {
+            Ok(res) => {
+                record.tx_hash = Some(res.tx_hash.clone());
+                record.gas_wanted = res.gas_wanted;
+                record.gas_used = res.gas_used;
+                record.fee = (res.gas_wanted as f64 * self.gas_price).ceil() as u128;
+                record.outcome = TxOutcome::Included { height: res.height };
+            }
+            Err(err) => {
+                let error = err.to_string();
+                record.outcome = match CroncatError::classify(err) {
+                    CroncatError::TxPending { hash, .. } => {
+                        record.tx_hash = hash;
+                        TxOutcome::TimedOut { error }
+                    }
+                    // Included but failed, the gas and fee were still spent
+                    CroncatError::TxFailed { receipt, .. } => {
+                        record.tx_hash = Some(receipt.hash);
+                        record.gas_wanted = receipt.gas_wanted;
+                        record.gas_used = receipt.gas_used;
+                        record.fee = receipt.fee;
+                        TxOutcome::Failed { error }
+                    }
+                    CroncatError::Timeout(_) => TxOutcome::TimedOut { error },
+                    _ => TxOutcome::Failed { error },
+                };
+            }
+        }
+
+        record
+    }
+}
+
+/// Start keeping receipts for a chain.
+pub async fn register(chain_config: &ChainConfig) {
+    TRACKERS
+        .lock()
+        .await
+        .entry(chain_config.info.chain_id.clone())
+        .or_insert_with(|| Tracker {
+            store: LocalTxStorage::new(&chain_config.info.chain_id),
+            gas_price: chain_config.gas_prices as f64,
+            denom: chain_config.fee_denom.clone(),
+        });
+}
+
+///
+/// Keep the receipt for a transaction, passing its result through.
+///
+pub async fn track(
+    chain_id: &str,
+    kind: TxKind,
+    task_hashes: Vec<String>,
+    result: Result<ChainTxResponse, Report>,
+) -> Result<ChainTxResponse, Report> {
+    let record = {
+        let trackers = TRACKERS.lock().await;
+        let Some(tracker) = trackers.get(chain_id) else {
+            return result;
+        };
+        let record = tracker.record(kind, task_hashes, &result);
+        if let Err(err) = tracker.store.append(&record) {
+            warn!(
+                "[{}] Failed to write transaction receipt: {}",
+                chain_id, err
+            );
+        }
+        record
+    };
+
+    if record.fee > 0 {
+        let proxy_call = matches!(
+            record.kind,
+            TxKind::ProxyCall | TxKind::ScheduledBatch | TxKind::EventedBatch
+        );
+        accounting::record_fee(chain_id, record.fee, proxy_call).await;
+    }
+
+    result
+}
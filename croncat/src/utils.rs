@@ -2,17 +2,23 @@
 //! Helpers for dealing with local agents.
 //!
 
-use std::sync::{
-    atomic::{AtomicU64, Ordering},
-    Arc,
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
 };
 
 use color_eyre::Report;
 use delegate::delegate;
 use tokio::task::JoinHandle;
+use tracing::warn;
 
 use croncat_sdk_agents::msg::AgentTaskResponse;
 
+use crate::{channels::ShutdownRx, errors::CroncatError};
+
 pub const DEFAULT_AGENT_ID: &str = "agent";
 pub const DERIVATION_PATH: &str = "m/44'/118'/0'/0/0";
 
@@ -61,6 +67,30 @@ pub async fn flatten_join<T>(handle: JoinHandle<Result<T, Report>>) -> Result<T,
     }
 }
 
+///
+/// Wait for a loop task, once shutdown is signalled it has `drain_timeout`
+/// to finish what it's doing before it gets aborted.
+///
+pub async fn drain_join(
+    mut handle: JoinHandle<Result<(), Report>>,
+    mut shutdown_rx: ShutdownRx,
+    drain_timeout: Duration,
+) -> Result<(), Report> {
+    tokio::select! {
+        result = &mut handle => return result?,
+        _ = shutdown_rx.recv() => {}
+    }
+
+    match tokio::time::timeout(drain_timeout, &mut handle).await {
+        Ok(result) => result?,
+        Err(_) => {
+            handle.abort();
+            warn!("Loop didn't drain within {:?}, aborted it", drain_timeout);
+            Ok(())
+        }
+    }
+}
+
 ///
 /// Block wrapper
 ///
@@ -160,19 +190,12 @@ pub fn normalize_rpc_url(rpc_url: &str) -> String {
     }
 }
 
+/// Errors that retrying won't fix, see [`CroncatError::is_fatal`].
 pub fn is_error_fallible(e: &Report) -> bool {
-    let msg = e.to_string().to_lowercase();
-    msg.contains("agent not registered")
-        || msg.contains("agent already registered")
-        || msg.contains("already registered")
-        || msg.contains("agent not found")
-        || msg.contains("account not found")
-        || msg.contains("failed to send funds")
-        || msg.contains("needs whitelist approval")
-        || msg.contains("Chain not found")
+    CroncatError::classify(e).is_fatal()
 }
 
+/// Errors returned by the contract itself.
 pub fn is_contract_error(e: &Report) -> bool {
-    let msg = e.to_string().to_lowercase();
-    msg.contains("execute wasm contract failed")
+    CroncatError::classify(e).contract_error().is_some()
 }
@@ -3,9 +3,12 @@
 //!
 
 use crate::opts::Opts;
-use croncat::errors::Report;
+use croncat::errors::{CroncatError, Report};
 use structopt::StructOpt;
 
+/// Environment variable holding the keystore passphrase.
+const PASSPHRASE_ENV: &str = "CRONCAT_PASSPHRASE";
+
 /// Load the banner ascii art as a `&'static str`.
 const BANNER_STR: &str = include_str!("../banner.txt");
 
@@ -22,3 +25,28 @@ pub fn print_banner() {
 pub fn get_opts() -> Result<Opts, Report> {
     Ok(Opts::from_args_safe()?)
 }
+
+///
+/// Get the keystore passphrase, from the passphrase file, the environment or a prompt.
+/// Prompting for a new passphrase asks twice.
+///
+pub fn get_passphrase(opts: &Opts, confirm: bool) -> Result<String, Report> {
+    let passphrase = if let Some(path) = &opts.passphrase_file {
+        let passphrase = std::fs::read_to_string(path)?;
+        passphrase.trim_end_matches(['\r', '\n']).to_string()
+    } else if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        passphrase
+    } else {
+        let passphrase = rpassword::prompt_password("Agent keystore passphrase: ")?;
+        if confirm && passphrase != rpassword::prompt_password("Confirm passphrase: ")? {
+            return Err(CroncatError::Config("Passphrases don't match".to_string()).into());
+        }
+        passphrase
+    };
+
+    if passphrase.is_empty() {
+        return Err(CroncatError::Config("Passphrase can't be empty".to_string()).into());
+    }
+
+    Ok(passphrase)
+}
@@ -3,17 +3,25 @@
 //!
 
 use croncat::{
+    accounting,
     channels::create_shutdown_channel,
-    config::Config,
-    errors::{eyre, Report},
+    config::{ChainConfig, Config},
+    errors::{eyre, ContractError, CroncatError, Report},
     logging::{self, error, info},
-    modules::{agent::Agent, factory::Factory, manager::Manager, tasks::Tasks},
+    modules::{agent::Agent, factory::Factory, manager::Manager, tasks::Tasks, treasury::Treasury},
     rpc::RpcClientService,
-    store::agent::LocalAgentStorage,
+    store::{
+        agent::LocalAgentStorage,
+        earnings::LocalEarningsStorage,
+        lock::StorageLock,
+        txs::{LocalTxStorage, TxOutcome},
+    },
     system,
     tokio::{self, sync::Mutex},
+    tracker,
     utils::is_error_fallible,
 };
+use futures::future::join_all;
 use opts::Opts;
 use std::{process::exit, sync::Arc};
 mod cli;
@@ -21,6 +29,13 @@ mod opts;
 
 const MAXIMUM_RETRY_INDEX: u64 = 600; // 600*30/60/60 = 5hrs
 
+///
+/// Check if the contract rejected the call because the agent isn't registered.
+///
+fn is_agent_not_registered(err: &Report) -> bool {
+    CroncatError::classify(err).contract_error() == Some(&ContractError::AgentNotRegistered)
+}
+
 ///
 /// Start the `croncatd` agent.
 ///
@@ -33,8 +48,13 @@ fn main() -> Result<(), Report> {
         })
         .unwrap();
 
-    // Setup tracing and logging.
-    let _logging_guards = logging::setup(opts.chain_id.clone())?;
+    // Setup tracing and logging, multi chain runs share a single set of log files.
+    let log_id = match &opts.cmd {
+        opts::Command::Go { all: true, .. } => Some("all".to_string()),
+        opts::Command::Go { chain_ids, .. } if !chain_ids.is_empty() => Some(chain_ids.join("_")),
+        _ => opts.chain_id.clone(),
+    };
+    let _logging_guards = logging::setup(log_id)?;
 
     // If there ain't no no-frills...
     if !opts.no_frills {
@@ -51,11 +71,12 @@ fn main() -> Result<(), Report> {
             let opts = opts.clone();
 
             async move {
-                // Load up the local storage
-                let storage = LocalAgentStorage::new();
-
-                // Run the command
-                if let Err(err) = run_command(opts.clone(), storage).await {
+                // Load up the local storage, then run the command
+                let result = match LocalAgentStorage::new() {
+                    Ok(storage) => run_command(opts.clone(), storage).await,
+                    Err(err) => Err(err),
+                };
+                if let Err(err) = result {
                     error!("{}", err);
 
                     if opts.debug {
@@ -89,9 +110,419 @@ fn main() -> Result<(), Report> {
     Ok(())
 }
 
+///
+/// The contract clients needed to run an agent on a single chain.
+///
+struct ChainClients {
+    account_addr: String,
+    factory: Arc<Mutex<Factory>>,
+    agent: Arc<Agent>,
+    manager: Arc<Manager>,
+    tasks: Arc<Mutex<Tasks>>,
+}
+
+impl ChainClients {
+    /// Bootstrap the factory, then every contract client the factory knows about.
+    async fn new(
+        chain_id: &String,
+        chain_config: &ChainConfig,
+        storage: &LocalAgentStorage,
+        agent_id: &String,
+    ) -> Result<Self, Report> {
+        // Get the key and create a signer
+        let key = storage.get_agent_signing_key(agent_id)?;
+
+        // Keep receipts for every transaction we send, and what the agent earns
+        tracker::register(chain_config).await;
+        accounting::register(chain_id).await?;
+
+        // Get an rpc client
+        let factory_client = RpcClientService::new(chain_config.clone(), key.clone(), None).await;
+
+        // Bootstrap all the factory stuffz
+        let factory = Arc::new(Mutex::new(
+            Factory::new(chain_config.clone(), factory_client).await?,
+        ));
+
+        // Get that factory info before moving on
+        if factory.lock().await.load().await? {
+            info!("[{}] Factory Cache Reloaded", chain_id);
+        }
+
+        // Init that agent client lyfe
+        let agent_contract_addr = factory
+            .lock()
+            .await
+            .get_contract_addr("agents".to_string())
+            .await?;
+        let agent_client = RpcClientService::new(
+            chain_config.clone(),
+            key.clone(),
+            Some(agent_contract_addr.clone()),
+        )
+        .await;
+        // Treasury to top up the agent from, if configured
+        let treasury = match &chain_config.treasury {
+            Some(treasury_config) => {
+                let treasury_key = storage.get_agent_signing_key(&treasury_config.account)?;
+                Some(
+                    Treasury::new(chain_config.clone(), treasury_config.clone(), treasury_key)
+                        .await?,
+                )
+            }
+            None => None,
+        };
+        let agent = Arc::new(
+            Agent::new(
+                chain_config.clone(),
+                agent_contract_addr,
+                key.clone(),
+                agent_client,
+                treasury,
+            )
+            .await?,
+        );
+        // Get the account id, the authz granter when there is one
+        let account_addr = agent.account_id().clone();
+
+        // Init that manager client lyfe
+        let manager_contract_addr = factory
+            .lock()
+            .await
+            .get_contract_addr("manager".to_string())
+            .await?;
+        let manager_client = RpcClientService::new(
+            chain_config.clone(),
+            key.clone(),
+            Some(manager_contract_addr.clone()),
+        )
+        .await;
+        let manager = Arc::new(
+            Manager::new(
+                chain_config.clone(),
+                manager_contract_addr.clone(),
+                manager_client.clone(),
+            )
+            .await?,
+        );
+
+        // Init that tasks client lyfe
+        let tasks_contract_addr = factory
+            .lock()
+            .await
+            .get_contract_addr("tasks".to_string())
+            .await?;
+        let generic_querier_addr = factory
+            .lock()
+            .await
+            .get_contract_addr("mod_generic".to_string())
+            .await?;
+        let tasks_client = RpcClientService::new(
+            chain_config.clone(),
+            key.clone(),
+            Some(tasks_contract_addr.clone()),
+        )
+        .await;
+        let tasks = Arc::new(Mutex::new(
+            Tasks::new(
+                chain_config.clone(),
+                tasks_contract_addr.clone(),
+                tasks_client,
+                generic_querier_addr,
+            )
+            .await?,
+        ));
+
+        Ok(Self {
+            account_addr,
+            factory,
+            agent,
+            manager,
+            tasks,
+        })
+    }
+}
+
+///
+/// Run the agent on several chains inside this process, each with its own retry budget.
+///
+async fn run_chains(
+    opts: &Opts,
+    config: Config,
+    chain_ids: Vec<String>,
+    storage: &LocalAgentStorage,
+) -> Result<(), Report> {
+    // Check every chain before locking or starting any, so a typo doesn't leave chains running
+    let chain_configs = chain_ids
+        .iter()
+        .map(|chain_id| {
+            config.chains.get(chain_id).cloned().ok_or_else(|| {
+                CroncatError::Config(format!("Chain not found in configuration: {chain_id}"))
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Take every lock up front, so a chain already running elsewhere stops us before anything starts
+    let locks = chain_ids
+        .iter()
+        .map(|chain_id| StorageLock::acquire(&opts.agent, chain_id))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut handles = Vec::with_capacity(chain_ids.len());
+    for (chain_id, chain_config) in chain_ids.into_iter().zip(chain_configs) {
+        let agent_id = opts.agent.clone();
+        let storage = storage.clone();
+
+        info!("[{}] Starting agent pipeline", chain_id);
+        handles.push(tokio::spawn(async move {
+            let result = run_chain_retry(&chain_id, &chain_config, &storage, &agent_id).await;
+            (chain_id, result)
+        }));
+    }
+
+    // Wait for every chain, a failing chain does not stop the others.
+    let mut failed = vec![];
+    for result in join_all(handles).await {
+        match result {
+            Ok((_, Ok(_))) => {}
+            Ok((chain_id, Err(err))) => {
+                error!("[{}] Agent stopped: {}", chain_id, err);
+                failed.push(chain_id);
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    drop(locks);
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        // Every chain already used up its own retries, don't restart the healthy ones
+        Err(CroncatError::Stopped(format!("Agent stopped on chains: {}", failed.join(", "))).into())
+    }
+}
+
+///
+/// Run a single chain pipeline, retrying on recoverable failures.
+///
+async fn run_chain_retry(
+    chain_id: &String,
+    chain_config: &ChainConfig,
+    storage: &LocalAgentStorage,
+    agent_id: &String,
+) -> Result<(), Report> {
+    let mut idx: u64 = 0;
+    loop {
+        let result = async {
+            let clients = ChainClients::new(chain_id, chain_config, storage, agent_id).await?;
+
+            // Each chain gets its own shutdown channel
+            let (shutdown_tx, _shutdown_rx) = create_shutdown_channel();
+
+            system::run_retry(
+                chain_id,
+                &shutdown_tx,
+                chain_config,
+                &clients.factory,
+                &clients.agent,
+                &clients.manager,
+                &clients.tasks,
+            )
+            .await
+        }
+        .await;
+
+        match result {
+            Ok(_) => return Ok(()),
+            Err(err) if is_error_fallible(&err) || idx >= MAXIMUM_RETRY_INDEX => return Err(err),
+            Err(err) => {
+                error!("[{}] {}", chain_id, err);
+                error!("[{}] Retrying in 30 seconds...", chain_id);
+                idx = idx.saturating_add(1);
+
+                tokio::select! {
+                    _ = tokio::time::sleep(std::time::Duration::from_secs(30)) => {}
+                    _ = system::shutdown_signal() => return Ok(()),
+                }
+            }
+        }
+    }
+}
+
+///
+/// Print the latest transaction receipts for a chain, and what they cost.
+///
+fn print_txs(chain_id: &str, limit: usize, failed: bool) -> Result<(), Report> {
+    let records = LocalTxStorage::new(chain_id).recent(limit, failed)?;
+    if records.is_empty() {
+        println!("No transactions recorded for {chain_id}");
+        return Ok(());
+    }
+
+    let mut fees: Vec<(String, u128)> = vec![];
+    for record in &records {
+        let outcome = match &record.outcome {
+            TxOutcome::Included { height } => format!("included at {height}"),
+            TxOutcome::Failed { error } => format!("failed: {error}"),
+            TxOutcome::TimedOut { error } => format!("timed out: {error}"),
+        };
+        println!(
+            "{} {:?} {} gas {}/{} fee {}{} tasks {} {}",
+            record.timestamp,
+            record.kind,
+            record.tx_hash.as_deref().unwrap_or("-"),
+            record.gas_used,
+            record.gas_wanted,
+            record.fee,
+            record.denom,
+            record.task_hashes.len(),
+            outcome,
+        );
+
+        match fees.iter_mut().find(|(denom, _)| *denom == record.denom) {
+            Some((_, total)) => *total += record.fee,
+            None => fees.push((record.denom.clone(), record.fee)),
+        }
+    }
+
+    let failures = records.iter().filter(|record| record.is_failure()).count();
+    let spent: Vec<String> = fees
+        .iter()
+        .map(|(denom, total)| format!("{total}{denom}"))
+        .collect();
+    println!(
+        "\n{} transactions, {} failed, {} spent on fees",
+        records.len(),
+        failures,
+        spent.join(", ")
+    );
+
+    Ok(())
+}
+
+///
+/// Print the rewards, fees and net profit for the last days on a chain.
+///
+fn print_earnings(chain_id: &str, denom: &str, days: usize) -> Result<(), Report> {
+    let storage = LocalEarningsStorage::new(chain_id)?;
+    let earnings = storage.days();
+    if earnings.is_empty() {
+        println!("No earnings recorded for {chain_id}");
+        return Ok(());
+    }
+
+    println!(
+        "{:<10} {:>14} {:>14} {:>14} {:>10} {:>14} {:>14}",
+        "day", "rewards", "withdrawn", "proxy fees", "proxy txs", "total fees", "net"
+    );
+    let (mut rewards, mut fees) = (0u128, 0u128);
+    for (day, entry) in earnings.iter().rev().take(days).rev() {
+        println!(
+            "{:<10} {:>14} {:>14} {:>14} {:>10} {:>14} {:>14}",
+            day,
+            entry.rewards,
+            entry.withdrawn,
+            entry.proxy_fees,
+            entry.proxy_txs,
+            entry.fees,
+            entry.net(),
+        );
+        rewards += entry.rewards;
+        fees += entry.fees;
+    }
+
+    println!(
+        "\nEarned {rewards}{denom}, spent {fees}{denom} on fees, net {}{denom}",
+        rewards as i128 - fees as i128
+    );
+
+    Ok(())
+}
+
 async fn run_command(opts: Opts, mut storage: LocalAgentStorage) -> Result<(), Report> {
+    // Refresh the registry snapshot before loading the config, since the config needs it
+    if let opts::Command::Registry {
+        cmd: opts::RegistryCommand::Sync,
+    } = &opts.cmd
+    {
+        let (chain_ids, registry_path) = Config::sync_registry(opts.config.as_deref())?;
+        info!(
+            "Synced chain registry for {} to {}",
+            chain_ids.join(", "),
+            registry_path.display()
+        );
+        return Ok(());
+    }
+
+    // Encrypt the keystore, this doesn't need any chain
+    if let opts::Command::Keys {
+        cmd: opts::KeysCommand::Encrypt,
+    } = &opts.cmd
+    {
+        let passphrase = cli::get_passphrase(&opts, true)?;
+        let encrypted = storage.encrypt_all(&passphrase)?;
+        if encrypted.is_empty() {
+            info!("No plain text agents to encrypt");
+        } else {
+            info!("Encrypted agents: {}", encrypted.join(", "));
+        }
+        return Ok(());
+    }
+
     // Get the key for the agent signing account
-    let config = Config::from_pwd()?;
+    let config = Config::from_path(opts.config.as_deref())?;
+
+    // Addresses come from the stored public key, no need to unlock or connect
+    match &opts.cmd {
+        opts::Command::ListAccounts => {
+            println!("Account addresses for agent: {}\n", &opts.agent);
+            for (chain_id, chain_config) in &config.chains {
+                let account_addr = storage.get_agent_signing_account_addr(
+                    &opts.agent,
+                    chain_config.info.bech32_prefix.clone(),
+                )?;
+                println!("{chain_id}: {account_addr}");
+            }
+            return Ok(());
+        }
+        opts::Command::GetAgentKeys { name } => {
+            let prefixes: Vec<_> = config
+                .chains
+                .iter()
+                .map(|(chain_id, chain_config)| {
+                    (chain_id.clone(), chain_config.info.bech32_prefix.clone())
+                })
+                .collect();
+            return storage.display_account(name, &prefixes);
+        }
+        _ => {}
+    }
+
+    // Unlock the keystore, if the agent or any treasury account is encrypted,
+    // or a new agent is added to an encrypted keystore
+    let treasury_encrypted = config
+        .chains
+        .values()
+        .filter_map(|chain_config| chain_config.treasury.as_ref())
+        .any(|treasury| storage.is_encrypted(&treasury.account));
+    let generating_encrypted =
+        matches!(opts.cmd, opts::Command::GenerateMnemonic { .. }) && storage.has_encrypted();
+    if storage.is_encrypted(&opts.agent) || treasury_encrypted || generating_encrypted {
+        storage.unlock(cli::get_passphrase(&opts, false)?);
+    }
+
+    // Run several chains from this process if asked to
+    if let opts::Command::Go { all, chain_ids } = &opts.cmd {
+        if *all {
+            let chain_ids = config.chains.keys().cloned().collect();
+            return run_chains(&opts, config, chain_ids, &storage).await;
+        } else if !chain_ids.is_empty() {
+            let chain_ids = chain_ids.clone();
+            return run_chains(&opts, config, chain_ids, &storage).await;
+        }
+    }
+
     // Make sure we have a chain id to run on
     if opts.chain_id.is_none() {
         return Err(eyre!("chain-id is required for go command"));
@@ -99,10 +530,9 @@ async fn run_command(opts: Opts, mut storage: LocalAgentStorage) -> Result<(), R
     let chain_id = opts.chain_id.unwrap();
 
     // Get the chain config for the chain we're going to run on
-    let chain_config = config
-        .chains
-        .get(&chain_id)
-        .ok_or_else(|| eyre!("Chain not found in configuration: {}", chain_id))?;
+    let chain_config = config.chains.get(&chain_id).ok_or_else(|| {
+        CroncatError::Config(format!("Chain not found in configuration: {chain_id}"))
+    })?;
     let fee_token = chain_config.clone().info.fees.fee_tokens.pop();
     let chain_denom = if let Some(token) = fee_token {
         token.denom
@@ -110,87 +540,28 @@ async fn run_command(opts: Opts, mut storage: LocalAgentStorage) -> Result<(), R
         chain_config.clone().denom.unwrap_or_default()
     };
 
-    // Get the key and create a signer
-    let key = storage.get_agent_signing_key(&opts.agent)?;
-
-    // Get an rpc client
-    let factory_client = RpcClientService::new(chain_config.clone(), key.clone(), None).await;
-
-    // Bootstrap all the factory stuffz
-    let factory = Arc::new(Mutex::new(
-        Factory::new(chain_config.clone(), factory_client).await?,
-    ));
-
-    // Get that factory info before moving on
-    if factory.lock().await.load().await? {
-        info!("[{}] Factory Cache Reloaded", chain_id);
+    // Receipts are kept locally, no need to connect to the chain
+    if let opts::Command::Txs { limit, failed } = opts.cmd {
+        return print_txs(&chain_id, limit, failed);
+    }
+    if let opts::Command::Earnings { days } = opts.cmd {
+        return print_earnings(&chain_id, &chain_denom, days);
     }
 
-    // Init that agent client lyfe
-    let agent_contract_addr = factory
-        .lock()
-        .await
-        .get_contract_addr("agents".to_string())
-        .await?;
-    let agent_client = RpcClientService::new(
-        chain_config.clone(),
-        key.clone(),
-        Some(agent_contract_addr.clone()),
-    )
-    .await;
-    // Get the account id
-    let account_addr = agent_client.account_id();
-    let agent = Arc::new(
-        Agent::new(
-            chain_config.clone(),
-            agent_contract_addr,
-            key.clone(),
-            agent_client,
-        )
-        .await?,
-    );
+    // Keep other processes off this agent's stores while we write to them
+    let _lock = if opts.cmd.needs_lock() {
+        Some(StorageLock::acquire(&opts.agent, &chain_id)?)
+    } else {
+        None
+    };
 
-    // Init that manager client lyfe
-    let manager_contract_addr = factory
-        .lock()
-        .await
-        .get_contract_addr("manager".to_string())
-        .await?;
-    let manager_client = RpcClientService::new(
-        chain_config.clone(),
-        key.clone(),
-        Some(manager_contract_addr.clone()),
-    )
-    .await;
-    let manager =
-        Arc::new(Manager::new(manager_contract_addr.clone(), manager_client.clone()).await?);
-
-    // Init that tasks client lyfe
-    let tasks_contract_addr = factory
-        .lock()
-        .await
-        .get_contract_addr("tasks".to_string())
-        .await?;
-    let generic_querier_addr = factory
-        .lock()
-        .await
-        .get_contract_addr("mod_generic".to_string())
-        .await?;
-    let tasks_client = RpcClientService::new(
-        chain_config.clone(),
-        key.clone(),
-        Some(tasks_contract_addr.clone()),
-    )
-    .await;
-    let tasks = Arc::new(Mutex::new(
-        Tasks::new(
-            chain_config.clone(),
-            tasks_contract_addr.clone(),
-            tasks_client,
-            generic_querier_addr,
-        )
-        .await?,
-    ));
+    let ChainClients {
+        account_addr,
+        factory,
+        agent,
+        manager,
+        tasks,
+    } = ChainClients::new(&chain_id, chain_config, &storage, &opts.agent).await?;
 
     match opts.cmd {
         opts::Command::Register { payable_account_id } => {
@@ -226,29 +597,33 @@ async fn run_command(opts: Opts, mut storage: LocalAgentStorage) -> Result<(), R
                     //     info!("Make sure to keep your agent running, it will automatically become active when enough tasks exist.");
                     // }
                 }
-                Err(err) if err.to_string().contains("Agent already registered") => {
-                    let account_addr = account_addr.clone();
-                    Err(eyre!("Agent {} already registered", account_addr))?;
-                }
-                Err(err)
-                    if err
-                        .to_string()
-                        .contains("Agent registration currently operates on a whitelist") =>
-                {
-                    let account_addr = account_addr.clone();
-                    Err(eyre!(
-                        "Agent {} needs whitelist approval, please submit request to CronCat DAO",
-                        account_addr
-                    ))?;
-                }
-                Err(err)
-                    if err.to_string().contains("account")
-                        && err.to_string().contains("not found") =>
-                {
-                    let account_addr = account_addr.clone();
-                    Err(eyre!("\n\nAgent account not found on chain\nPlease add enough funds to execute a few transactions on your account then try to register again.\nYour account: {}", account_addr))?;
-                }
-                Err(err) => Err(eyre!("Failed to register agent: {}", err))?,
+                Err(err) => match CroncatError::classify(&err).cause().clone() {
+                    CroncatError::Contract {
+                        kind: ContractError::AgentAlreadyRegistered,
+                        ..
+                    } => {
+                        let account_addr = account_addr.clone();
+                        Err(CroncatError::contract(
+                            ContractError::AgentAlreadyRegistered,
+                            format!("Agent {account_addr} already registered"),
+                        ))?;
+                    }
+                    CroncatError::Contract {
+                        kind: ContractError::WhitelistRequired,
+                        ..
+                    } => {
+                        let account_addr = account_addr.clone();
+                        Err(CroncatError::contract(
+                            ContractError::WhitelistRequired,
+                            format!("Agent {account_addr} needs whitelist approval, please submit request to CronCat DAO"),
+                        ))?;
+                    }
+                    CroncatError::AccountNotFound(_) => {
+                        let account_addr = account_addr.clone();
+                        Err(CroncatError::AccountNotFound(format!("\n\nAgent account not found on chain\nPlease add enough funds to execute a few transactions on your account then try to register again.\nYour account: {account_addr}")))?;
+                    }
+                    error => Err(error.context("Failed to register agent"))?,
+                },
             }
         }
         opts::Command::Unregister => {
@@ -270,9 +645,10 @@ async fn run_command(opts: Opts, mut storage: LocalAgentStorage) -> Result<(), R
                         info!("\nResult: {}", log);
                     }
                 }
-                Err(err) if err.to_string().contains("Agent not registered") => {
-                    Err(eyre!(
-                        "Agent doesnt exist, must first register and do tasks."
+                Err(err) if is_agent_not_registered(&err) => {
+                    Err(CroncatError::contract(
+                        ContractError::AgentNotRegistered,
+                        "Agent doesnt exist, must first register and do tasks.",
                     ))?;
                 }
                 Err(err) => Err(eyre!("Failed to unregister agent: {}", err))?,
@@ -295,15 +671,15 @@ async fn run_command(opts: Opts, mut storage: LocalAgentStorage) -> Result<(), R
                         info!("\nResult: {}", log);
                     }
                 }
-                Err(err) if err.to_string().contains("Agent not registered") => {
-                    Err(eyre!(
-                        "Agent doesnt exist, must first register and do tasks."
+                Err(err) if is_agent_not_registered(&err) => {
+                    Err(CroncatError::contract(
+                        ContractError::AgentNotRegistered,
+                        "Agent doesnt exist, must first register and do tasks.",
                     ))?;
                 }
                 Err(err)
-                    if err
-                        .to_string()
-                        .contains("No rewards available for withdraw") =>
+                    if CroncatError::classify(&err).contract_error()
+                        == Some(&ContractError::NoRewards) =>
                 {
                     info!(
                         "No rewards available for withdraw, please wait until your agent is active and has processed tasks before next withdraw."
@@ -312,15 +688,6 @@ async fn run_command(opts: Opts, mut storage: LocalAgentStorage) -> Result<(), R
                 Err(err) => Err(eyre!("Failed to withdraw reward: {}", err))?,
             }
         }
-        opts::Command::ListAccounts => {
-            println!("Account addresses for agent: {}\n", &opts.agent);
-            // Get the chain config for the chain we're going to run on
-            for (chain_id, chain_config) in config.chains {
-                let account_addr = storage
-                    .get_agent_signing_account_addr(&opts.agent, chain_config.info.bech32_prefix)?;
-                println!("{chain_id}: {account_addr}");
-            }
-        }
         opts::Command::Status => {
             // Print info about the agent
             let account_addr = account_addr.clone();
@@ -353,8 +720,11 @@ async fn run_command(opts: Opts, mut storage: LocalAgentStorage) -> Result<(), R
                     // TODO: Parse and represent results better
                     info!("{}", result);
                 }
-                Err(err) if err.to_string().contains("Agent not registered") => {
-                    Err(eyre!("Agent not registered"))?;
+                Err(err) if is_agent_not_registered(&err) => {
+                    Err(CroncatError::contract(
+                        ContractError::AgentNotRegistered,
+                        "Agent not registered",
+                    ))?;
                 }
                 Err(err) => Err(eyre!("Failed to get contract tasks: {}", err))?,
             }
@@ -390,8 +760,11 @@ async fn run_command(opts: Opts, mut storage: LocalAgentStorage) -> Result<(), R
                         info!("\nResult: {}", log);
                     }
                 }
-                Err(err) if err.to_string().contains("Agent not registered") => {
-                    Err(eyre!("Agent not registered"))?;
+                Err(err) if is_agent_not_registered(&err) => {
+                    Err(CroncatError::contract(
+                        ContractError::AgentNotRegistered,
+                        "Agent not registered",
+                    ))?;
                 }
                 Err(err) => Err(eyre!(
                     "Failed to update agent configuration on chain: {}",
@@ -399,8 +772,7 @@ async fn run_command(opts: Opts, mut storage: LocalAgentStorage) -> Result<(), R
                 ))?,
             }
         }
-        opts::Command::GetAgentKeys { name } => storage.display_account(&name),
-        opts::Command::Go {} => {
+        opts::Command::Go { .. } => {
             // Create the global shutdown channel
             let (shutdown_tx, _shutdown_rx) = create_shutdown_channel();
 
@@ -426,14 +798,33 @@ async fn run_command(opts: Opts, mut storage: LocalAgentStorage) -> Result<(), R
                 Err(eyre!("Failed to clear local cache"))?
             }
         }
-        opts::Command::SetupService { output } => {
-            for (chain_id, _) in config.chains {
-                system::DaemonService::create(output.clone(), &chain_id, opts.no_frills)?;
+        opts::Command::SetupService { output, all } => {
+            // Point the unit at the config we loaded, wherever it's started from
+            let config_path = Config::resolve_path(opts.config.as_deref())?;
+            if all {
+                system::DaemonService::create(output, None, &config_path, opts.no_frills)?;
+            } else {
+                for (chain_id, _) in config.chains {
+                    system::DaemonService::create(
+                        output.clone(),
+                        Some(&chain_id),
+                        &config_path,
+                        opts.no_frills,
+                    )?;
+                }
             }
         }
+        // Handled before the config is loaded, or before connecting to the chain
+        opts::Command::Keys { .. }
+        | opts::Command::Registry { .. }
+        | opts::Command::ListAccounts
+        | opts::Command::GetAgentKeys { .. }
+        | opts::Command::Txs { .. }
+        | opts::Command::Earnings { .. } => {}
         opts::Command::SendFunds { to, amount, denom } => {
             let amount = amount.parse::<u128>()?;
-            let account_addr = account_addr.clone();
+            // Plain bank sends come from the signing key, even with an authz granter
+            let account_addr = agent.gas_payer();
             let d = denom.unwrap_or(chain_denom);
 
             // Send funds to the given address.
@@ -2,6 +2,8 @@
 //! `croncatd` CLI option builder.
 //!
 
+use std::path::PathBuf;
+
 use croncat::utils::DEFAULT_AGENT_ID;
 use enum_display::EnumDisplay;
 use structopt::StructOpt;
@@ -27,6 +29,14 @@ pub struct Opts {
     /// ID of the agent config to use
     #[structopt(long, global = true, default_value = DEFAULT_AGENT_ID, env = "CRONCAT_AGENT")]
     pub agent: String,
+
+    /// File holding the passphrase for encrypted agents, otherwise CRONCAT_PASSPHRASE or a prompt is used
+    #[structopt(long, global = true, env = "CRONCAT_PASSPHRASE_FILE")]
+    pub passphrase_file: Option<String>,
+
+    /// Config file, or directory of config fragments, defaults to ./config.yaml
+    #[structopt(long, global = true, env = "CRONCAT_CONFIG")]
+    pub config: Option<PathBuf>,
 }
 
 #[derive(Debug, StructOpt, Clone, EnumDisplay)]
@@ -56,6 +66,24 @@ pub enum Command {
     /// Withdraw the agent's funds to the payable account ID
     Withdraw,
 
+    /// List recent transactions with their gas, fees and outcome
+    Txs {
+        /// How many transactions to list
+        #[structopt(long, default_value = "20")]
+        limit: usize,
+
+        /// Only list failed and timed out transactions
+        #[structopt(long)]
+        failed: bool,
+    },
+
+    /// Show rewards, fees and net profit by day
+    Earnings {
+        /// How many days to show
+        #[structopt(long, default_value = "30")]
+        days: usize,
+    },
+
     /// Get contract's state
     // #[cfg(feature = "debug")]
     // GetState {
@@ -70,7 +98,15 @@ pub enum Command {
     },
 
     /// Starts the Croncat agent, allowing it to fulfill tasks
-    Go {},
+    Go {
+        /// Run every chain in config.yaml from this process
+        #[structopt(long)]
+        all: bool,
+
+        /// Comma separated list of chain IDs to run from this process
+        #[structopt(long, use_delimiter = true)]
+        chain_ids: Vec<String>,
+    },
 
     /// Generates a new keypair and agent account (good first step)
     GenerateMnemonic {
@@ -82,7 +118,7 @@ pub enum Command {
         mnemonic: Option<String>,
     },
 
-    /// [SENSITIVE!] Shows all details about agents on this machine
+    /// Shows the public key and addresses of an agent on this machine
     GetAgentKeys {
         #[structopt(long, default_value = "agent", env = "CRONCAT_AGENT")]
         name: String,
@@ -92,6 +128,22 @@ pub enum Command {
     SetupService {
         #[structopt(long)]
         output: Option<String>,
+
+        /// Create a single service running every chain, instead of one service per chain
+        #[structopt(long)]
+        all: bool,
+    },
+
+    /// Manage the agent keystore
+    Keys {
+        #[structopt(subcommand)]
+        cmd: KeysCommand,
+    },
+
+    /// Manage the local chain registry snapshot
+    Registry {
+        #[structopt(subcommand)]
+        cmd: RegistryCommand,
     },
 
     /// Send funds from the agent account to another account (`cargo run send juno123abc... 1 ujuno`)
@@ -107,3 +159,33 @@ pub enum Command {
         denom: Option<String>,
     },
 }
+
+impl Command {
+    /// Commands that write to the chain or its local stores, only one process may run these per agent and chain.
+    pub fn needs_lock(&self) -> bool {
+        matches!(
+            self,
+            Self::ClearCache
+                | Self::Register { .. }
+                | Self::Unregister
+                | Self::Update { .. }
+                | Self::Withdraw
+                | Self::Go { .. }
+                | Self::SendFunds { .. }
+        )
+    }
+}
+
+#[derive(Debug, StructOpt, Clone, EnumDisplay)]
+#[enum_display(case = "Kebab")]
+pub enum RegistryCommand {
+    /// Refresh the snapshot with the remote chain registry, for every chain in config.yaml
+    Sync,
+}
+
+#[derive(Debug, StructOpt, Clone, EnumDisplay)]
+#[enum_display(case = "Kebab")]
+pub enum KeysCommand {
+    /// Encrypt every plain text agent in the keystore with a passphrase
+    Encrypt,
+}